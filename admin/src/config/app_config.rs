@@ -11,6 +11,9 @@ pub struct ApplicationSettings {
     pub jwt_secret: String,
     pub mysql_url: String,
     pub static_dir: String,
+    //与节点config.toml中的admin.token一致,为空时拒绝节点的请求
+    #[serde(default)]
+    pub vt_node_token: String,
 }
 
 pub static mut SETTINGS: Option<BasicSettings<ApplicationSettings>> = None;
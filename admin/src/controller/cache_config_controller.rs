@@ -19,8 +19,13 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::error::SysError;
-use crate::model::cache_config_model::{CacheConfigCreateParam, CacheConfigListParam};
+use crate::entity::cache_purge_log;
+use crate::model::cache_config_model::{CacheConfigCreateParam, CacheConfigListParam, CachePreviewParam, CachePurgeParam};
 use crate::model::{cache_config_model, CurrentUser, DataWrapper, IdParam, PageResponse};
+use crate::model::vt_model::{PurgeTarget, VtNodeCommand};
+use crate::utils::vt_node_client;
+
+pub(crate) const ADMISSION_MODES: [&str; 2] = ["ALWAYS", "ADAPTIVE"];
 
 #[post("/cache_config/list")]
 pub(crate) async fn list(
@@ -71,12 +76,23 @@ pub(crate) async fn create(
     app_state: Data<AppState>,
     current_user: CurrentUser,
 ) -> Result<HttpResponse, SysError> {
+    if let Some(admission_mode) = &req.admission_mode {
+        if !ADMISSION_MODES.contains(&admission_mode.to_uppercase().as_str()) {
+            return Err(SysError::BIZ(format!("缓存准入模式必须是{}之一", ADMISSION_MODES.join("/"))));
+        }
+    }
+    if let Some(warm_params) = &req.warm_params {
+        if let Err(e) = cache_config_model::parse_warm_params(warm_params) {
+            return Err(SysError::BIZ(format!("预热参数必须是JSON数组的数组:{}", e)));
+        }
+    }
     let conn = &app_state.conn;
     req.to_owned()
         .to_active_model()
         .save(conn)
         .await
         .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
     let data_wrapper = DataWrapper::success("");
     Ok(HttpResponse::Ok().json(data_wrapper))
 }
@@ -92,6 +108,108 @@ pub(crate) async fn delete(
         .exec(conn)
         .await
         .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
     let data_wrapper = DataWrapper::success("");
     Ok(HttpResponse::Ok().json(data_wrapper))
 }
+
+//预热缓存,节点共用Redis,只交给一个在线节点执行
+#[post("/cache_config/warm")]
+pub(crate) async fn warm(
+    req: web::Json<IdParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let vt_node = app_state.vt_nodes_lock.lock().await.keys().next().cloned();
+    let vt_node = match vt_node {
+        None => return Err(SysError::BIZ(String::from("没有在线的vt-node"))),
+        Some(vt_node) => vt_node,
+    };
+    info!("user {} warms cache_config {} on {}", current_user.user_name, req.id, vt_node);
+    app_state.vt_node_commands_lock.lock().await
+        .entry(vt_node.clone())
+        .or_default()
+        .push(VtNodeCommand::WarmCache { cache_config_id: req.id });
+    Ok(HttpResponse::Ok().json(DataWrapper::success(vt_node)))
+}
+
+//清除缓存,下发给所有在线节点,并记录操作人
+#[post("/cache_config/purge")]
+pub(crate) async fn purge(
+    req: web::Json<CachePurgeParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let value = req.value.trim().to_string();
+    if value.is_empty() {
+        return Err(SysError::BIZ(String::from("清除的内容不能为空")));
+    }
+    let target = match req.purge_type.to_uppercase().as_str() {
+        "CACHE_CONFIG" => match value.parse::<i32>() {
+            Ok(id) => PurgeTarget::CacheConfig(id),
+            Err(_) => return Err(SysError::BIZ(String::from("cache_config的id必须是数字"))),
+        },
+        "TABLE" => PurgeTarget::Table(value.clone()),
+        "TAG" => PurgeTarget::Tag(value.clone()),
+        "SQL" => PurgeTarget::Sql(value.clone()),
+        _ => return Err(SysError::BIZ(String::from("清除方式必须是CACHE_CONFIG/TABLE/TAG/SQL之一"))),
+    };
+    let vt_nodes: Vec<String> = app_state.vt_nodes_lock.lock().await.keys().cloned().collect();
+    if vt_nodes.is_empty() {
+        return Err(SysError::BIZ(String::from("没有在线的vt-node")));
+    }
+    {
+        let mut vt_node_commands = app_state.vt_node_commands_lock.lock().await;
+        for vt_node in vt_nodes.iter() {
+            vt_node_commands
+                .entry(vt_node.clone())
+                .or_default()
+                .push(VtNodeCommand::PurgeCache { target: target.clone() });
+        }
+    }
+    info!("user {} purges cache {:?} on {:?}", current_user.user_name, target, vt_nodes);
+    cache_purge_log::ActiveModel {
+        purge_type: Set(req.purge_type.to_uppercase()),
+        purge_value: Set(value),
+        vt_nodes: Set(vt_nodes.join(",")),
+        created_by: Set(current_user.user_id),
+        created_by_name: Set(current_user.user_name.clone()),
+        ..Default::default()
+    }
+        .insert(&app_state.conn)
+        .await
+        .map_err(Error::new)?;
+    Ok(HttpResponse::Ok().json(DataWrapper::success(vt_nodes)))
+}
+
+//预览缓存的列、行数、大小和剩余时间,节点共用Redis,由一个在线节点读取
+#[post("/cache_config/preview")]
+pub(crate) async fn preview(
+    req: web::Json<CachePreviewParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let key = req.key.trim();
+    let pattern = req.pattern.trim();
+    let mut query = match (key.is_empty(), pattern.is_empty()) {
+        (false, true) => vec![("key", key.to_string())],
+        (true, false) => vec![("pattern", pattern.to_string())],
+        _ => return Err(SysError::BIZ(String::from("key和pattern必须且只能填写一个"))),
+    };
+    if let Some(limit) = req.limit {
+        query.push(("limit", limit.to_string()));
+    }
+    if let Some(max_rows) = req.max_rows {
+        query.push(("max_rows", max_rows.to_string()));
+    }
+    let vt_node = app_state.vt_nodes_lock.lock().await.iter()
+        .find(|(_, vt_node_state)| vt_node_state.metric_port != 0)
+        .map(|(vt_node, vt_node_state)| (vt_node.clone(), vt_node_state.clone()));
+    let (vt_node, vt_node_state) = match vt_node {
+        None => return Err(SysError::BIZ(String::from("没有支持预览缓存的在线vt-node"))),
+        Some(vt_node) => vt_node,
+    };
+    info!("user {} previews cache {:?} on {}", current_user.user_name, query, vt_node);
+    let previews = vt_node_client::get_json(&vt_node, &vt_node_state, "/cache/preview", &query).await?;
+    Ok(HttpResponse::Ok().json(DataWrapper::success(previews)))
+}
@@ -0,0 +1,102 @@
+#![allow(unused_variables)]
+use actix_web::web::Data;
+use actix_web::{post, web, HttpResponse};
+use anyhow::Error;
+use log::info;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, EntityTrait, PaginatorTrait, QueryFilter};
+
+use crate::entity::prelude::FirewallRule;
+use crate::entity::firewall_rule;
+use crate::error::SysError;
+use crate::model::firewall_rule_model::{FirewallRuleCreateParam, FirewallRuleListParam};
+use crate::model::{CurrentUser, DataWrapper, IdParam, PageResponse};
+use crate::AppState;
+
+pub(crate) const RULE_TYPES: [&str; 4] = ["DENY_NO_WHERE", "DENY_DDL", "DENY_PATTERN", "ALLOW_PATTERN"];
+
+#[post("/firewall_rule/list")]
+pub(crate) async fn list(
+    req: web::Json<FirewallRuleListParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    let mut query = FirewallRule::find();
+    if req.rule_name.is_some() && !req.rule_name.as_ref().unwrap().is_empty() {
+        query = query.filter(
+            Expr::col(firewall_rule::Column::RuleName).eq(req.rule_name.clone().unwrap()),
+        );
+    }
+    if req.rule_type.is_some() && !req.rule_type.as_ref().unwrap().is_empty() {
+        query = query.filter(
+            Expr::col(firewall_rule::Column::RuleType).eq(req.rule_type.clone().unwrap().to_uppercase()),
+        );
+    }
+    if req.enabled.is_some() {
+        query = query.filter(Expr::col(firewall_rule::Column::Enabled).eq(req.enabled.unwrap()));
+    }
+
+    let page_param = req.clone().page_param;
+    let paginator = query.paginate(conn, page_param.clone().get_limit());
+    let items_and_page_number = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(anyhow::Error::new)?;
+    let list = paginator
+        .fetch_page(page_param.clone().get_page_no())
+        .await
+        .map_err(anyhow::Error::new)?;
+    info!(
+        "page_no:{:?},page_size:{:?},list:{:?}",
+        page_param.page_no, page_param.page_size, list
+    );
+
+    let data_wrapper = DataWrapper::success(PageResponse {
+        list,
+        total: items_and_page_number.number_of_items as i64,
+    });
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+#[post("/firewall_rule/createOrUpdate")]
+pub(crate) async fn create(
+    req: web::Json<FirewallRuleCreateParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    if let Some(rule_type) = &req.rule_type {
+        if !RULE_TYPES.contains(&rule_type.to_uppercase().as_str()) {
+            return Err(SysError::BIZ(format!("规则类型必须是{}之一", RULE_TYPES.join("/"))));
+        }
+        if rule_type.to_uppercase().ends_with("_PATTERN")
+            && req.sql_template.as_ref().map(|v| v.trim().is_empty()).unwrap_or(true) {
+            return Err(SysError::BIZ(String::from("PATTERN类型的规则必须填写SQL模板")));
+        }
+    }
+    let conn = &app_state.conn;
+    req.to_owned()
+        .to_active_model()
+        .save(conn)
+        .await
+        .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    let data_wrapper = DataWrapper::success("");
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+#[post("/firewall_rule/delete")]
+pub(crate) async fn delete(
+    req: web::Json<IdParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    firewall_rule::Entity::delete_by_id(req.id)
+        .exec(conn)
+        .await
+        .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    let data_wrapper = DataWrapper::success("");
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
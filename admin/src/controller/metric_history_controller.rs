@@ -14,7 +14,7 @@ use crate::AppState;
 use crate::entity::prelude::MetricHistory;
 use crate::error::SysError;
 use crate::model::{DataWrapper, metric, PageResponse};
-use crate::model::metric::{MetricQueryParam, MetricResult};
+use crate::model::metric::{CacheSuggestion, CacheSuggestionParam, MetricQueryParam, MetricResult};
 
 #[post("/metric/list_sql")]
 pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppState>) -> Result<HttpResponse, SysError> {
@@ -22,12 +22,13 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
     let mut query_params = vec![];
     let sql = match &metric_param.sql {
         None => {
-            r#"select sql_str from metric_history group by sql_str"#
+            r#"select sql_digest,max(sql_str) as sql_str from metric_history group by sql_digest"#
         }
         Some(sql_condition) => {
-            let condition = Value::String(Some(Box::new(sql_condition.to_owned())));
-            query_params.push(condition);
-            r#"select sql_str from metric_history where sql_str like concat('%',?,'%') group by sql_str"#
+            //按SQL片段或指纹查询
+            query_params.push(Value::String(Some(Box::new(sql_condition.to_owned()))));
+            query_params.push(Value::String(Some(Box::new(sql_condition.to_owned()))));
+            r#"select sql_digest,max(sql_str) as sql_str from metric_history where sql_str like concat('%',?,'%') or sql_digest = ? group by sql_digest"#
         }
     };
     let page_param = metric_param.page_param.clone();
@@ -46,21 +47,18 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
         .map(|x| x.as_object())
         .filter(|x| x.is_some())
         .map(|x| x.unwrap())
-        .map(|x| x.get("sql_str"))
-        .filter(|x| x.is_some())
-        .map(|x| x.unwrap())
-        .map(|x| x.as_str())
-        .filter(|x| x.is_some())
-        .map(|x| x.unwrap())
         .map(|x| {
+            let get_str = |key: &str| x.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
             MetricResult {
-                sql_str: String::from(x),
+                sql_digest: get_str("sql_digest"),
+                sql_str: get_str("sql_str"),
                 dates: vec![],
                 avg_durations: vec![],
                 min_durations: vec![],
                 max_durations: vec![],
                 exec_counts: vec![],
                 cache_hit_counts: vec![],
+                throttle_counts: vec![],
             }
         })
         .collect();
@@ -75,7 +73,8 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
       ifnull(max_duration, 0) AS max_duration,
       ifnull(min_duration, 0) AS min_duration,
       ifnull(exec_count, 0) AS exec_count,
-      ifnull(cache_hit_count, 0) AS cache_hit_count
+      ifnull(cache_hit_count, 0) AS cache_hit_count,
+      ifnull(throttle_count, 0) AS throttle_count
     FROM
       (
         SELECT
@@ -99,6 +98,7 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
           min(min_duration) as min_duration,
           sum(exec_count) as exec_count,
           sum(cache_hit_count) as cache_hit_count,
+          sum(throttle_count) as throttle_count,
           created_at
         from
           (
@@ -110,19 +110,21 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
               min_duration,
               exec_count,
               cache_hit_count,
+              throttle_count,
               DATE_FORMAT(created_at, "%Y-%m-%d %H:%i:00") as created_at
             from
               metric_history
             where
-              sql_str = "{}"
+              sql_digest = ?
               and created_at > DATE_SUB("{}", INTERVAL 30 MINUTE)
           ) as t
         group by
           created_at
       ) t2 ON t1.date = t2.created_at
 			order by date asc
-        "#, now_str,now_str,now_str,x.sql_str,now_str);
-        let query_result_vec = conn.query_all(Statement::from_sql_and_values(DatabaseBackend::MySql, sub_sql.as_str(), vec![]))
+        "#, now_str,now_str,now_str,now_str);
+        let sub_params = vec![Value::String(Some(Box::new(x.sql_digest.clone())))];
+        let query_result_vec = conn.query_all(Statement::from_sql_and_values(DatabaseBackend::MySql, sub_sql.as_str(), sub_params))
             .await
             .map_err(anyhow::Error::new)
             ?;
@@ -162,6 +164,11 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
             .filter(|x| x.is_ok())
             .map(|x| x.unwrap())
             .collect();
+        let throttle_counts: Vec<Decimal> = query_result_vec.iter()
+            .map(|x| x.try_get("", "throttle_count"))
+            .filter(|x| x.is_ok())
+            .map(|x| x.unwrap())
+            .collect();
         let dates: Vec<String> = query_result_vec.iter()
             .map(|x| x.try_get("", "date"))
             .filter(|x| x.is_ok())
@@ -172,6 +179,7 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
         x.max_durations = max_durations;
         x.exec_counts = exec_counts;
         x.cache_hit_counts = cache_hit_counts;
+        x.throttle_counts = throttle_counts;
         x.dates = dates;
         final_metric_result_vec.push(x);
     }
@@ -180,4 +188,37 @@ pub async fn list_sql(metric_param: Json<MetricQueryParam>, app_state: Data<AppS
         total: total as i64,
     });
     Ok(HttpResponse::Ok().json(data_wrapper))
-}
\ No newline at end of file
+}
+
+#[post("/metric/suggest")]
+pub async fn suggest(param: Json<CacheSuggestionParam>, app_state: Data<AppState>) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    let minutes = param.minutes.filter(|v| *v > 0).unwrap_or(60);
+    let limit = param.limit.filter(|v| *v > 0).unwrap_or(20);
+    //已有缓存配置的指纹不再推荐
+    let sql = r#"
+    select
+      m.sql_digest,
+      max(m.sql_str) as sql_str,
+      sum(m.exec_count) as exec_count,
+      sum(m.cache_hit_count) as cache_hit_count,
+      cast(avg(m.avg_duration) as decimal(20, 2)) as avg_duration
+    from
+      metric_history m
+    where
+      m.created_at > DATE_SUB(now(), INTERVAL ? MINUTE)
+      and m.sql_digest <> ''
+      and m.sql_str like 'SELECT%'
+      and not exists (select 1 from cache_config c where c.sql_template = m.sql_str)
+    group by
+      m.sql_digest
+    order by
+      exec_count desc
+    limit ?
+    "#;
+    let list = CacheSuggestion::find_by_statement(Statement::from_sql_and_values(DatabaseBackend::MySql, sql, vec![minutes.into(), limit.into()]))
+        .all(conn)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(HttpResponse::Ok().json(DataWrapper::success(list)))
+}
@@ -4,3 +4,7 @@ pub mod mock_controller;
 pub mod user_controller;
 pub mod vt_node_controller;
 pub mod metric_history_controller;
+pub mod firewall_rule_controller;
+pub mod query_rewrite_controller;
+pub mod rate_limit_config_controller;
+pub mod rule_file_controller;
@@ -0,0 +1,93 @@
+#![allow(unused_variables)]
+use actix_web::web::Data;
+use actix_web::{post, web, HttpResponse};
+use anyhow::Error;
+use log::info;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, EntityTrait, PaginatorTrait, QueryFilter};
+
+use crate::entity::prelude::QueryRewrite;
+use crate::entity::query_rewrite;
+use crate::error::SysError;
+use crate::model::query_rewrite_model::{QueryRewriteCreateParam, QueryRewriteListParam};
+use crate::model::{CurrentUser, DataWrapper, IdParam, PageResponse};
+use crate::AppState;
+
+#[post("/query_rewrite/list")]
+pub(crate) async fn list(
+    req: web::Json<QueryRewriteListParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    let mut query = QueryRewrite::find();
+    if req.rule_name.is_some() && !req.rule_name.as_ref().unwrap().is_empty() {
+        query = query.filter(
+            Expr::col(query_rewrite::Column::RuleName).eq(req.rule_name.clone().unwrap()),
+        );
+    }
+    if req.enabled.is_some() {
+        query = query.filter(Expr::col(query_rewrite::Column::Enabled).eq(req.enabled.unwrap()));
+    }
+
+    let page_param = req.clone().page_param;
+    let paginator = query.paginate(conn, page_param.clone().get_limit());
+    let items_and_page_number = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(anyhow::Error::new)?;
+    let list = paginator
+        .fetch_page(page_param.clone().get_page_no())
+        .await
+        .map_err(anyhow::Error::new)?;
+    info!(
+        "page_no:{:?},page_size:{:?},list:{:?}",
+        page_param.page_no, page_param.page_size, list
+    );
+
+    let data_wrapper = DataWrapper::success(PageResponse {
+        list,
+        total: items_and_page_number.number_of_items as i64,
+    });
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+#[post("/query_rewrite/createOrUpdate")]
+pub(crate) async fn create(
+    req: web::Json<QueryRewriteCreateParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    if req.id.is_none() && (is_blank(&req.sql_template) || is_blank(&req.replacement)) {
+        return Err(SysError::BIZ(String::from("SQL模板和改写后的SQL不能为空")));
+    }
+    let conn = &app_state.conn;
+    req.to_owned()
+        .to_active_model()
+        .save(conn)
+        .await
+        .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    let data_wrapper = DataWrapper::success("");
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+#[post("/query_rewrite/delete")]
+pub(crate) async fn delete(
+    req: web::Json<IdParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    query_rewrite::Entity::delete_by_id(req.id)
+        .exec(conn)
+        .await
+        .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    let data_wrapper = DataWrapper::success("");
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+fn is_blank(value: &Option<String>) -> bool {
+    value.as_ref().map(|v| v.trim().is_empty()).unwrap_or(true)
+}
@@ -0,0 +1,98 @@
+#![allow(unused_variables)]
+use actix_web::web::Data;
+use actix_web::{post, web, HttpResponse};
+use anyhow::Error;
+use log::info;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, EntityTrait, PaginatorTrait, QueryFilter};
+
+use crate::entity::prelude::RateLimitConfig;
+use crate::entity::rate_limit_config;
+use crate::error::SysError;
+use crate::model::rate_limit_config_model::{RateLimitConfigCreateParam, RateLimitConfigListParam};
+use crate::model::{CurrentUser, DataWrapper, IdParam, PageResponse};
+use crate::AppState;
+
+pub(crate) const LIMIT_TYPES: [&str; 3] = ["USER", "IP", "PATTERN"];
+
+#[post("/rate_limit_config/list")]
+pub(crate) async fn list(
+    req: web::Json<RateLimitConfigListParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    let mut query = RateLimitConfig::find();
+    if req.limit_name.is_some() && !req.limit_name.as_ref().unwrap().is_empty() {
+        query = query.filter(
+            Expr::col(rate_limit_config::Column::LimitName).eq(req.limit_name.clone().unwrap()),
+        );
+    }
+    if req.limit_type.is_some() && !req.limit_type.as_ref().unwrap().is_empty() {
+        query = query.filter(
+            Expr::col(rate_limit_config::Column::LimitType).eq(req.limit_type.clone().unwrap().to_uppercase()),
+        );
+    }
+    if req.enabled.is_some() {
+        query = query.filter(Expr::col(rate_limit_config::Column::Enabled).eq(req.enabled.unwrap()));
+    }
+
+    let page_param = req.clone().page_param;
+    let paginator = query.paginate(conn, page_param.clone().get_limit());
+    let items_and_page_number = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(anyhow::Error::new)?;
+    let list = paginator
+        .fetch_page(page_param.clone().get_page_no())
+        .await
+        .map_err(anyhow::Error::new)?;
+    info!(
+        "page_no:{:?},page_size:{:?},list:{:?}",
+        page_param.page_no, page_param.page_size, list
+    );
+
+    let data_wrapper = DataWrapper::success(PageResponse {
+        list,
+        total: items_and_page_number.number_of_items as i64,
+    });
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+#[post("/rate_limit_config/createOrUpdate")]
+pub(crate) async fn create(
+    req: web::Json<RateLimitConfigCreateParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    if let Some(limit_type) = &req.limit_type {
+        if !LIMIT_TYPES.contains(&limit_type.to_uppercase().as_str()) {
+            return Err(SysError::BIZ(format!("限流类型必须是{}之一", LIMIT_TYPES.join("/"))));
+        }
+    }
+    let conn = &app_state.conn;
+    req.to_owned()
+        .to_active_model()
+        .save(conn)
+        .await
+        .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    let data_wrapper = DataWrapper::success("");
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
+
+#[post("/rate_limit_config/delete")]
+pub(crate) async fn delete(
+    req: web::Json<IdParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    rate_limit_config::Entity::delete_by_id(req.id)
+        .exec(conn)
+        .await
+        .map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    let data_wrapper = DataWrapper::success("");
+    Ok(HttpResponse::Ok().json(data_wrapper))
+}
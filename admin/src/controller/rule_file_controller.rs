@@ -0,0 +1,234 @@
+#![allow(unused_variables)]
+use std::collections::HashSet;
+
+use actix_web::web::Data;
+use actix_web::{post, web, HttpResponse};
+use anyhow::Error;
+use log::info;
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, TransactionTrait};
+
+use crate::controller::cache_config_controller::ADMISSION_MODES;
+use crate::controller::firewall_rule_controller::RULE_TYPES;
+use crate::controller::rate_limit_config_controller::LIMIT_TYPES;
+use crate::entity::prelude::{CacheConfig, FirewallRule, QueryRewrite, RateLimitConfig};
+use crate::entity::{cache_config, firewall_rule, query_rewrite, rate_limit_config};
+use crate::error::SysError;
+use crate::model::rule_file_model::{RuleExportParam, RuleImportParam, RulesFile};
+use crate::model::{cache_config_model, rule_file_model, CurrentUser, DataWrapper};
+use crate::AppState;
+
+//导出启用的规则,格式与vt-node的rules_file相同
+#[post("/rule_file/export")]
+pub(crate) async fn export(
+    req: web::Json<RuleExportParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let conn = &app_state.conn;
+    let rules_file = RulesFile {
+        cache_configs: CacheConfig::find()
+            .filter(cache_config::Column::Enabled.eq(1))
+            .order_by_asc(cache_config::Column::Id)
+            .all(conn).await.map_err(Error::new)?
+            .into_iter().map(Into::into).collect(),
+        rate_limit_configs: RateLimitConfig::find()
+            .filter(rate_limit_config::Column::Enabled.eq(1))
+            .order_by_asc(rate_limit_config::Column::Id)
+            .all(conn).await.map_err(Error::new)?
+            .into_iter().map(Into::into).collect(),
+        firewall_rules: FirewallRule::find()
+            .filter(firewall_rule::Column::Enabled.eq(1))
+            .order_by_asc(firewall_rule::Column::Id)
+            .all(conn).await.map_err(Error::new)?
+            .into_iter().map(Into::into).collect(),
+        query_rewrites: QueryRewrite::find()
+            .filter(query_rewrite::Column::Enabled.eq(1))
+            .order_by_asc(query_rewrite::Column::Id)
+            .all(conn).await.map_err(Error::new)?
+            .into_iter().map(Into::into).collect(),
+    };
+    let content = rule_file_model::to_rules_content(req.format.as_deref(), &rules_file)
+        .map_err(SysError::BIZ)?;
+    Ok(HttpResponse::Ok().json(DataWrapper::success(content)))
+}
+
+//导入规则文件,按id新增或覆盖,导入的规则都会启用
+#[post("/rule_file/import")]
+pub(crate) async fn import(
+    req: web::Json<RuleImportParam>,
+    app_state: Data<AppState>,
+    current_user: CurrentUser,
+) -> Result<HttpResponse, SysError> {
+    let rules_file = rule_file_model::parse_rules_content(req.format.as_deref(), &req.content)
+        .map_err(|e| SysError::BIZ(format!("规则文件格式错误:{}", e)))?;
+    validate(&rules_file)?;
+
+    let txn = app_state.conn.begin().await.map_err(Error::new)?;
+    let user_id = current_user.user_id;
+    for x in rules_file.cache_configs.iter().cloned() {
+        let exists = CacheConfig::find_by_id(x.id).one(&txn).await.map_err(Error::new)?.is_some();
+        let mut active_model = cache_config::ActiveModel {
+            id: Set(x.id),
+            cache_name: Set(x.cache_name),
+            sql_template: Set(x.sql_template),
+            duration: Set(x.duration),
+            timeout_ms: Set(x.timeout_ms),
+            admission_mode: Set(x.admission_mode.to_uppercase()),
+            max_result_bytes: Set(x.max_result_bytes),
+            warm_params: Set(x.warm_params),
+            remark: Set(x.remark),
+            enabled: Set(1),
+            updated_by: Set(user_id),
+            ..Default::default()
+        };
+        if exists {
+            active_model.update(&txn).await.map_err(Error::new)?;
+        } else {
+            active_model.created_by = Set(user_id);
+            CacheConfig::insert(active_model).exec(&txn).await.map_err(Error::new)?;
+        }
+    }
+    for x in rules_file.rate_limit_configs.iter().cloned() {
+        let exists = RateLimitConfig::find_by_id(x.id).one(&txn).await.map_err(Error::new)?.is_some();
+        let mut active_model = rate_limit_config::ActiveModel {
+            id: Set(x.id),
+            limit_name: Set(x.limit_name),
+            limit_type: Set(x.limit_type.to_uppercase()),
+            limit_key: Set(x.limit_key),
+            max_qps: Set(x.max_qps),
+            max_concurrency: Set(x.max_concurrency),
+            queue_timeout_ms: Set(x.queue_timeout_ms),
+            remark: Set(x.remark),
+            enabled: Set(1),
+            updated_by: Set(user_id),
+            ..Default::default()
+        };
+        if exists {
+            active_model.update(&txn).await.map_err(Error::new)?;
+        } else {
+            active_model.created_by = Set(user_id);
+            RateLimitConfig::insert(active_model).exec(&txn).await.map_err(Error::new)?;
+        }
+    }
+    for x in rules_file.firewall_rules.iter().cloned() {
+        let exists = FirewallRule::find_by_id(x.id).one(&txn).await.map_err(Error::new)?.is_some();
+        let mut active_model = firewall_rule::ActiveModel {
+            id: Set(x.id),
+            rule_name: Set(x.rule_name),
+            rule_type: Set(x.rule_type.to_uppercase()),
+            sql_template: Set(x.sql_template),
+            users: Set(x.users),
+            dry_run: Set(x.dry_run as i32),
+            remark: Set(x.remark),
+            enabled: Set(1),
+            updated_by: Set(user_id),
+            ..Default::default()
+        };
+        if exists {
+            active_model.update(&txn).await.map_err(Error::new)?;
+        } else {
+            active_model.created_by = Set(user_id);
+            FirewallRule::insert(active_model).exec(&txn).await.map_err(Error::new)?;
+        }
+    }
+    for x in rules_file.query_rewrites.iter().cloned() {
+        let exists = QueryRewrite::find_by_id(x.id).one(&txn).await.map_err(Error::new)?.is_some();
+        let mut active_model = query_rewrite::ActiveModel {
+            id: Set(x.id),
+            rule_name: Set(x.rule_name),
+            sql_template: Set(x.sql_template),
+            replacement: Set(x.replacement),
+            remark: Set(x.remark),
+            enabled: Set(1),
+            updated_by: Set(user_id),
+            ..Default::default()
+        };
+        if exists {
+            active_model.update(&txn).await.map_err(Error::new)?;
+        } else {
+            active_model.created_by = Set(user_id);
+            QueryRewrite::insert(active_model).exec(&txn).await.map_err(Error::new)?;
+        }
+    }
+
+    if req.disable_missing.unwrap_or(false) {
+        CacheConfig::update_many()
+            .col_expr(cache_config::Column::Enabled, Expr::value(0))
+            .filter(cache_config::Column::Id.is_not_in(rules_file.cache_configs.iter().map(|x| x.id)))
+            .exec(&txn).await.map_err(Error::new)?;
+        RateLimitConfig::update_many()
+            .col_expr(rate_limit_config::Column::Enabled, Expr::value(0))
+            .filter(rate_limit_config::Column::Id.is_not_in(rules_file.rate_limit_configs.iter().map(|x| x.id)))
+            .exec(&txn).await.map_err(Error::new)?;
+        FirewallRule::update_many()
+            .col_expr(firewall_rule::Column::Enabled, Expr::value(0))
+            .filter(firewall_rule::Column::Id.is_not_in(rules_file.firewall_rules.iter().map(|x| x.id)))
+            .exec(&txn).await.map_err(Error::new)?;
+        QueryRewrite::update_many()
+            .col_expr(query_rewrite::Column::Enabled, Expr::value(0))
+            .filter(query_rewrite::Column::Id.is_not_in(rules_file.query_rewrites.iter().map(|x| x.id)))
+            .exec(&txn).await.map_err(Error::new)?;
+    }
+    txn.commit().await.map_err(Error::new)?;
+    app_state.config_changed.notify_waiters();
+    info!("user {} imported rules: {} cache configs, {} rate limit configs, {} firewall rules, {} query rewrites",
+        current_user.user_name, rules_file.cache_configs.len(), rules_file.rate_limit_configs.len(),
+        rules_file.firewall_rules.len(), rules_file.query_rewrites.len());
+    Ok(HttpResponse::Ok().json(DataWrapper::success("")))
+}
+
+//与各规则的createOrUpdate相同的校验,任何一条不通过都不导入
+fn validate(rules_file: &RulesFile) -> Result<(), SysError> {
+    let mut ids = HashSet::new();
+    for x in rules_file.cache_configs.iter() {
+        if !ids.insert(x.id) {
+            return Err(SysError::BIZ(format!("缓存配置id {}重复", x.id)));
+        }
+        if x.sql_template.trim().is_empty() || x.duration <= 0 {
+            return Err(SysError::BIZ(format!("缓存配置{}的SQL模板不能为空,缓存时间必须大于0", x.id)));
+        }
+        if !ADMISSION_MODES.contains(&x.admission_mode.to_uppercase().as_str()) {
+            return Err(SysError::BIZ(format!("缓存配置{}的准入模式必须是{}之一", x.id, ADMISSION_MODES.join("/"))));
+        }
+        if let Err(e) = cache_config_model::parse_warm_params(&x.warm_params) {
+            return Err(SysError::BIZ(format!("缓存配置{}的预热参数必须是JSON数组的数组:{}", x.id, e)));
+        }
+    }
+    let mut ids = HashSet::new();
+    for x in rules_file.rate_limit_configs.iter() {
+        if !ids.insert(x.id) {
+            return Err(SysError::BIZ(format!("限流配置id {}重复", x.id)));
+        }
+        if !LIMIT_TYPES.contains(&x.limit_type.to_uppercase().as_str()) {
+            return Err(SysError::BIZ(format!("限流配置{}的限流类型必须是{}之一", x.id, LIMIT_TYPES.join("/"))));
+        }
+        if x.limit_key.trim().is_empty() {
+            return Err(SysError::BIZ(format!("限流配置{}的限流对象不能为空", x.id)));
+        }
+    }
+    let mut ids = HashSet::new();
+    for x in rules_file.firewall_rules.iter() {
+        if !ids.insert(x.id) {
+            return Err(SysError::BIZ(format!("防火墙规则id {}重复", x.id)));
+        }
+        let rule_type = x.rule_type.to_uppercase();
+        if !RULE_TYPES.contains(&rule_type.as_str()) {
+            return Err(SysError::BIZ(format!("防火墙规则{}的类型必须是{}之一", x.id, RULE_TYPES.join("/"))));
+        }
+        if rule_type.ends_with("_PATTERN") && x.sql_template.trim().is_empty() {
+            return Err(SysError::BIZ(format!("防火墙规则{}是PATTERN类型,必须填写SQL模板", x.id)));
+        }
+    }
+    let mut ids = HashSet::new();
+    for x in rules_file.query_rewrites.iter() {
+        if !ids.insert(x.id) {
+            return Err(SysError::BIZ(format!("改写规则id {}重复", x.id)));
+        }
+        if x.sql_template.trim().is_empty() || x.replacement.trim().is_empty() {
+            return Err(SysError::BIZ(format!("改写规则{}的SQL模板和改写后的SQL不能为空", x.id)));
+        }
+    }
+    Ok(())
+}
@@ -12,21 +12,25 @@ use actix_web::web::Data;
 use anyhow::Error;
 use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone, Utc};
 use log::info;
-use sea_orm::ActiveModelTrait;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, Statement};
 use sea_orm::ActiveValue::Set;
 
 use crate::AppState;
 use crate::config::app_config::ApplicationSettings;
-use crate::entity::metric_history;
+use crate::entity::{cache_config, firewall_rule, metric_history, query_rewrite, rate_limit_config};
+use crate::entity::prelude::{CacheConfig, FirewallRule, QueryRewrite, RateLimitConfig};
 use crate::entity::metric_history::ActiveModel;
 use crate::entity::prelude::MetricHistory;
 use crate::error::SysError;
-use crate::model::{DataWrapper, vt_model};
+use crate::model::{cache_config_model, CurrentUser, DataWrapper, vt_model, VtNodeAuth};
+use crate::model::vt_model::{CacheConfigRow, FirewallRuleRow, KillSessionParam, MetaSnapshot, MetaWatchParam, QueryRewriteRow, RateLimitConfigRow, VtNodeCommand, VtNodeSessions, VtNodeState, WarmQuery, WarmTask, WarmTaskParam};
+use crate::utils::vt_node_client;
 
 #[post("/vt_node/register")]
 pub async fn register(req_param: web::Json<vt_model::VtNodeRegisterParam>,
                       _req: HttpRequest,
-                      app_state_data: Data<AppState>, ) -> Result<HttpResponse, SysError> {
+                      app_state_data: Data<AppState>,
+                      _vt_node: VtNodeAuth, ) -> Result<HttpResponse, SysError> {
     let remote_addr_option = _req.peer_addr();
     if remote_addr_option.is_none() {
         return Ok(HttpResponse::Ok().json(DataWrapper::result(-1, String::from("无法获取客户端IP"), Some(""))));
@@ -40,13 +44,14 @@ pub async fn register(req_param: web::Json<vt_model::VtNodeRegisterParam>,
     let vt_nodes_lock = Arc::clone(&app_state_data.vt_nodes_lock);
     let mut vt_nodes = vt_nodes_lock.lock().await;
 
-    vt_nodes.insert(key.clone(), expire_at);
+    vt_nodes.insert(key.clone(), VtNodeState { expire_at, metric_port: req_param.metric_port });
 
     for x in &req_param.metric_history_list {
         let x = x.clone();
         let created_at = Local.timestamp_millis_opt(x.created_at*1000).unwrap();
         let _ = ActiveModel {
             id: Default::default(),
+            sql_digest: Set(x.sql_digest),
             sql_str: Set(x.sql_str),
             db_server_ip: Set(remote_ip.clone()),
             db_server_port: Set(x.db_server_port),
@@ -56,11 +61,257 @@ pub async fn register(req_param: web::Json<vt_model::VtNodeRegisterParam>,
             min_duration: Set(x.min_duration as i32),
             exec_count: Set(x.exec_count as i32),
             cache_hit_count: Set(x.cache_hit_count as i32),
+            throttle_count: Set(x.throttle_count as i32),
             created_at: Set(created_at.naive_local()),
+            config_version: Set(x.config_version),
         }.insert(&app_state_data.conn)
             .await
             .map_err(anyhow::Error::new)?;
     }
 
-    return Ok(HttpResponse::Ok().json(DataWrapper::success("")));
-}
\ No newline at end of file
+    //累加各节点上报的执行次数
+    for x in &req_param.warm_query_list {
+        let sql = r#"
+        insert into cache_warm_query(cache_config_id, user_name, schema_name, sql_hash, sql_str, hit_count)
+        values (?, ?, ?, ?, ?, ?)
+        on duplicate key update hit_count = hit_count + values(hit_count)
+        "#;
+        app_state_data.conn.execute(Statement::from_sql_and_values(DatabaseBackend::MySql, sql, vec![
+            x.cache_config_id.into(), x.user_name.clone().into(), x.schema_name.clone().into(), x.sql_hash.clone().into(), x.sql_str.clone().into(), x.hit_count.into(),
+        ]))
+            .await
+            .map_err(anyhow::Error::new)?;
+    }
+
+    //下发待执行的指令
+    let commands: Vec<VtNodeCommand> = app_state_data.vt_node_commands_lock.lock().await
+        .remove(key)
+        .unwrap_or_default();
+    return Ok(HttpResponse::Ok().json(DataWrapper::success(commands)));
+}
+
+//节点停止服务时从在线列表中移除,不再等待过期
+#[post("/vt_node/deregister")]
+pub async fn deregister(req_param: web::Json<vt_model::VtNodeDeregisterParam>,
+                        _req: HttpRequest,
+                        app_state_data: Data<AppState>,
+                        _vt_node: VtNodeAuth, ) -> Result<HttpResponse, SysError> {
+    let remote_addr = _req.peer_addr()
+        .ok_or_else(|| SysError::BIZ(String::from("无法获取客户端IP")))?;
+    let key = format!("{}:{}", remote_addr.ip(), req_param.port);
+    app_state_data.vt_nodes_lock.lock().await.remove(&key);
+    app_state_data.vt_node_commands_lock.lock().await.remove(&key);
+    info!("vt-node {} deregistered", key);
+    Ok(HttpResponse::Ok().json(DataWrapper::success("")))
+}
+
+//节点预热缓存时获取历史语句和配置的参数
+#[post("/vt_node/cache_warm_tasks")]
+pub async fn cache_warm_tasks(req_param: web::Json<WarmTaskParam>,
+                              app_state_data: Data<AppState>,
+                              _vt_node: VtNodeAuth, ) -> Result<HttpResponse, SysError> {
+    let conn = &app_state_data.conn;
+    let mut query = CacheConfig::find().filter(cache_config::Column::Enabled.eq(1));
+    if let Some(cache_config_id) = req_param.cache_config_id {
+        query = query.filter(cache_config::Column::Id.eq(cache_config_id));
+    }
+    let cache_config_list = query.all(conn).await.map_err(anyhow::Error::new)?;
+
+    let mut tasks = vec![];
+    for cache_config in cache_config_list {
+        let sql = r#"
+        select cache_config_id, user_name, schema_name, sql_hash, sql_str, hit_count
+        from cache_warm_query
+        where cache_config_id = ? and user_name = ?
+        order by hit_count desc
+        limit ?
+        "#;
+        //其他用户的语句不能用预热账号回放
+        let queries = WarmQuery::find_by_statement(Statement::from_sql_and_values(DatabaseBackend::MySql, sql, vec![
+            cache_config.id.into(), req_param.user_name.clone().into(), req_param.top_n.into(),
+        ]))
+            .all(conn)
+            .await
+            .map_err(anyhow::Error::new)?;
+        let params = cache_config_model::parse_warm_params(&cache_config.warm_params).unwrap_or_else(|e| {
+            log::warn!("invalid warm_params of cache_config {}:{}", cache_config.id, e);
+            vec![]
+        });
+        tasks.push(WarmTask {
+            cache_config_id: cache_config.id,
+            sql_template: cache_config.sql_template,
+            duration: cache_config.duration,
+            queries,
+            params,
+        });
+    }
+    Ok(HttpResponse::Ok().json(DataWrapper::success(tasks)))
+}
+
+//config_watch默认的等待时间
+const CONFIG_WATCH_TIMEOUT_SECONDS: u64 = 30;
+//config_watch最长的等待时间,节点传入更大的值时按该值返回
+const CONFIG_WATCH_MAX_TIMEOUT_SECONDS: u64 = 60;
+
+pub async fn load_config_snapshot(conn: &DatabaseConnection) -> Result<MetaSnapshot, SysError> {
+    let cache_configs = CacheConfig::find()
+        .filter(cache_config::Column::Enabled.eq(1))
+        .order_by_asc(cache_config::Column::Id)
+        .all(conn).await.map_err(anyhow::Error::new)?
+        .into_iter()
+        .map(|x| CacheConfigRow {
+            id: x.id,
+            sql_template: x.sql_template,
+            duration: x.duration,
+            timeout_ms: x.timeout_ms,
+            admission_mode: x.admission_mode,
+            max_result_bytes: x.max_result_bytes,
+        })
+        .collect::<Vec<_>>();
+    let rate_limit_configs = RateLimitConfig::find()
+        .filter(rate_limit_config::Column::Enabled.eq(1))
+        .order_by_asc(rate_limit_config::Column::Id)
+        .all(conn).await.map_err(anyhow::Error::new)?
+        .into_iter()
+        .map(|x| RateLimitConfigRow {
+            id: x.id,
+            limit_type: x.limit_type,
+            limit_key: x.limit_key,
+            max_qps: x.max_qps,
+            max_concurrency: x.max_concurrency,
+            queue_timeout_ms: x.queue_timeout_ms,
+        })
+        .collect::<Vec<_>>();
+    let firewall_rules = FirewallRule::find()
+        .filter(firewall_rule::Column::Enabled.eq(1))
+        .order_by_asc(firewall_rule::Column::Id)
+        .all(conn).await.map_err(anyhow::Error::new)?
+        .into_iter()
+        .map(|x| FirewallRuleRow {
+            id: x.id,
+            rule_type: x.rule_type,
+            sql_template: x.sql_template,
+            users: x.users,
+            dry_run: x.dry_run != 0,
+        })
+        .collect::<Vec<_>>();
+    let query_rewrites = QueryRewrite::find()
+        .filter(query_rewrite::Column::Enabled.eq(1))
+        .order_by_asc(query_rewrite::Column::Id)
+        .all(conn).await.map_err(anyhow::Error::new)?
+        .into_iter()
+        .map(|x| QueryRewriteRow {
+            id: x.id,
+            sql_template: x.sql_template,
+            replacement: x.replacement,
+        })
+        .collect::<Vec<_>>();
+    let content = serde_json::to_string(&(&cache_configs, &rate_limit_configs, &firewall_rules, &query_rewrites))
+        .map_err(anyhow::Error::new)?;
+    Ok(MetaSnapshot {
+        version: format!("{:x}", md5::compute(content)),
+        cache_configs,
+        rate_limit_configs,
+        firewall_rules,
+        query_rewrites,
+    })
+}
+
+//刷新任务还没有读取到规则时直接查询
+async fn current_config_snapshot(current: Option<Arc<MetaSnapshot>>, conn: &DatabaseConnection) -> Result<Arc<MetaSnapshot>, SysError> {
+    match current {
+        Some(snapshot) => Ok(snapshot),
+        None => Ok(Arc::new(load_config_snapshot(conn).await?)),
+    }
+}
+
+//节点启动或需要全量规则时调用
+#[post("/vt_node/config_snapshot")]
+pub async fn config_snapshot(app_state_data: Data<AppState>,
+                             _vt_node: VtNodeAuth, ) -> Result<HttpResponse, SysError> {
+    let current = app_state_data.config_snapshot.borrow().clone();
+    let snapshot = current_config_snapshot(current, &app_state_data.conn).await?;
+    Ok(HttpResponse::Ok().json(DataWrapper::success(snapshot.as_ref())))
+}
+
+//长轮询,规则版本与节点当前版本不同或超时后返回最新的规则
+#[post("/vt_node/config_watch")]
+pub async fn config_watch(req_param: web::Json<MetaWatchParam>,
+                          app_state_data: Data<AppState>,
+                          _vt_node: VtNodeAuth, ) -> Result<HttpResponse, SysError> {
+    let timeout_seconds = match req_param.timeout_seconds {
+        0 => CONFIG_WATCH_TIMEOUT_SECONDS,
+        timeout_seconds => timeout_seconds.min(CONFIG_WATCH_MAX_TIMEOUT_SECONDS),
+    };
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(timeout_seconds);
+    let mut receiver = app_state_data.config_snapshot.clone();
+    loop {
+        //标记为已读,changed只在之后的更新时返回
+        let current = receiver.borrow_and_update().clone();
+        let snapshot = current_config_snapshot(current, &app_state_data.conn).await?;
+        if snapshot.version != req_param.version {
+            info!("config snapshot {} sent to {}", snapshot.version, req_param.node_id);
+            return Ok(HttpResponse::Ok().json(DataWrapper::success(snapshot.as_ref())));
+        }
+        match tokio::time::timeout_at(deadline, receiver.changed()).await {
+            Ok(Ok(())) => continue,
+            //超时或刷新任务已结束
+            _ => return Ok(HttpResponse::Ok().json(DataWrapper::success(snapshot.as_ref()))),
+        }
+    }
+}
+
+//通知所有在线节点重新加载config.toml,节点在下次注册时执行
+#[post("/vt_node/reload_config")]
+pub async fn reload_config(app_state_data: Data<AppState>,
+                           current_user: CurrentUser, ) -> Result<HttpResponse, SysError> {
+    let vt_nodes: Vec<String> = app_state_data.vt_nodes_lock.lock().await.keys().cloned().collect();
+    if vt_nodes.is_empty() {
+        return Err(SysError::BIZ(String::from("没有在线的vt-node")));
+    }
+    let mut vt_node_commands = app_state_data.vt_node_commands_lock.lock().await;
+    for vt_node in vt_nodes.iter() {
+        vt_node_commands
+            .entry(vt_node.clone())
+            .or_default()
+            .push(VtNodeCommand::ReloadConfig);
+    }
+    info!("user {} reloads config of {:?}", current_user.user_name, vt_nodes);
+    Ok(HttpResponse::Ok().json(DataWrapper::success(vt_nodes)))
+}
+
+//同时查询所有在线节点的客户端连接,无法访问的节点返回错误信息
+#[post("/vt_node/sessions")]
+pub async fn sessions(app_state_data: Data<AppState>,
+                      _current_user: CurrentUser, ) -> Result<HttpResponse, SysError> {
+    let mut vt_nodes: Vec<(String, VtNodeState)> = app_state_data.vt_nodes_lock.lock().await
+        .iter()
+        .map(|(vt_node, vt_node_state)| (vt_node.clone(), vt_node_state.clone()))
+        .collect();
+    vt_nodes.sort_by(|a, b| a.0.cmp(&b.0));
+    let result = futures::future::join_all(vt_nodes.iter().map(|(vt_node, vt_node_state)| async move {
+        match vt_node_client::get_json(vt_node, vt_node_state, "/sessions", &[]).await {
+            Ok(sessions) => VtNodeSessions { vt_node: vt_node.clone(), sessions: Some(sessions), error: None },
+            Err(SysError::BIZ(err)) => VtNodeSessions { vt_node: vt_node.clone(), sessions: None, error: Some(err) },
+            Err(SysError::SYSTEM(err)) => VtNodeSessions { vt_node: vt_node.clone(), sessions: None, error: Some(format!("{:?}", err)) },
+        }
+    })).await;
+    Ok(HttpResponse::Ok().json(DataWrapper::success(result)))
+}
+
+//关闭节点上的客户端连接,执行中的语句同时被KILL QUERY
+#[post("/vt_node/kill_session")]
+pub async fn kill_session(req_param: web::Json<KillSessionParam>,
+                          app_state_data: Data<AppState>,
+                          current_user: CurrentUser, ) -> Result<HttpResponse, SysError> {
+    let vt_node_state = app_state_data.vt_nodes_lock.lock().await
+        .get(&req_param.vt_node)
+        .cloned()
+        .ok_or_else(|| SysError::BIZ(format!("vt-node {} 不在线", req_param.vt_node)))?;
+    info!("user {} kills session {} on {}", current_user.user_name, req_param.session_id, req_param.vt_node);
+    let result = vt_node_client::post_json(&req_param.vt_node, &vt_node_state, "/sessions/kill", &[("id", req_param.session_id.to_string())]).await?;
+    if result.get("killed").and_then(|v| v.as_bool()) != Some(true) {
+        return Err(SysError::BIZ(format!("vt-node {} 上没有连接 {}", req_param.vt_node, req_param.session_id)));
+    }
+    Ok(HttpResponse::Ok().json(DataWrapper::success(result)))
+}
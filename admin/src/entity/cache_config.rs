@@ -10,6 +10,10 @@ pub struct Model {
     pub id: i32,
     pub sql_template: String,
     pub duration: i32,
+    pub timeout_ms: i32,
+    pub admission_mode: String,
+    pub max_result_bytes: i32,
+    pub warm_params: String,
     pub cache_name: String,
     pub remark: String,
     pub enabled: i32,
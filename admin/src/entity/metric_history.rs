@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
+    pub sql_digest: String,
     pub sql_str: String,
     pub db_server_ip: String,
     pub db_server_port: String,
@@ -17,7 +18,9 @@ pub struct Model {
     pub min_duration: i32,
     pub exec_count: i32,
     pub cache_hit_count: i32,
+    pub throttle_count: i32,
     pub created_at: DateTime,
+    pub config_version: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -3,5 +3,9 @@
 pub mod prelude;
 
 pub mod cache_config;
+pub mod cache_purge_log;
+pub mod firewall_rule;
 pub mod metric_history;
+pub mod query_rewrite;
+pub mod rate_limit_config;
 pub mod sys_user;
@@ -1,5 +1,9 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.6
 
 pub use super::cache_config::Entity as CacheConfig;
+pub use super::cache_purge_log::Entity as CachePurgeLog;
+pub use super::firewall_rule::Entity as FirewallRule;
 pub use super::metric_history::Entity as MetricHistory;
+pub use super::query_rewrite::Entity as QueryRewrite;
+pub use super::rate_limit_config::Entity as RateLimitConfig;
 pub use super::sys_user::Entity as SysUser;
@@ -1,12 +1,17 @@
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use chrono::Local;
 
-use log::{debug, info};
+use log::{debug, info, warn};
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
 use tokio::{runtime, time};
+use tokio::sync::watch;
 use tokio::time::Instant;
 
 use crate::AppState;
+use crate::controller::vt_node_controller::load_config_snapshot;
+use crate::model::vt_model::MetaSnapshot;
 
 //启用vt-server存活状态检查任务
 pub async fn enable_vt_node_alive_check(app_state: AppState) {
@@ -18,9 +23,11 @@ pub async fn enable_vt_node_alive_check(app_state: AppState) {
         loop {
             interval.tick().await;
             let mut vt_nodes = app_state.vt_nodes_lock.lock().await;
-            vt_nodes.retain(|_,expire_at|{
-                &Local::now() < expire_at
+            vt_nodes.retain(|_,vt_node_state|{
+                Local::now() < vt_node_state.expire_at
             });
+            //下线节点的指令不再下发
+            app_state.vt_node_commands_lock.lock().await.retain(|vt_node, _| vt_nodes.contains_key(vt_node));
             for entry in vt_nodes.iter() {
                 debug!("current vt_node:{:?}",entry)
             }
@@ -28,4 +35,53 @@ pub async fn enable_vt_node_alive_check(app_state: AppState) {
             debug!("check validity .time:{:?}", start.elapsed());
         }
     });
-}
\ No newline at end of file
+}
+//超过该天数没有再执行的预热语句被删除
+const WARM_QUERY_RETENTION_DAYS: i64 = 7;
+
+//定时清理cache_warm_query,避免一次性语句无限累积
+pub async fn enable_cache_warm_query_prune(app_state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let sql = "delete from cache_warm_query where updated_at < date_sub(now(), interval ? day)";
+            let result = app_state.conn
+                .execute(Statement::from_sql_and_values(DatabaseBackend::MySql, sql, vec![WARM_QUERY_RETENTION_DAYS.into()]))
+                .await;
+            match result {
+                Ok(result) => info!("pruned {} cache warm queries.", result.rows_affected()),
+                Err(err) => warn!("prune cache warm queries fail.err:{:?}", err),
+            }
+        }
+    });
+}
+
+//其他admin实例修改的规则只能通过定时重新读取发现
+const CONFIG_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+//规则快照只在这里读取,版本变化时通知等待中的config_watch
+pub async fn enable_config_snapshot_refresh(app_state: AppState, sender: watch::Sender<Option<Arc<MetaSnapshot>>>) {
+    tokio::spawn(async move {
+        loop {
+            //先注册等待再读取,避免错过读取期间的变更
+            let notified = app_state.config_changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            match load_config_snapshot(&app_state.conn).await {
+                Ok(snapshot) => {
+                    sender.send_if_modified(|current| {
+                        if current.as_ref().is_some_and(|current| current.version == snapshot.version) {
+                            return false;
+                        }
+                        info!("config snapshot {} loaded.", snapshot.version);
+                        *current = Some(Arc::new(snapshot));
+                        true
+                    });
+                }
+                Err(err) => warn!("load config snapshot fail.err:{:?}", err),
+            }
+            let _ = tokio::time::timeout(CONFIG_RELOAD_INTERVAL, notified).await;
+        }
+    });
+}
@@ -2,7 +2,7 @@
 
 use std::collections::HashMap;
 use crate::config::app_config::ApplicationSettings;
-use crate::controller::{cache_config_controller, metric_history_controller, vt_node_controller};
+use crate::controller::{cache_config_controller, firewall_rule_controller, metric_history_controller, query_rewrite_controller, rate_limit_config_controller, rule_file_controller, vt_node_controller};
 use actix_cors::Cors;
 use actix_settings::{ApplySettings as _, BasicSettings};
 use actix_web::http::header;
@@ -16,8 +16,9 @@ use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use actix_web::web::Data;
 use chrono::{DateTime, Local};
-use tokio::sync::Mutex;
-use crate::job::vt_node_job::enable_vt_node_alive_check;
+use tokio::sync::{watch, Mutex, Notify};
+use crate::job::vt_node_job::{enable_cache_warm_query_prune, enable_config_snapshot_refresh, enable_vt_node_alive_check};
+use crate::model::vt_model::{MetaSnapshot, VtNodeCommand, VtNodeState};
 
 mod config;
 mod controller;
@@ -31,7 +32,14 @@ mod sys_log;
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub conn: DatabaseConnection,
-    pub vt_nodes_lock:Arc<Mutex<HashMap<String, DateTime<Local>>>>
+    //ip:port -> 节点状态
+    pub vt_nodes_lock:Arc<Mutex<HashMap<String, VtNodeState>>>,
+    //节点 -> 待下发的指令,节点注册时取走
+    pub vt_node_commands_lock:Arc<Mutex<HashMap<String, Vec<VtNodeCommand>>>>,
+    //规则变更时唤醒规则快照的刷新任务
+    pub config_changed:Arc<Notify>,
+    //刷新任务读取的最新规则快照,节点的请求直接返回,不再查询数据库
+    pub config_snapshot:watch::Receiver<Option<Arc<MetaSnapshot>>>,
 }
 
 #[tokio::main]
@@ -50,10 +58,13 @@ async fn main() -> std::io::Result<()> {
         .sqlx_logging_level(log::LevelFilter::Info); // Setting default PostgreSQL schema
 
     let conn = Database::connect(opt).await.unwrap();
-    let locked_vt_nodes:Arc<Mutex<HashMap<String, DateTime<Local>>>> = Arc::new(Mutex::new(HashMap::new()));
-    let app_state = AppState { conn,vt_nodes_lock:locked_vt_nodes };
+    let locked_vt_nodes:Arc<Mutex<HashMap<String, VtNodeState>>> = Arc::new(Mutex::new(HashMap::new()));
+    let (config_snapshot_sender, config_snapshot) = watch::channel(None);
+    let app_state = AppState { conn,vt_nodes_lock:locked_vt_nodes,vt_node_commands_lock:Arc::new(Mutex::new(HashMap::new())),config_changed:Arc::new(Notify::new()),config_snapshot };
 
     enable_vt_node_alive_check(app_state.clone()).await;
+    enable_cache_warm_query_prune(app_state.clone()).await;
+    enable_config_snapshot_refresh(app_state.clone(), config_snapshot_sender).await;
 
     let http_server = HttpServer::new({
         let settings = settings.clone();
@@ -88,8 +99,30 @@ async fn main() -> std::io::Result<()> {
                 .service(cache_config_controller::list)
                 .service(cache_config_controller::create)
                 .service(cache_config_controller::delete)
+                .service(cache_config_controller::warm)
+                .service(cache_config_controller::purge)
+                .service(cache_config_controller::preview)
+                .service(rate_limit_config_controller::list)
+                .service(rate_limit_config_controller::create)
+                .service(rate_limit_config_controller::delete)
+                .service(firewall_rule_controller::list)
+                .service(firewall_rule_controller::create)
+                .service(firewall_rule_controller::delete)
+                .service(query_rewrite_controller::list)
+                .service(query_rewrite_controller::create)
+                .service(query_rewrite_controller::delete)
+                .service(rule_file_controller::export)
+                .service(rule_file_controller::import)
                 .service(vt_node_controller::register)
+                .service(vt_node_controller::deregister)
+                .service(vt_node_controller::cache_warm_tasks)
+                .service(vt_node_controller::config_snapshot)
+                .service(vt_node_controller::config_watch)
+                .service(vt_node_controller::reload_config)
+                .service(vt_node_controller::sessions)
+                .service(vt_node_controller::kill_session)
                 .service(metric_history_controller::list_sql)
+                .service(metric_history_controller::suggest)
 
                 // 必须在最后
                 .service(actix_files::Files::new( "/",settings.application.clone().static_dir).index_file("index.html"))
@@ -7,6 +7,39 @@ use crate::entity::cache_config::{ActiveModel, Model};
 use crate::model::PageParam;
 use crate::utils::orm::option_to_active_value;
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachePurgeParam {
+    //CACHE_CONFIG/TABLE/TAG/SQL
+    #[serde(rename = "purge_type")]
+    pub purge_type: String,
+    //cache_config的id,表名,标签或SQL
+    pub value: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachePreviewParam {
+    //缓存的key,如cache:"select * from users"
+    #[serde(default)]
+    pub key: String,
+    //Redis的glob,如*users*,与key二选一
+    #[serde(default)]
+    pub pattern: String,
+    //按pattern预览时最多返回的key数
+    pub limit: Option<u32>,
+    //每个缓存最多返回的行数
+    pub max_rows: Option<u32>,
+}
+
+/// Parse the `warm_params` of a cache config, an empty string means no params
+pub fn parse_warm_params(warm_params: &str) -> Result<Vec<Vec<serde_json::Value>>, String> {
+    if warm_params.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    serde_json::from_str::<Vec<Vec<serde_json::Value>>>(warm_params).map_err(|e| e.to_string())
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CacheConfigListParam {
@@ -28,6 +61,18 @@ pub struct CacheConfigCreateParam {
     pub sql_template: Option<String>,
     pub remark: Option<String>,
     pub duration: Option<i32>,
+    //语句超时时间(毫秒),0表示使用用户或全局配置
+    #[serde(rename = "timeout_ms")]
+    pub timeout_ms: Option<i32>,
+    //ALWAYS:总是缓存 ADAPTIVE:只缓存慢查询或高频查询
+    #[serde(rename = "admission_mode")]
+    pub admission_mode: Option<String>,
+    //可缓存的最大结果字节数,0表示使用全局配置
+    #[serde(rename = "max_result_bytes")]
+    pub max_result_bytes: Option<i32>,
+    //预热参数,JSON数组,如[[1,"a"],[2,"b"]]
+    #[serde(rename = "warm_params")]
+    pub warm_params: Option<String>,
     pub enabled: Option<i32>,
 }
 
@@ -39,6 +84,10 @@ impl CacheConfigCreateParam {
         cache_config_entity.id = option_to_active_value(self.id);
         cache_config_entity.sql_template = option_to_active_value(self.sql_template);
         cache_config_entity.duration = option_to_active_value(self.duration);
+        cache_config_entity.timeout_ms = option_to_active_value(self.timeout_ms);
+        cache_config_entity.admission_mode = option_to_active_value(self.admission_mode.map(|v| v.to_uppercase()));
+        cache_config_entity.max_result_bytes = option_to_active_value(self.max_result_bytes);
+        cache_config_entity.warm_params = option_to_active_value(self.warm_params);
         cache_config_entity.cache_name = option_to_active_value(self.cache_name);
         cache_config_entity.remark = option_to_active_value(self.remark);
         cache_config_entity.enabled = option_to_active_value(self.enabled);
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entity::firewall_rule::ActiveModel;
+use crate::model::PageParam;
+use crate::utils::orm::option_to_active_value;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirewallRuleListParam {
+    pub page_param: PageParam,
+    #[serde(rename = "rule_name")]
+    pub rule_name: Option<String>,
+    #[serde(rename = "rule_type")]
+    pub rule_type: Option<String>,
+    pub enabled: Option<i32>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirewallRuleCreateParam {
+    pub id: Option<i32>,
+    #[serde(rename = "rule_name")]
+    pub rule_name: Option<String>,
+    //DENY_NO_WHERE/DENY_DDL/DENY_PATTERN/ALLOW_PATTERN
+    #[serde(rename = "rule_type")]
+    pub rule_type: Option<String>,
+    //仅DENY_PATTERN/ALLOW_PATTERN使用
+    #[serde(rename = "sql_template")]
+    pub sql_template: Option<String>,
+    //逗号分隔的用户名,为空表示所有用户
+    pub users: Option<String>,
+    #[serde(rename = "dry_run")]
+    pub dry_run: Option<i32>,
+    pub remark: Option<String>,
+    pub enabled: Option<i32>,
+}
+
+impl FirewallRuleCreateParam {
+    pub fn to_active_model(self) -> ActiveModel {
+        let mut firewall_rule_entity = ActiveModel {
+            ..Default::default()
+        };
+        firewall_rule_entity.id = option_to_active_value(self.id);
+        firewall_rule_entity.rule_name = option_to_active_value(self.rule_name);
+        firewall_rule_entity.rule_type = option_to_active_value(self.rule_type.map(|v| v.to_uppercase()));
+        firewall_rule_entity.sql_template = option_to_active_value(self.sql_template);
+        firewall_rule_entity.users = option_to_active_value(self.users);
+        firewall_rule_entity.dry_run = option_to_active_value(self.dry_run);
+        firewall_rule_entity.remark = option_to_active_value(self.remark);
+        firewall_rule_entity.enabled = option_to_active_value(self.enabled);
+        return firewall_rule_entity;
+    }
+}
@@ -1,4 +1,5 @@
 use sea_orm::prelude::Decimal;
+use sea_orm::FromQueryResult;
 use crate::model::PageParam;
 use serde::{Serialize,Deserialize};
 
@@ -12,6 +13,7 @@ pub struct MetricQueryParam{
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MetricResult{
+    pub sql_digest:String,
     pub sql_str:String,
     pub dates:Vec<String>,
     pub avg_durations:Vec<Decimal>,
@@ -19,4 +21,27 @@ pub struct MetricResult{
     pub max_durations:Vec<i64>,
     pub exec_counts: Vec<Decimal>,
     pub cache_hit_counts: Vec<Decimal>,
-}
\ No newline at end of file
+    pub throttle_counts: Vec<Decimal>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheSuggestionParam{
+    //统计最近多少分钟,默认60
+    pub minutes:Option<i64>,
+    //默认20
+    pub limit:Option<i64>,
+}
+
+/**
+ * 执行次数多且还没有缓存配置的SQL指纹,sql_str可以直接作为缓存模板
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromQueryResult)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheSuggestion{
+    pub sql_digest:String,
+    pub sql_str:String,
+    pub exec_count:Decimal,
+    pub cache_hit_count:Decimal,
+    pub avg_duration:Decimal,
+}
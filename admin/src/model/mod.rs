@@ -22,6 +22,10 @@ pub mod cache_config_model;
 pub mod user_model;
 pub mod vt_model;
 pub mod metric;
+pub mod rate_limit_config_model;
+pub mod firewall_rule_model;
+pub mod query_rewrite_model;
+pub mod rule_file_model;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataWrapper<V> {
@@ -113,6 +117,29 @@ impl PageParam {
     }
 }
 
+/**
+ * 节点调用的接口用共享token鉴权,而不是用户的JWT
+ */
+#[derive(Debug, Clone)]
+pub struct VtNodeAuth;
+
+impl FromRequest for VtNodeAuth {
+    type Error = SysError;
+    type Future = futures::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req.headers()
+            .get(utils::vt_node_token::VT_NODE_TOKEN_HEADER)
+            .and_then(|header| header.to_str().ok())
+            .unwrap_or_default();
+        if utils::vt_node_token::is_valid(token) {
+            ok(VtNodeAuth)
+        } else {
+            err(SysError::BIZ(String::from("vt-node token无效")))
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CurrentUser {
     pub user_id: i64,
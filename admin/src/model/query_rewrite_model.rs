@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entity::query_rewrite::ActiveModel;
+use crate::model::PageParam;
+use crate::utils::orm::option_to_active_value;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRewriteListParam {
+    pub page_param: PageParam,
+    #[serde(rename = "rule_name")]
+    pub rule_name: Option<String>,
+    pub enabled: Option<i32>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRewriteCreateParam {
+    pub id: Option<i32>,
+    #[serde(rename = "rule_name")]
+    pub rule_name: Option<String>,
+    #[serde(rename = "sql_template")]
+    pub sql_template: Option<String>,
+    //`?`按顺序替换为模板占位符对应的值,`$n`替换为第n个值
+    pub replacement: Option<String>,
+    pub remark: Option<String>,
+    pub enabled: Option<i32>,
+}
+
+impl QueryRewriteCreateParam {
+    pub fn to_active_model(self) -> ActiveModel {
+        let mut query_rewrite_entity = ActiveModel {
+            ..Default::default()
+        };
+        query_rewrite_entity.id = option_to_active_value(self.id);
+        query_rewrite_entity.rule_name = option_to_active_value(self.rule_name);
+        query_rewrite_entity.sql_template = option_to_active_value(self.sql_template);
+        query_rewrite_entity.replacement = option_to_active_value(self.replacement);
+        query_rewrite_entity.remark = option_to_active_value(self.remark);
+        query_rewrite_entity.enabled = option_to_active_value(self.enabled);
+        return query_rewrite_entity;
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entity::rate_limit_config::ActiveModel;
+use crate::model::PageParam;
+use crate::utils::orm::option_to_active_value;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfigListParam {
+    pub page_param: PageParam,
+    #[serde(rename = "limit_name")]
+    pub limit_name: Option<String>,
+    #[serde(rename = "limit_type")]
+    pub limit_type: Option<String>,
+    pub enabled: Option<i32>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfigCreateParam {
+    pub id: Option<i32>,
+    #[serde(rename = "limit_name")]
+    pub limit_name: Option<String>,
+    //USER/IP/PATTERN
+    #[serde(rename = "limit_type")]
+    pub limit_type: Option<String>,
+    //用户名/客户端IP/SQL模板,`*`表示每个用户(IP)分别限流
+    #[serde(rename = "limit_key")]
+    pub limit_key: Option<String>,
+    #[serde(rename = "max_qps")]
+    pub max_qps: Option<i32>,
+    #[serde(rename = "max_concurrency")]
+    pub max_concurrency: Option<i32>,
+    #[serde(rename = "queue_timeout_ms")]
+    pub queue_timeout_ms: Option<i32>,
+    pub remark: Option<String>,
+    pub enabled: Option<i32>,
+}
+
+impl RateLimitConfigCreateParam {
+    pub fn to_active_model(self) -> ActiveModel {
+        let mut rate_limit_config_entity = ActiveModel {
+            ..Default::default()
+        };
+        rate_limit_config_entity.id = option_to_active_value(self.id);
+        rate_limit_config_entity.limit_name = option_to_active_value(self.limit_name);
+        rate_limit_config_entity.limit_type = option_to_active_value(self.limit_type.map(|v| v.to_uppercase()));
+        rate_limit_config_entity.limit_key = option_to_active_value(self.limit_key);
+        rate_limit_config_entity.max_qps = option_to_active_value(self.max_qps);
+        rate_limit_config_entity.max_concurrency = option_to_active_value(self.max_concurrency);
+        rate_limit_config_entity.queue_timeout_ms = option_to_active_value(self.queue_timeout_ms);
+        rate_limit_config_entity.remark = option_to_active_value(self.remark);
+        rate_limit_config_entity.enabled = option_to_active_value(self.enabled);
+        return rate_limit_config_entity;
+    }
+}
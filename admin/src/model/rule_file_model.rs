@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entity::{cache_config, firewall_rule, query_rewrite, rate_limit_config};
+
+/**
+ * 规则文件,与vt-node的file模式读取的格式相同,可以保存在git中review并在环境之间迁移
+ * 名称,备注和预热参数只在admin中使用,vt-node读取时忽略
+ */
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RulesFile {
+    pub cache_configs: Vec<CacheConfigItem>,
+    pub rate_limit_configs: Vec<RateLimitConfigItem>,
+    pub firewall_rules: Vec<FirewallRuleItem>,
+    pub query_rewrites: Vec<QueryRewriteItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheConfigItem {
+    pub id: i32,
+    #[serde(default)]
+    pub cache_name: String,
+    pub sql_template: String,
+    pub duration: i32,
+    #[serde(default)]
+    pub timeout_ms: i32,
+    #[serde(default = "default_admission_mode")]
+    pub admission_mode: String,
+    #[serde(default)]
+    pub max_result_bytes: i32,
+    #[serde(default)]
+    pub warm_params: String,
+    #[serde(default)]
+    pub remark: String,
+}
+
+fn default_admission_mode() -> String {
+    String::from("ALWAYS")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfigItem {
+    pub id: i32,
+    #[serde(default)]
+    pub limit_name: String,
+    pub limit_type: String,
+    pub limit_key: String,
+    #[serde(default)]
+    pub max_qps: i32,
+    #[serde(default)]
+    pub max_concurrency: i32,
+    #[serde(default)]
+    pub queue_timeout_ms: i32,
+    #[serde(default)]
+    pub remark: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FirewallRuleItem {
+    pub id: i32,
+    #[serde(default)]
+    pub rule_name: String,
+    pub rule_type: String,
+    #[serde(default)]
+    pub sql_template: String,
+    #[serde(default)]
+    pub users: String,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub remark: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryRewriteItem {
+    pub id: i32,
+    #[serde(default)]
+    pub rule_name: String,
+    pub sql_template: String,
+    pub replacement: String,
+    #[serde(default)]
+    pub remark: String,
+}
+
+impl From<cache_config::Model> for CacheConfigItem {
+    fn from(x: cache_config::Model) -> Self {
+        CacheConfigItem {
+            id: x.id,
+            cache_name: x.cache_name,
+            sql_template: x.sql_template,
+            duration: x.duration,
+            timeout_ms: x.timeout_ms,
+            admission_mode: x.admission_mode,
+            max_result_bytes: x.max_result_bytes,
+            warm_params: x.warm_params,
+            remark: x.remark,
+        }
+    }
+}
+
+impl From<rate_limit_config::Model> for RateLimitConfigItem {
+    fn from(x: rate_limit_config::Model) -> Self {
+        RateLimitConfigItem {
+            id: x.id,
+            limit_name: x.limit_name,
+            limit_type: x.limit_type,
+            limit_key: x.limit_key,
+            max_qps: x.max_qps,
+            max_concurrency: x.max_concurrency,
+            queue_timeout_ms: x.queue_timeout_ms,
+            remark: x.remark,
+        }
+    }
+}
+
+impl From<firewall_rule::Model> for FirewallRuleItem {
+    fn from(x: firewall_rule::Model) -> Self {
+        FirewallRuleItem {
+            id: x.id,
+            rule_name: x.rule_name,
+            rule_type: x.rule_type,
+            sql_template: x.sql_template,
+            users: x.users,
+            dry_run: x.dry_run != 0,
+            remark: x.remark,
+        }
+    }
+}
+
+impl From<query_rewrite::Model> for QueryRewriteItem {
+    fn from(x: query_rewrite::Model) -> Self {
+        QueryRewriteItem {
+            id: x.id,
+            rule_name: x.rule_name,
+            sql_template: x.sql_template,
+            replacement: x.replacement,
+            remark: x.remark,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleExportParam {
+    //toml/yaml,默认toml
+    pub format: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleImportParam {
+    //toml/yaml,默认toml
+    pub format: Option<String>,
+    pub content: String,
+    //为true时停用文件中没有的规则,使admin中启用的规则与文件一致
+    #[serde(rename = "disable_missing")]
+    pub disable_missing: Option<bool>,
+}
+
+fn is_yaml(format: Option<&str>) -> bool {
+    matches!(format.map(|v| v.to_lowercase()).as_deref(), Some("yaml") | Some("yml"))
+}
+
+/// Serialize the rules, TOML unless `format` is yaml
+pub fn to_rules_content(format: Option<&str>, rules_file: &RulesFile) -> Result<String, String> {
+    if is_yaml(format) {
+        return serde_yaml::to_string(rules_file).map_err(|e| e.to_string());
+    }
+    //先转换为Value,使普通字段排在表之前
+    let value = toml::Value::try_from(rules_file).map_err(|e| e.to_string())?;
+    toml::to_string(&value).map_err(|e| e.to_string())
+}
+
+/// Parse rules exported by `to_rules_content` or written by hand
+pub fn parse_rules_content(format: Option<&str>, content: &str) -> Result<RulesFile, String> {
+    if is_yaml(format) {
+        return serde_yaml::from_str(content).map_err(|e| e.to_string());
+    }
+    toml::from_str(content).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_rules_content() {
+    let rules_file = RulesFile {
+        cache_configs: vec![CacheConfigItem {
+            id: 1,
+            cache_name: String::from("user"),
+            sql_template: String::from("select * from users where id = ?"),
+            duration: 60,
+            timeout_ms: 0,
+            admission_mode: String::from("ALWAYS"),
+            max_result_bytes: 0,
+            warm_params: String::from("[[1]]"),
+            remark: String::new(),
+        }],
+        ..Default::default()
+    };
+    for format in [None, Some("yaml")] {
+        let content = to_rules_content(format, &rules_file).unwrap();
+        assert_eq!(rules_file, parse_rules_content(format, &content).unwrap());
+    }
+}
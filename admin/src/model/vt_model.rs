@@ -1,7 +1,12 @@
+use chrono::{DateTime, Local};
+use sea_orm::FromQueryResult;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MetricHistory {
+    #[serde(default)]
+    pub sql_digest: String,
     pub sql_str: String,
     pub db_server_port: String,
     pub database_name: String,
@@ -10,14 +15,182 @@ pub struct MetricHistory {
     pub min_duration: i64,
     pub exec_count: i64,
     pub cache_hit_count: i64,
+    #[serde(default)]
+    pub throttle_count: i64,
     pub created_at: i64,
+    #[serde(default)]
+    pub config_version: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VtNodeRegisterParam {
     pub port: String,
+    //0表示节点版本较旧,不支持预览缓存
+    #[serde(default)]
+    pub metric_port: u16,
     pub metric_history_list: Vec<MetricHistory>,
+    #[serde(default)]
+    pub warm_query_list: Vec<WarmQuery>,
+}
+
+/**
+ * 在线的节点,注册后2分钟内没有再次注册视为下线
+ */
+#[derive(Debug, Clone)]
+pub struct VtNodeState {
+    pub expire_at: DateTime<Local>,
+    pub metric_port: u16,
+}
+
+/**
+ * 一个节点上的客户端连接,节点无法访问时sessions为空
+ */
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VtNodeSessions {
+    pub vt_node: String,
+    pub sessions: Option<Value>,
+    pub error: Option<String>,
+}
+
+/**
+ * 关闭节点上的一个客户端连接
+ */
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KillSessionParam {
+    pub vt_node: String,
+    pub session_id: u64,
+}
+
+/**
+ * 节点停止服务时注销
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VtNodeDeregisterParam {
+    pub port: String,
+}
+
+/**
+ * 代理记录的命中cache_config的具体语句
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, FromQueryResult)]
+pub struct WarmQuery {
+    pub cache_config_id: i32,
+    //执行该语句的MySQL用户,预热时只回放预热账号自己的语句
+    #[serde(default)]
+    pub user_name: String,
+    pub schema_name: String,
+    pub sql_hash: String,
+    pub sql_str: String,
+    pub hit_count: i64,
+}
+
+/**
+ * 在节点下次注册时下发的指令
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum VtNodeCommand {
+    //预热一个cache_config的缓存
+    WarmCache { cache_config_id: i32 },
+    //清除缓存
+    PurgeCache { target: PurgeTarget },
+    //重新加载config.toml,需要重启的配置变更会记录在节点日志中
+    ReloadConfig,
+}
+
+/**
+ * 要清除的缓存,与vt-node的格式一致
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "purge_type", content = "value", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PurgeTarget {
+    CacheConfig(i32),
+    Table(String),
+    Tag(String),
+    Sql(String),
+}
+
+/**
+ * 下发给vt-node的全部规则,version为内容的md5
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MetaSnapshot {
+    pub version: String,
+    pub cache_configs: Vec<CacheConfigRow>,
+    pub rate_limit_configs: Vec<RateLimitConfigRow>,
+    pub firewall_rules: Vec<FirewallRuleRow>,
+    pub query_rewrites: Vec<QueryRewriteRow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheConfigRow {
+    pub id: i32,
+    pub sql_template: String,
+    pub duration: i32,
+    pub timeout_ms: i32,
+    pub admission_mode: String,
+    pub max_result_bytes: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitConfigRow {
+    pub id: i32,
+    pub limit_type: String,
+    pub limit_key: String,
+    pub max_qps: i32,
+    pub max_concurrency: i32,
+    pub queue_timeout_ms: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FirewallRuleRow {
+    pub id: i32,
+    pub rule_type: String,
+    pub sql_template: String,
+    pub users: String,
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryRewriteRow {
+    pub id: i32,
+    pub sql_template: String,
+    pub replacement: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetaWatchParam {
+    pub node_id: String,
+    //节点当前的版本,与最新版本一致时等待变更
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub timeout_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarmTaskParam {
+    //为空时返回所有启用的cache_config
+    pub cache_config_id: Option<i32>,
+    pub top_n: u64,
+    //节点预热使用的MySQL用户
+    #[serde(default)]
+    pub user_name: String,
+}
+
+/**
+ * 一个cache_config的预热任务
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarmTask {
+    pub cache_config_id: i32,
+    pub sql_template: String,
+    pub duration: i32,
+    pub queries: Vec<WarmQuery>,
+    pub params: Vec<Vec<Value>>,
 }
 
 #[test]
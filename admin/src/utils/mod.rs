@@ -1,3 +1,5 @@
 pub mod jwt;
 pub mod orm;
 pub mod password;
+pub mod vt_node_client;
+pub mod vt_node_token;
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use crate::error::SysError;
+use crate::model::vt_model::VtNodeState;
+use crate::utils::vt_node_token;
+
+//请求节点metric端口的超时时间
+const REQUEST_TIMEOUT_SECONDS: u64 = 5;
+
+/// Base URL of the metric port of a vt-node, `vt_node` is its `ip:port` key in the node list
+pub fn metric_url(vt_node: &str, vt_node_state: &VtNodeState) -> Result<String, SysError> {
+    if vt_node_state.metric_port == 0 {
+        return Err(SysError::BIZ(format!("vt-node {} 版本较旧,不支持该操作", vt_node)));
+    }
+    let ip = vt_node.rsplit_once(':').map_or(vt_node, |(ip, _)| ip);
+    let host = if ip.contains(':') { format!("[{}]", ip) } else { ip.to_string() };
+    Ok(format!("http://{}:{}", host, vt_node_state.metric_port))
+}
+
+/// GET a JSON document from the metric port of a vt-node
+pub async fn get_json(vt_node: &str, vt_node_state: &VtNodeState, path: &str, query: &[(&str, String)]) -> Result<serde_json::Value, SysError> {
+    let url = format!("{}{}", metric_url(vt_node, vt_node_state)?, path);
+    send(vt_node, reqwest::Client::new().get(url).query(query)).await
+}
+
+/// POST to the metric port of a vt-node and read the JSON response
+pub async fn post_json(vt_node: &str, vt_node_state: &VtNodeState, path: &str, query: &[(&str, String)]) -> Result<serde_json::Value, SysError> {
+    let url = format!("{}{}", metric_url(vt_node, vt_node_state)?, path);
+    send(vt_node, reqwest::Client::new().post(url).query(query)).await
+}
+
+async fn send(vt_node: &str, request: reqwest::RequestBuilder) -> Result<serde_json::Value, SysError> {
+    //除/metrics外,节点的metric端口都校验token
+    let response = request
+        .header(vt_node_token::VT_NODE_TOKEN_HEADER, vt_node_token::configured_token())
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+        .send().await
+        .map_err(|e| SysError::BIZ(format!("请求vt-node {} 失败:{}", vt_node, e)))?;
+    let status = response.status();
+    let body = response.text().await
+        .map_err(|e| SysError::BIZ(format!("读取vt-node {} 的响应失败:{}", vt_node, e)))?;
+    if !status.is_success() {
+        //错误信息会直接拼接到JSON中
+        return Err(SysError::BIZ(format!("vt-node {} 返回{}:{}", vt_node, status.as_u16(), body.trim().replace('"', "'"))));
+    }
+    serde_json::from_str(&body).map_err(|e| SysError::BIZ(format!("vt-node {} 的响应不是JSON:{}", vt_node, e)))
+}
@@ -0,0 +1,21 @@
+use crate::config::app_config::SETTINGS;
+
+//节点与admin之间互相调用时携带的请求头,值为两边配置的同一个token
+pub const VT_NODE_TOKEN_HEADER: &str = "X-VirtDB-Token";
+
+/// The `vt-node-token` of actix.toml, empty when not configured
+pub fn configured_token() -> String {
+    unsafe {
+        SETTINGS.clone().map(|settings| settings.application.vt_node_token).unwrap_or_default()
+    }
+}
+
+/// Whether `token` equals the configured token, always false when no token is configured.
+/// Compared in constant time so the token can't be guessed byte by byte.
+pub fn is_valid(token: &str) -> bool {
+    let configured_token = configured_token();
+    if configured_token.is_empty() || configured_token.len() != token.len() {
+        return false;
+    }
+    configured_token.bytes().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,58 @@
+#![allow(dead_code, unused_imports, unused_variables)]
+//! Compare the linear `is_pattern_match` scan with `PatternIndex` at 10, 100 and 1000 templates.
+//! Run with `cargo bench -p server --bench pattern_match`.
+#[macro_use]
+extern crate log;
+
+use criterion::{BenchmarkId, black_box, Criterion, criterion_group, criterion_main};
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+#[path = "../src/utils/sys_sql.rs"]
+mod sys_sql;
+#[path = "../src/utils/sys_fingerprint.rs"]
+mod sys_fingerprint;
+#[path = "../src/utils/sys_pattern_index.rs"]
+mod sys_pattern_index;
+
+use sys_pattern_index::PatternIndex;
+
+const SHAPES: [&str; 4] = [
+    "SELECT * FROM ARTICLE_{} WHERE ID = ?",
+    "SELECT A.ID,A.TITLE FROM ARTICLE_{} A WHERE A.TENANT_ID = ? AND A.STATUS = ? ORDER BY A.PUBLISH_TIME DESC LIMIT ?,?",
+    "SELECT COUNT(1) FROM ARTICLE_{} WHERE CHANNEL_ID = ? AND TENANT_ID = ?",
+    "SELECT A.*, B.CONTENT FROM ARTICLE_{} A LEFT JOIN CONTENT B ON A.CONTENT_ID = B.ID WHERE A.ID IN (?,?,?)",
+];
+
+fn templates(count: usize) -> Vec<Vec<Token>> {
+    let dialect = MySqlDialect {};
+    (0..count)
+        .map(|i| SHAPES[i % SHAPES.len()].replace("{}", &i.to_string()))
+        .map(|pattern| sys_sql::template_tokens(&dialect, &pattern))
+        .collect()
+}
+
+fn bench_pattern_match(c: &mut Criterion) {
+    let dialect = MySqlDialect {};
+    let mut group = c.benchmark_group("pattern_match");
+    for count in [10, 100, 1000] {
+        let patterns = templates(count);
+        let index = PatternIndex::new(patterns.iter().cloned().enumerate().map(|(i, tokens)| (tokens, i)).collect());
+        //最后一个模板,线性扫描的最坏情况
+        let last = count - 1;
+        let sql = SHAPES[last % SHAPES.len()]
+            .replace("{}", &last.to_string())
+            .replace('?', "1");
+
+        group.bench_with_input(BenchmarkId::new("linear", count), &sql, |b, sql| {
+            b.iter(|| patterns.iter().position(|tokens| sys_sql::is_pattern_match(tokens, black_box(sql), &dialect)))
+        });
+        group.bench_with_input(BenchmarkId::new("index", count), &sql, |b, sql| {
+            b.iter(|| index.find_sql(black_box(sql), &dialect).copied())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pattern_match);
+criterion_main!(benches);
@@ -13,12 +13,22 @@ use tokio::sync::mpsc;
 
 use crate::server::start;
 use crate::sys_assistant_client::{enable_cache_task_handle_job, enable_metric_writing_job};
+use crate::sys_audit_log::enable_audit_log_job;
 
 mod meta;
+mod meta_file;
 mod sys_assistant_client;
 mod server;
 mod sys_config;
 mod sys_log;
+mod sys_audit_log;
+mod sys_metric;
+mod sys_cache_warm;
+mod sys_cache_purge;
+mod sys_cache_preview;
+mod sys_reload;
+mod sys_session;
+mod sys_shutdown;
 mod utils;
 mod math;
 mod protocol;
@@ -33,6 +43,12 @@ mod serve;
 struct CliArgs {
     #[clap(short, long, default_value = "./config.toml")]
     config_file: String,
+    /// Override a config field after the file and VIRTDB_* environment variables, e.g. --set server.port=3308
+    #[clap(short, long = "set", value_name = "KEY=VALUE", multiple_occurrences = true)]
+    set: Vec<String>,
+    /// Validate the config and exit, the exit code is non-zero when it is invalid
+    #[clap(long)]
+    check_config: bool,
 }
 
 #[tokio::main]
@@ -40,21 +56,65 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let cli_args = CliArgs::parse();
     sys_log::init_logger()?;
 
-    let sys_config_wrapper = sys_config::parse_config(&cli_args.config_file);
-    if let Err(err) = sys_config_wrapper {
-        error!("Read config file fail:{:?}", err);
-        return Err(Box::try_from(err).unwrap());
+    let sys_config_wrapper = sys_config::parse_config(&cli_args.config_file, &cli_args.set)
+        .and_then(|sys_config| sys_config::validate(&sys_config).map(|_| sys_config));
+    if cli_args.check_config {
+        match sys_config_wrapper {
+            Ok(_) => {
+                println!("config {} is valid", cli_args.config_file);
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("invalid config:\n{}", err);
+                std::process::exit(1);
+            }
+        }
     }
-    let sys_config = sys_config_wrapper.unwrap();
+    let sys_config = match sys_config_wrapper {
+        Ok(sys_config) => sys_config,
+        Err(err) => {
+            error!("Invalid config:\n{}", err);
+            return Err(err.into());
+        }
+    };
+    if let Err(err) = sys_log::apply_log_config(&sys_config) {
+        error!("Apply log config fail:{:?}", err);
+    }
+    sys_config::set_current_config(sys_config.clone());
+    sys_reload::enable_config_reload_job(cli_args.config_file.clone(), cli_args.set.clone());
     let virt_db_config = sys_config.clone();
+    utils::sys_sql::set_lower_case_table_names(sys_config.mysql.lower_case_table_names);
 
     let (exec_log_channel_sender, exec_log_channel_receiver) = mpsc::channel(10*100_000);
     let (cache_load_task_channel_sender, cache_load_task_channel_receiver) = mpsc::channel(10*100_000);
+    let (audit_log_channel_sender, audit_log_channel_receiver) = mpsc::channel(10*100_000);
 
-    enable_metric_writing_job(sys_config.clone(), exec_log_channel_receiver);
+    //停止服务时等待这些任务写完通道中剩余的数据
+    let mut flush_jobs = Vec::new();
+    flush_jobs.push(enable_metric_writing_job(exec_log_channel_receiver));
     meta::enable_meta_refresh_job(sys_config.clone());
-    enable_cache_task_handle_job(sys_config.clone(),cache_load_task_channel_receiver);
+    flush_jobs.push(enable_cache_task_handle_job(sys_config.clone(),cache_load_task_channel_receiver));
+    flush_jobs.extend(enable_audit_log_job(sys_config.clone(), audit_log_channel_receiver));
+    sys_metric::enable_metric_expose_job(sys_config.clone());
+    sys_cache_warm::enable_cache_warm_on_start(sys_config.clone());
+
+    let server = start(virt_db_config, exec_log_channel_sender,cache_load_task_channel_sender, audit_log_channel_sender);
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => {
+            result.unwrap();
+            return Ok(());
+        }
+        _ = sys_shutdown::wait_for_signal() => {}
+    }
 
-    start(virt_db_config, exec_log_channel_sender,cache_load_task_channel_sender).await.unwrap();
+    //停止接受新连接,等待执行中的语句结束后关闭连接,再写完剩余的指标,缓存和审计日志
+    sys_shutdown::begin_drain();
+    server.await.unwrap();
+    let sys_config = sys_config::current_config();
+    sys_shutdown::drain(&sys_config.shutdown).await;
+    sys_shutdown::flush(&sys_config.shutdown, flush_jobs).await;
+    sys_assistant_client::deregister(&sys_config).await;
+    info!("shutdown complete");
     Ok(())
 }
@@ -1,32 +1,67 @@
-use std::{thread};
+use std::{env, fs, thread};
+use std::sync::Arc;
 use std::time::Duration;
-use mysql::{Pool};
+use mysql::{OptsBuilder, Pool, PooledConn};
 use mysql::prelude::*;
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
 use sqlparser::dialect::MySqlDialect;
+use serde::{Deserialize, Serialize};
 use sqlparser::tokenizer::{Token, Tokenizer};
 
-use crate::sys_config::VirtDBConfig;
+use crate::sys_assistant_client::{self, DataWrapper};
+use crate::sys_config::{MetaSource, VirtDBConfig};
+use crate::{meta_file, sys_config, utils};
+use crate::serve::cache_admission;
+use crate::utils::sys_pattern_index::PatternIndex;
 
-static mut CACHE_CONFIG_ENTITY_LIST: Vec<CacheConfigEntity> = vec![];
+//当前生效的规则,刷新时整体替换,请求开始时取一次,处理过程中看到的始终是同一个版本
+static CONFIG_SNAPSHOT: Lazy<ArcSwap<ConfigSnapshot>> = Lazy::new(|| ArcSwap::from_pointee(ConfigSnapshot::default()));
 
-pub fn get_cache_config_entity_list() -> &'static Vec<CacheConfigEntity> {
-    unsafe { &CACHE_CONFIG_ENTITY_LIST }
+/**
+ * 一个版本的全部规则,创建后不再修改
+ */
+pub struct ConfigSnapshot {
+    //MetaSnapshot的版本,没有加载过规则时为空
+    pub version: String,
+    pub cache_config_index: PatternIndex<CacheConfigEntity>,
+    pub rate_limit_configs: Vec<RateLimitConfigEntity>,
+    pub firewall_rules: Vec<FirewallRuleEntity>,
+    pub query_rewrites: Vec<QueryRewriteEntity>,
 }
 
-fn set_cache_config_entity_list(entity_list: Vec<CacheConfigEntity>) {
-    unsafe {
-        CACHE_CONFIG_ENTITY_LIST.clear();
-        for entity in entity_list {
-            CACHE_CONFIG_ENTITY_LIST.push(entity);
+impl Default for ConfigSnapshot {
+    fn default() -> Self {
+        ConfigSnapshot {
+            version: String::new(),
+            cache_config_index: PatternIndex::new(vec![]),
+            rate_limit_configs: vec![],
+            firewall_rules: vec![],
+            query_rewrites: vec![],
         }
     }
 }
 
-#[derive(Debug)]
+/// The rules currently in effect
+pub fn current_snapshot() -> Arc<ConfigSnapshot> {
+    CONFIG_SNAPSHOT.load_full()
+}
+
+fn publish_snapshot(config_snapshot: ConfigSnapshot) {
+    CONFIG_SNAPSHOT.store(Arc::new(config_snapshot));
+}
+
+#[derive(Debug, Clone)]
 pub struct CacheConfigEntity {
     pub id: i32,
     pub sql_template: String,
     pub duration: i32,
+    //语句超时时间,0表示使用用户或全局配置
+    pub timeout_ms: i32,
+    //ALWAYS/ADAPTIVE
+    pub admission_mode: String,
+    //可缓存的最大结果,0表示使用全局配置
+    pub max_result_bytes: i32,
     pub cache_name: String,
     pub remark: String,
     pub enabled: i32,
@@ -37,63 +72,297 @@ pub struct CacheConfigEntity {
     pub cached_sql_parser_token: Vec<Token>,
 }
 
+/**
+ * 限流配置
+ * limit_type: USER/IP/PATTERN, limit_key为用户名/客户端IP/SQL模板,`*`表示对每个用户(IP)分别限流
+ */
+#[derive(Debug)]
+pub struct RateLimitConfigEntity {
+    pub id: i32,
+    pub limit_type: String,
+    pub limit_key: String,
+    //每秒最多执行次数,0表示不限制
+    pub max_qps: i32,
+    //最大并发执行数,0表示不限制
+    pub max_concurrency: i32,
+    //超限时排队等待的最长时间,0表示直接拒绝
+    pub queue_timeout_ms: i32,
+    pub cached_sql_parser_token: Vec<Token>,
+}
+
+/**
+ * 防火墙规则
+ * rule_type: DENY_NO_WHERE/DENY_DDL/DENY_PATTERN/ALLOW_PATTERN, sql_template仅对PATTERN类型有效
+ */
+#[derive(Debug)]
+pub struct FirewallRuleEntity {
+    pub id: i32,
+    pub rule_type: String,
+    pub sql_template: String,
+    //生效的用户,为空表示所有用户
+    pub users: Vec<String>,
+    //只记录日志,不拦截
+    pub dry_run: bool,
+    pub cached_sql_parser_token: Vec<Token>,
+}
+
+/**
+ * SQL改写规则
+ * 匹配sql_template的语句改写为replacement,replacement中的`?`按顺序替换为模板占位符对应的值,`$n`替换为第n个值
+ */
+#[derive(Debug)]
+pub struct QueryRewriteEntity {
+    pub id: i32,
+    pub sql_template: String,
+    pub replacement: String,
+    pub cached_sql_parser_token: Vec<Token>,
+}
+
+fn to_tokens(dialect: &MySqlDialect, sql_pattern: &str) -> Vec<Token> {
+    utils::sys_sql::template_tokens(dialect, sql_pattern)
+}
+
+/**
+ * 一个版本的全部规则,从meta_db读取或由admin推送,推送的版本会保存到本地文件
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MetaSnapshot {
+    pub version: String,
+    pub cache_configs: Vec<CacheConfigRow>,
+    pub rate_limit_configs: Vec<RateLimitConfigRow>,
+    pub firewall_rules: Vec<FirewallRuleRow>,
+    pub query_rewrites: Vec<QueryRewriteRow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CacheConfigRow {
+    pub id: i32,
+    pub sql_template: String,
+    pub duration: i32,
+    #[serde(default)]
+    pub timeout_ms: i32,
+    #[serde(default = "default_admission_mode")]
+    pub admission_mode: String,
+    #[serde(default)]
+    pub max_result_bytes: i32,
+}
+
+fn default_admission_mode() -> String {
+    String::from(cache_admission::ADMISSION_ALWAYS)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RateLimitConfigRow {
+    pub id: i32,
+    pub limit_type: String,
+    pub limit_key: String,
+    #[serde(default)]
+    pub max_qps: i32,
+    #[serde(default)]
+    pub max_concurrency: i32,
+    #[serde(default)]
+    pub queue_timeout_ms: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FirewallRuleRow {
+    pub id: i32,
+    pub rule_type: String,
+    #[serde(default)]
+    pub sql_template: String,
+    //逗号分隔的用户名
+    #[serde(default)]
+    pub users: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueryRewriteRow {
+    pub id: i32,
+    pub sql_template: String,
+    pub replacement: String,
+}
+
+impl MetaSnapshot {
+    //规则内容的hash,meta_db和file模式下作为版本号
+    pub fn content_version(&self) -> String {
+        let content = serde_json::to_string(&(&self.cache_configs, &self.rate_limit_configs, &self.firewall_rules, &self.query_rewrites)).unwrap_or_default();
+        format!("{:016x}", utils::sys_fingerprint::fnv1a(content.as_bytes()))
+    }
+}
+
+fn load_snapshot_from_db(conn: &mut PooledConn) -> mysql::Result<MetaSnapshot> {
+    let cache_configs = "select id,sql_template,duration,timeout_ms,admission_mode,max_result_bytes from cache_config where enabled = true"
+        .with(())
+        .map(&mut *conn, |(id, sql_template, duration, timeout_ms, admission_mode, max_result_bytes)| {
+            CacheConfigRow { id, sql_template, duration, timeout_ms, admission_mode, max_result_bytes }
+        })?;
+    let rate_limit_configs = "select id,limit_type,limit_key,max_qps,max_concurrency,queue_timeout_ms from rate_limit_config where enabled = true"
+        .with(())
+        .map(&mut *conn, |(id, limit_type, limit_key, max_qps, max_concurrency, queue_timeout_ms)| {
+            RateLimitConfigRow { id, limit_type, limit_key, max_qps, max_concurrency, queue_timeout_ms }
+        })?;
+    let firewall_rules = "select id,rule_type,sql_template,users,dry_run from firewall_rule where enabled = true"
+        .with(())
+        .map(&mut *conn, |(id, rule_type, sql_template, users, dry_run)| {
+            let sql_template: Option<String> = sql_template;
+            let users: Option<String> = users;
+            FirewallRuleRow {
+                id,
+                rule_type,
+                sql_template: sql_template.unwrap_or_default(),
+                users: users.unwrap_or_default(),
+                dry_run,
+            }
+        })?;
+    let query_rewrites = "select id,sql_template,replacement from query_rewrite where enabled = true"
+        .with(())
+        .map(&mut *conn, |(id, sql_template, replacement)| {
+            QueryRewriteRow { id, sql_template, replacement }
+        })?;
+    let mut snapshot = MetaSnapshot {
+        version: String::new(),
+        cache_configs,
+        rate_limit_configs,
+        firewall_rules,
+        query_rewrites,
+    };
+    snapshot.version = snapshot.content_version();
+    Ok(snapshot)
+}
+
+/// Build the rules of the snapshot and publish them as the current version
+pub fn apply_snapshot(snapshot: &MetaSnapshot) {
+    publish_snapshot(build_config_snapshot(snapshot));
+    debug!("meta snapshot {} applied", snapshot.version);
+}
+
+fn build_config_snapshot(snapshot: &MetaSnapshot) -> ConfigSnapshot {
+    let dialect = MySqlDialect {};
+    let cache_config_list: Vec<CacheConfigEntity> = snapshot.cache_configs.iter()
+        .map(|row| {
+            let sql_pattern = String::from(row.sql_template.trim());
+            let tokens = to_tokens(&dialect, &*sql_pattern);
+            CacheConfigEntity {
+                id: row.id,
+                sql_template: sql_pattern,
+                duration: row.duration,
+                timeout_ms: row.timeout_ms,
+                admission_mode: row.admission_mode.to_uppercase(),
+                max_result_bytes: row.max_result_bytes,
+                cache_name: "".to_string(),
+                remark: "".to_string(),
+                enabled: -1,
+                created_by: -1,
+                updated_by: -1,
+                cached_sql_parser_token: tokens,
+            }
+        })
+        .collect();
+    let cache_config_index = PatternIndex::new(cache_config_list.into_iter()
+        .map(|entity| (entity.cached_sql_parser_token.clone(), entity))
+        .collect());
+
+    let rate_limit_configs = snapshot.rate_limit_configs.iter()
+        .map(|row| {
+            let limit_type = row.limit_type.to_uppercase();
+            let limit_key = String::from(row.limit_key.trim());
+            let tokens = if limit_type == "PATTERN" {
+                to_tokens(&dialect, &*limit_key)
+            } else {
+                vec![]
+            };
+            RateLimitConfigEntity {
+                id: row.id,
+                limit_type,
+                limit_key,
+                max_qps: row.max_qps,
+                max_concurrency: row.max_concurrency,
+                queue_timeout_ms: row.queue_timeout_ms,
+                cached_sql_parser_token: tokens,
+            }
+        })
+        .collect();
+
+    let firewall_rules = snapshot.firewall_rules.iter()
+        .map(|row| {
+            let sql_template = String::from(row.sql_template.trim());
+            let tokens = to_tokens(&dialect, &*sql_template);
+            FirewallRuleEntity {
+                id: row.id,
+                rule_type: row.rule_type.to_uppercase(),
+                sql_template,
+                users: row.users
+                    .split(',')
+                    .map(|u| String::from(u.trim()))
+                    .filter(|u| !u.is_empty())
+                    .collect(),
+                dry_run: row.dry_run,
+                cached_sql_parser_token: tokens,
+            }
+        })
+        .collect();
+
+    let query_rewrites = snapshot.query_rewrites.iter()
+        .map(|row| {
+            let sql_template = String::from(row.sql_template.trim());
+            let tokens = to_tokens(&dialect, &*sql_template);
+            QueryRewriteEntity {
+                id: row.id,
+                sql_template,
+                replacement: String::from(row.replacement.trim()),
+                cached_sql_parser_token: tokens,
+            }
+        })
+        .collect();
+    ConfigSnapshot {
+        version: snapshot.version.clone(),
+        cache_config_index,
+        rate_limit_configs,
+        firewall_rules,
+        query_rewrites,
+    }
+}
+
 pub fn enable_meta_refresh_job(sys_config: VirtDBConfig) {
+    match sys_config.meta.source {
+        MetaSource::Db => enable_meta_db_refresh_job(sys_config),
+        MetaSource::Admin => enable_meta_subscribe_job(sys_config),
+        MetaSource::File => meta_file::enable_meta_file_watch_job(sys_config),
+    }
+}
+
+fn enable_meta_db_refresh_job(sys_config: VirtDBConfig) {
     let meta_config = sys_config.meta_db.clone();
     thread::spawn(move || {
-        let meta_mysql_username = meta_config.username;
-        let meta_mysql_password = meta_config.password;
-        let meta_mysql_ip = meta_config.ip;
-        let meta_mysql_port = meta_config.port;
-        let meta_mysql_database = meta_config.database;
-        let mysql_url = format!(
-            "mysql://{}:{}@{}:{}/{}",
-            meta_mysql_username,
-            meta_mysql_password,
-            meta_mysql_ip,
-            meta_mysql_port,
-            meta_mysql_database
-        )
-            .clone();
-        let pool = Pool::new(&*mysql_url.clone()).unwrap();
-        let dialect = MySqlDialect {};
+        //不拼接url,密码中有特殊字符时也能连接
+        let opts = OptsBuilder::new()
+            .ip_or_hostname(Some(meta_config.ip))
+            .tcp_port(meta_config.port as u16)
+            .user(Some(meta_config.username))
+            .pass(Some(meta_config.password))
+            .db_name(Some(meta_config.database));
+        let pool = Pool::new(opts).unwrap();
+        let mut version = String::new();
         loop {
             let conn_result = pool.get_conn();
 
             match conn_result {
                 Ok(mut conn) => {
-                    let cache_config_list =
-                        "select id,sql_template,duration from cache_config where enabled = true"
-                            .with(())
-                            .map(&mut conn, |(id, sql_template, duration)| {
-                                let sql_pattern:String = sql_template;
-                                let sql_pattern = String::from(sql_pattern.to_uppercase().trim());
-                                let tokens = Tokenizer::new(&dialect, &*sql_pattern)
-                                    .tokenize()
-                                    .unwrap_or_default()
-                                    .into_iter()
-                                    .filter(|t| {
-                                        return match t {
-                                            Token::EOF => false,
-                                            Token::Whitespace(_) => false,
-                                            _ => true,
-                                        };
-                                    })
-                                    .collect();
-                                CacheConfigEntity {
-                                    id,
-                                    sql_template:sql_pattern,
-                                    duration,
-                                    cache_name: "".to_string(),
-                                    remark: "".to_string(),
-                                    enabled: -1,
-                                    created_by: -1,
-                                    updated_by: -1,
-                                    cached_sql_parser_token: tokens,
-                                }
-                            })
-                            .unwrap();
-                    set_cache_config_entity_list(cache_config_list);
-                    debug!("reload cache_config_list finish");
+                    match load_snapshot_from_db(&mut conn) {
+                        Ok(snapshot) => {
+                            //没有变化时不重建索引
+                            if snapshot.version != version {
+                                apply_snapshot(&snapshot);
+                                version = snapshot.version;
+                            }
+                        }
+                        Err(err) => {
+                            warn!("Load meta data fail.err:{:?}", err);
+                        }
+                    }
                 }
                 Err(err) => {
                     warn!("Connect Meta DB fail.err:{:?}",err);
@@ -104,3 +373,133 @@ pub fn enable_meta_refresh_job(sys_config: VirtDBConfig) {
     });
     info!("CacheConfig auto-reload task Running");
 }
+
+#[derive(Debug, Serialize)]
+struct MetaWatchParam {
+    node_id: String,
+    //节点当前的版本,与admin一致时等待变更
+    version: String,
+    timeout_seconds: u64,
+}
+
+//admin不可用时的重试间隔
+const META_RETRY_SECONDS: u64 = 5;
+
+//订阅admin的配置变更,admin不可用时使用本地保存的最后一个版本
+fn enable_meta_subscribe_job(sys_config: VirtDBConfig) {
+    let meta_config = sys_config.meta.clone();
+    let node_id = meta_config.node_id(sys_config.server.port);
+    tokio::spawn(async move {
+        let mut version = String::new();
+        match read_snapshot_file(&meta_config.snapshot_file) {
+            Ok(snapshot) => {
+                info!("meta snapshot {} loaded from {}", snapshot.version, meta_config.snapshot_file);
+                apply_snapshot(&snapshot);
+                version = snapshot.version;
+            }
+            Err(err) => info!("no local meta snapshot in {}:{}", meta_config.snapshot_file, err),
+        }
+        info!("subscribe meta from {} as {}", sys_config.admin.address, node_id);
+        loop {
+            //admin地址可以重新加载
+            let param = MetaWatchParam { node_id: node_id.clone(), version: version.clone(), timeout_seconds: meta_config.watch_timeout_seconds };
+            let result = sys_assistant_client::admin_post(&sys_config::current_config().admin, "/vt_node/config_watch")
+                .json(&param)
+                .timeout(Duration::from_secs(meta_config.watch_timeout_seconds + 30))
+                .send().await;
+            let data_wrapper = match result {
+                Ok(response) => response.json::<DataWrapper<MetaSnapshot>>().await,
+                Err(err) => Err(err),
+            };
+            match data_wrapper {
+                Ok(DataWrapper { data: Some(snapshot), success: true, .. }) => {
+                    if snapshot.version != version {
+                        info!("meta snapshot {} received", snapshot.version);
+                        apply_snapshot(&snapshot);
+                        if let Err(err) = write_snapshot_file(&meta_config.snapshot_file, &snapshot) {
+                            warn!("save meta snapshot to {} fail.err:{}", meta_config.snapshot_file, err);
+                        }
+                        version = snapshot.version;
+                    }
+                }
+                Ok(data_wrapper) => {
+                    warn!("watch meta fail. response:{:?}", data_wrapper.message);
+                    tokio::time::sleep(Duration::from_secs(META_RETRY_SECONDS)).await;
+                }
+                Err(err) => {
+                    warn!("watch meta fail.err:{:?}", err);
+                    tokio::time::sleep(Duration::from_secs(META_RETRY_SECONDS)).await;
+                }
+            }
+        }
+    });
+}
+
+pub fn read_snapshot_file(path: &str) -> Result<MetaSnapshot, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str::<MetaSnapshot>(&content).map_err(|e| e.to_string())
+}
+
+//先写临时文件再改名,避免进程退出时留下不完整的文件
+pub fn write_snapshot_file(path: &str, snapshot: &MetaSnapshot) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(snapshot).map_err(|e| e.to_string())?;
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, content).map_err(|e| e.to_string())?;
+    fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_snapshot_file() {
+    let path = env::temp_dir().join(format!("virtdb_meta_snapshot_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut snapshot = MetaSnapshot::default();
+    snapshot.cache_configs.push(CacheConfigRow {
+        id: 1,
+        sql_template: String::from("select * from users where id = ?"),
+        duration: 60,
+        timeout_ms: 0,
+        admission_mode: String::from("ALWAYS"),
+        max_result_bytes: 0,
+    });
+    snapshot.version = snapshot.content_version();
+    write_snapshot_file(path, &snapshot).unwrap();
+    assert_eq!(snapshot, read_snapshot_file(path).unwrap());
+    let _ = fs::remove_file(path);
+
+    let mut changed = snapshot.clone();
+    changed.cache_configs[0].duration = 30;
+    assert_ne!(snapshot.version, changed.content_version());
+}
+
+#[test]
+fn test_apply_snapshot() {
+    let mut snapshot = MetaSnapshot::default();
+    snapshot.cache_configs.push(CacheConfigRow {
+        id: 1,
+        sql_template: String::from("select * from users where id = ?"),
+        duration: 60,
+        timeout_ms: 0,
+        admission_mode: String::from("adaptive"),
+        max_result_bytes: 0,
+    });
+    snapshot.firewall_rules.push(FirewallRuleRow {
+        id: 2,
+        rule_type: String::from("deny_ddl"),
+        sql_template: String::new(),
+        users: String::from("a, b,"),
+        dry_run: false,
+    });
+    snapshot.version = snapshot.content_version();
+    //不修改全局的CONFIG_SNAPSHOT,避免影响并行执行的其他测试
+    let config_snapshot: ArcSwap<ConfigSnapshot> = ArcSwap::from_pointee(build_config_snapshot(&snapshot));
+    let current = config_snapshot.load_full();
+    assert_eq!(snapshot.version, current.version);
+    assert_eq!(1, current.cache_config_index.len());
+    assert_eq!(vec![String::from("a"), String::from("b")], current.firewall_rules[0].users);
+    assert_eq!("DENY_DDL", current.firewall_rules[0].rule_type);
+
+    //已取得的版本不受后续刷新影响
+    config_snapshot.store(Arc::new(build_config_snapshot(&MetaSnapshot::default())));
+    assert_eq!(snapshot.version, current.version);
+    assert!(config_snapshot.load_full().cache_config_index.is_empty());
+}
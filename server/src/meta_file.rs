@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::tokenizer::Tokenizer;
+
+use crate::meta;
+use crate::meta::MetaSnapshot;
+use crate::serve::{cache_admission, firewall, rate_limiter};
+use crate::sys_config::VirtDBConfig;
+use crate::utils::sys_fingerprint;
+
+/// Parse a rules file, `.yaml`/`.yml` files are YAML and anything else is TOML.
+/// The version is always computed from the content.
+pub fn parse_rules(path: &str, content: &str) -> Result<MetaSnapshot, String> {
+    let lower_path = path.to_lowercase();
+    let mut snapshot: MetaSnapshot = if lower_path.ends_with(".yaml") || lower_path.ends_with(".yml") {
+        serde_yaml::from_str(content).map_err(|e| e.to_string())?
+    } else {
+        toml::from_str(content).map_err(|e| e.to_string())?
+    };
+    snapshot.version = snapshot.content_version();
+    Ok(snapshot)
+}
+
+/// Check the rules the same way the admin does before saving them
+pub fn validate(snapshot: &MetaSnapshot) -> Result<(), String> {
+    let dialect = MySqlDialect {};
+    let mut ids = HashSet::new();
+    for row in snapshot.cache_configs.iter() {
+        if !ids.insert(row.id) {
+            return Err(format!("duplicate cache_config id {}", row.id));
+        }
+        check_template(&dialect, "cache_config", row.id, &row.sql_template)?;
+        if row.duration <= 0 {
+            return Err(format!("cache_config {}: duration must be positive", row.id));
+        }
+        let admission_mode = row.admission_mode.to_uppercase();
+        if admission_mode != cache_admission::ADMISSION_ALWAYS && admission_mode != cache_admission::ADMISSION_ADAPTIVE {
+            return Err(format!("cache_config {}: unknown admission_mode {:?}", row.id, row.admission_mode));
+        }
+    }
+
+    let mut ids = HashSet::new();
+    for row in snapshot.rate_limit_configs.iter() {
+        if !ids.insert(row.id) {
+            return Err(format!("duplicate rate_limit_config id {}", row.id));
+        }
+        match row.limit_type.to_uppercase().as_str() {
+            rate_limiter::LIMIT_TYPE_PATTERN => check_template(&dialect, "rate_limit_config", row.id, &row.limit_key)?,
+            rate_limiter::LIMIT_TYPE_USER | rate_limiter::LIMIT_TYPE_IP if !row.limit_key.trim().is_empty() => {}
+            rate_limiter::LIMIT_TYPE_USER | rate_limiter::LIMIT_TYPE_IP => {
+                return Err(format!("rate_limit_config {}: limit_key is empty", row.id));
+            }
+            _ => return Err(format!("rate_limit_config {}: unknown limit_type {:?}", row.id, row.limit_type)),
+        }
+    }
+
+    let mut ids = HashSet::new();
+    for row in snapshot.firewall_rules.iter() {
+        if !ids.insert(row.id) {
+            return Err(format!("duplicate firewall_rule id {}", row.id));
+        }
+        match row.rule_type.to_uppercase().as_str() {
+            firewall::RULE_TYPE_DENY_PATTERN | firewall::RULE_TYPE_ALLOW_PATTERN => {
+                check_template(&dialect, "firewall_rule", row.id, &row.sql_template)?
+            }
+            firewall::RULE_TYPE_DENY_NO_WHERE | firewall::RULE_TYPE_DENY_DDL => {}
+            _ => return Err(format!("firewall_rule {}: unknown rule_type {:?}", row.id, row.rule_type)),
+        }
+    }
+
+    let mut ids = HashSet::new();
+    for row in snapshot.query_rewrites.iter() {
+        if !ids.insert(row.id) {
+            return Err(format!("duplicate query_rewrite id {}", row.id));
+        }
+        check_template(&dialect, "query_rewrite", row.id, &row.sql_template)?;
+        if row.replacement.trim().is_empty() {
+            return Err(format!("query_rewrite {}: replacement is empty", row.id));
+        }
+    }
+    Ok(())
+}
+
+fn check_template(dialect: &MySqlDialect, kind: &str, id: i32, template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err(format!("{} {}: sql template is empty", kind, id));
+    }
+    Tokenizer::new(dialect, template)
+        .tokenize()
+        .map(|_| ())
+        .map_err(|e| format!("{} {}: invalid sql template:{:?}", kind, id, e))
+}
+
+fn load_rules(path: &str, content: &str) -> Result<MetaSnapshot, String> {
+    let snapshot = parse_rules(path, content)?;
+    validate(&snapshot)?;
+    Ok(snapshot)
+}
+
+//定时检查规则文件,无效的内容不生效,继续使用上一个有效版本
+pub fn enable_meta_file_watch_job(sys_config: VirtDBConfig) {
+    let meta_config = sys_config.meta.clone();
+    thread::spawn(move || {
+        let mut version = String::new();
+        //上次检查时文件内容的hash,内容不变时不重复解析,也不重复打印错误
+        let mut last_checked: Option<Option<u64>> = None;
+        loop {
+            let content = fs::read_to_string(&meta_config.rules_file);
+            let content_hash = content.as_ref().ok().map(|content| sys_fingerprint::fnv1a(content.as_bytes()));
+            if last_checked != Some(content_hash) {
+                last_checked = Some(content_hash);
+                let result = content
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| load_rules(&meta_config.rules_file, &content));
+                match result {
+                    Ok(snapshot) if snapshot.version == version => {}
+                    Ok(snapshot) => {
+                        info!("meta rules {} loaded from {}", snapshot.version, meta_config.rules_file);
+                        meta::apply_snapshot(&snapshot);
+                        if let Err(err) = meta::write_snapshot_file(&meta_config.snapshot_file, &snapshot) {
+                            warn!("save meta snapshot to {} fail.err:{}", meta_config.snapshot_file, err);
+                        }
+                        version = snapshot.version;
+                    }
+                    Err(err) => {
+                        warn!("invalid rules file {}, keep version {:?}.err:{}", meta_config.rules_file, version, err);
+                        //启动时文件不可用,使用最后一个有效版本
+                        if version.is_empty() {
+                            if let Ok(snapshot) = meta::read_snapshot_file(&meta_config.snapshot_file) {
+                                info!("meta snapshot {} loaded from {}", snapshot.version, meta_config.snapshot_file);
+                                meta::apply_snapshot(&snapshot);
+                                version = snapshot.version;
+                            }
+                        }
+                    }
+                }
+            }
+            thread::sleep(Duration::from_secs(meta_config.file_check_seconds.max(1)));
+        }
+    });
+    info!("meta rules file watch task Running");
+}
+
+#[test]
+fn test_parse_rules() {
+    let toml_content = r#"
+[[cache_configs]]
+id = 1
+sql_template = "select * from users where id = ?"
+duration = 60
+
+[[firewall_rules]]
+id = 2
+rule_type = "DENY_DDL"
+"#;
+    let snapshot = parse_rules("rules.toml", toml_content).unwrap();
+    assert_eq!(1, snapshot.cache_configs.len());
+    assert_eq!("ALWAYS", snapshot.cache_configs[0].admission_mode);
+    assert_eq!("", snapshot.firewall_rules[0].users);
+    assert!(validate(&snapshot).is_ok());
+
+    let yaml_content = r#"
+cache_configs:
+  - id: 1
+    sql_template: "select * from users where id = ?"
+    duration: 60
+firewall_rules:
+  - id: 2
+    rule_type: DENY_DDL
+"#;
+    let yaml_snapshot = parse_rules("rules.yml", yaml_content).unwrap();
+    assert_eq!(snapshot, yaml_snapshot);
+
+    //导出的文件可以原样读取,先转换为Value使普通字段排在表之前
+    let exported = toml::to_string(&toml::Value::try_from(&snapshot).unwrap()).unwrap();
+    assert_eq!(snapshot, parse_rules("rules.toml", &exported).unwrap());
+
+    assert!(parse_rules("rules.toml", "[[cache_configs]]\nid = 1").is_err());
+}
+
+#[test]
+fn test_validate() {
+    let mut snapshot = parse_rules("rules.toml", r#"
+[[rate_limit_configs]]
+id = 1
+limit_type = "PATTERN"
+limit_key = "select * from users where id = ?"
+max_qps = 10
+"#).unwrap();
+    assert!(validate(&snapshot).is_ok());
+    snapshot.rate_limit_configs[0].limit_type = String::from("HOST");
+    assert!(validate(&snapshot).is_err());
+    snapshot.rate_limit_configs[0].limit_type = String::from("user");
+    snapshot.rate_limit_configs[0].limit_key = String::new();
+    assert!(validate(&snapshot).is_err());
+
+    let snapshot = parse_rules("rules.toml", r#"
+[[query_rewrites]]
+id = 1
+sql_template = "select * from users"
+replacement = "select * from users limit 100"
+
+[[query_rewrites]]
+id = 1
+sql_template = "select * from orders"
+replacement = "select * from orders limit 100"
+"#).unwrap();
+    assert_eq!(Err(String::from("duplicate query_rewrite id 1")), validate(&snapshot));
+}
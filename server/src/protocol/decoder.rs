@@ -0,0 +1,337 @@
+//! Reads a complete result set response, e.g. a cached one, back into typed columns and rows.
+//! Works with responses sent with or without CLIENT_DEPRECATE_EOF.
+
+use std::convert::TryFrom;
+
+use byteorder::{ByteOrder, LittleEndian};
+use mysql::consts::ColumnType;
+use mysql::Value;
+
+use crate::protocol::result_set::{ColumnDefinition, ResultSet, UNSIGNED_FLAG};
+use crate::protocol::{read_lenenc_int, U24_MAX};
+
+const NULL_VALUE: u8 = 0xfb;
+
+/// Decode the response of a COM_QUERY
+pub fn decode_text(bytes: &[u8]) -> Result<ResultSet, String> {
+    decode(bytes, false)
+}
+
+/// Decode the response of a COM_STMT_EXECUTE
+pub fn decode_binary(bytes: &[u8]) -> Result<ResultSet, String> {
+    decode(bytes, true)
+}
+
+fn decode(bytes: &[u8], binary: bool) -> Result<ResultSet, String> {
+    let payloads = read_payloads(bytes)?;
+    let first = payloads.first().ok_or_else(|| String::from("empty response"))?;
+    match first[0] {
+        0x00 => return Err(String::from("an OK packet, not a result set")),
+        0xff => return Err(error_message(first)),
+        _ => {}
+    }
+    let mut pos = 0;
+    let column_count = read_lenenc_int(first, &mut pos).ok_or_else(|| String::from("invalid column count"))? as usize;
+    if payloads.len() < column_count + 2 {
+        return Err(format!("truncated response, {} packets for {} columns", payloads.len(), column_count));
+    }
+    let columns = payloads[1..=column_count].iter()
+        .map(|payload| read_column_definition(payload))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    //列定义后的EOF包,CLIENT_DEPRECATE_EOF时没有,此时紧跟的0xfe包是最后一个包
+    let mut index = column_count + 1;
+    let deprecate_eof = !(is_eof(&payloads[index]) && index + 1 < payloads.len());
+    if !deprecate_eof {
+        index += 1;
+    }
+
+    let mut result_set = ResultSet::with_columns(columns);
+    for payload in payloads[index..].iter() {
+        if payload[0] == 0xff {
+            return Err(error_message(payload));
+        }
+        if payload[0] == 0xfe && (deprecate_eof || payload.len() < 9) {
+            let (status_flags, warnings) = read_terminator(payload, deprecate_eof)?;
+            result_set.status_flags = status_flags;
+            result_set.warnings = warnings;
+            //多结果集只读取第一个
+            return Ok(result_set);
+        }
+        let row = if binary {
+            read_binary_row(&result_set.columns, payload)?
+        } else {
+            read_text_row(&result_set.columns, payload)?
+        };
+        result_set.rows.push(row);
+    }
+    Err(String::from("truncated response, no terminator after the rows"))
+}
+
+fn is_eof(payload: &[u8]) -> bool {
+    payload[0] == 0xfe && payload.len() < 9
+}
+
+//去掉包头,合并超过16M被拆分的包
+fn read_payloads(bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut payloads: Vec<Vec<u8>> = Vec::new();
+    let mut pos = 0;
+    let mut continued = false;
+    while pos < bytes.len() {
+        if pos + 4 > bytes.len() {
+            return Err(format!("truncated packet header at {}", pos));
+        }
+        let len = LittleEndian::read_u24(&bytes[pos..pos + 3]) as usize;
+        let payload = bytes.get(pos + 4..pos + 4 + len).ok_or_else(|| format!("truncated packet at {}", pos))?;
+        if continued {
+            payloads.last_mut().unwrap().extend_from_slice(payload);
+        } else {
+            payloads.push(payload.to_vec());
+        }
+        continued = len == U24_MAX;
+        pos += 4 + len;
+    }
+    if payloads.iter().any(|payload| payload.is_empty()) {
+        return Err(String::from("empty packet"));
+    }
+    Ok(payloads)
+}
+
+fn error_message(payload: &[u8]) -> String {
+    if payload.len() < 3 {
+        return String::from("error packet");
+    }
+    let code = LittleEndian::read_u16(&payload[1..3]);
+    //跳过#和SQLSTATE
+    let message = if payload.get(3) == Some(&b'#') { payload.get(9..).unwrap_or_default() } else { &payload[3..] };
+    format!("error {}: {}", code, String::from_utf8_lossy(message))
+}
+
+fn read_terminator(payload: &[u8], deprecate_eof: bool) -> Result<(u16, u16), String> {
+    if !deprecate_eof {
+        let warnings = LittleEndian::read_u16(payload.get(1..3).ok_or("truncated EOF packet")?);
+        let status_flags = LittleEndian::read_u16(payload.get(3..5).ok_or("truncated EOF packet")?);
+        return Ok((status_flags, warnings));
+    }
+    let mut pos = 1;
+    read_lenenc_int(payload, &mut pos).ok_or("invalid affected rows")?;
+    read_lenenc_int(payload, &mut pos).ok_or("invalid last insert id")?;
+    let status_flags = LittleEndian::read_u16(payload.get(pos..pos + 2).ok_or("truncated OK packet")?);
+    let warnings = LittleEndian::read_u16(payload.get(pos + 2..pos + 4).ok_or("truncated OK packet")?);
+    Ok((status_flags, warnings))
+}
+
+fn read_lenenc_str<'a>(payload: &'a [u8], pos: &mut usize) -> Result<&'a [u8], String> {
+    let len = read_lenenc_int(payload, pos).ok_or_else(|| format!("invalid length at {}", pos))? as usize;
+    let value = payload.get(*pos..*pos + len).ok_or_else(|| format!("truncated value at {}", pos))?;
+    *pos += len;
+    Ok(value)
+}
+
+fn read_column_definition(payload: &[u8]) -> Result<ColumnDefinition, String> {
+    let mut pos = 0;
+    let mut next_str = || read_lenenc_str(payload, &mut pos).map(|v| String::from_utf8_lossy(v).to_string());
+    let _catalog = next_str()?;
+    let schema = next_str()?;
+    let table = next_str()?;
+    let org_table = next_str()?;
+    let name = next_str()?;
+    let org_name = next_str()?;
+    // length of the fixed fields, always 0x0c
+    let fixed = payload.get(pos + 1..pos + 13).ok_or("truncated column definition")?;
+    let column_type = ColumnType::try_from(fixed[6]).map_err(|_| format!("unknown column type {}", fixed[6]))?;
+    Ok(ColumnDefinition {
+        schema,
+        table,
+        org_table,
+        name,
+        org_name,
+        character_set: LittleEndian::read_u16(&fixed[0..2]),
+        column_length: LittleEndian::read_u32(&fixed[2..6]),
+        column_type,
+        flags: LittleEndian::read_u16(&fixed[7..9]),
+        decimals: fixed[9],
+    })
+}
+
+fn read_text_row(columns: &[ColumnDefinition], payload: &[u8]) -> Result<Vec<Value>, String> {
+    let mut pos = 0;
+    let mut row = Vec::with_capacity(columns.len());
+    for column in columns.iter() {
+        if payload.get(pos) == Some(&NULL_VALUE) {
+            pos += 1;
+            row.push(Value::NULL);
+            continue;
+        }
+        let text = read_lenenc_str(payload, &mut pos)?;
+        row.push(typed_value(column, text));
+    }
+    Ok(row)
+}
+
+//文本协议的值按列类型转换,无法转换的保留原始字节
+fn typed_value(column: &ColumnDefinition, text: &[u8]) -> Value {
+    let bytes = || Value::Bytes(text.to_vec());
+    let text_str = match std::str::from_utf8(text) {
+        Ok(v) => v,
+        Err(_) => return bytes(),
+    };
+    let value = match column.column_type {
+        ColumnType::MYSQL_TYPE_TINY | ColumnType::MYSQL_TYPE_SHORT | ColumnType::MYSQL_TYPE_INT24
+        | ColumnType::MYSQL_TYPE_LONG | ColumnType::MYSQL_TYPE_LONGLONG | ColumnType::MYSQL_TYPE_YEAR => {
+            if column.flags & UNSIGNED_FLAG != 0 {
+                text_str.parse::<u64>().ok().map(Value::UInt)
+            } else {
+                text_str.parse::<i64>().ok().map(Value::Int)
+            }
+        }
+        ColumnType::MYSQL_TYPE_FLOAT => text_str.parse::<f32>().ok().map(Value::Float),
+        ColumnType::MYSQL_TYPE_DOUBLE => text_str.parse::<f64>().ok().map(Value::Double),
+        ColumnType::MYSQL_TYPE_DATE | ColumnType::MYSQL_TYPE_DATETIME | ColumnType::MYSQL_TYPE_TIMESTAMP => parse_date(text_str),
+        ColumnType::MYSQL_TYPE_TIME => parse_time(text_str),
+        _ => None,
+    };
+    value.unwrap_or_else(bytes)
+}
+
+// HH:MM:SS[.ffffff],小时可以超过24
+fn parse_hms(text: &str) -> Option<(u32, u8, u8, u32)> {
+    let (hms, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let mut parts = hms.splitn(3, ':');
+    let hours = parts.next()?.parse().ok()?;
+    let minutes = parts.next()?.parse().ok()?;
+    let seconds = parts.next()?.parse().ok()?;
+    let micros = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<6}", fraction).get(..6)?.parse().ok()?
+    };
+    Some((hours, minutes, seconds, micros))
+}
+
+fn parse_date(text: &str) -> Option<Value> {
+    let (date, time) = text.split_once(' ').unwrap_or((text, ""));
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let (hours, minutes, seconds, micros) = if time.is_empty() { (0, 0, 0, 0) } else { parse_hms(time)? };
+    Some(Value::Date(year, month, day, u8::try_from(hours).ok()?, minutes, seconds, micros))
+}
+
+fn parse_time(text: &str) -> Option<Value> {
+    let (negative, hms) = match text.strip_prefix('-') {
+        Some(hms) => (true, hms),
+        None => (false, text),
+    };
+    let (hours, minutes, seconds, micros) = parse_hms(hms)?;
+    Some(Value::Time(negative, hours / 24, (hours % 24) as u8, minutes, seconds, micros))
+}
+
+fn read_binary_row(columns: &[ColumnDefinition], payload: &[u8]) -> Result<Vec<Value>, String> {
+    //包头0x00和NULL位图,位图的前2位保留
+    let bitmap_len = (columns.len() + 7 + 2) / 8;
+    let bitmap = payload.get(1..1 + bitmap_len).ok_or("truncated binary row")?;
+    let mut pos = 1 + bitmap_len;
+    let mut row = Vec::with_capacity(columns.len());
+    for (index, column) in columns.iter().enumerate() {
+        if bitmap[(index + 2) / 8] & (1 << ((index + 2) % 8)) != 0 {
+            row.push(Value::NULL);
+            continue;
+        }
+        let value = read_binary_value(column, payload, &mut pos)
+            .ok_or_else(|| format!("truncated value of column {}", column.name))?;
+        row.push(value);
+    }
+    Ok(row)
+}
+
+fn read_binary_value(column: &ColumnDefinition, payload: &[u8], pos: &mut usize) -> Option<Value> {
+    let unsigned = column.flags & UNSIGNED_FLAG != 0;
+    let mut take = |len: usize| {
+        let value = payload.get(*pos..*pos + len);
+        *pos += len;
+        value
+    };
+    let integer = |v: u64, signed: i64| if unsigned { Value::UInt(v) } else { Value::Int(signed) };
+    let value = match column.column_type {
+        ColumnType::MYSQL_TYPE_TINY => {
+            let v = take(1)?[0];
+            integer(v as u64, v as i8 as i64)
+        }
+        ColumnType::MYSQL_TYPE_SHORT | ColumnType::MYSQL_TYPE_YEAR => {
+            let v = LittleEndian::read_u16(take(2)?);
+            integer(v as u64, v as i16 as i64)
+        }
+        ColumnType::MYSQL_TYPE_INT24 | ColumnType::MYSQL_TYPE_LONG => {
+            let v = LittleEndian::read_u32(take(4)?);
+            integer(v as u64, v as i32 as i64)
+        }
+        ColumnType::MYSQL_TYPE_LONGLONG => {
+            let v = LittleEndian::read_u64(take(8)?);
+            integer(v, v as i64)
+        }
+        ColumnType::MYSQL_TYPE_FLOAT => Value::Float(LittleEndian::read_f32(take(4)?)),
+        ColumnType::MYSQL_TYPE_DOUBLE => Value::Double(LittleEndian::read_f64(take(8)?)),
+        ColumnType::MYSQL_TYPE_DATE | ColumnType::MYSQL_TYPE_DATETIME | ColumnType::MYSQL_TYPE_TIMESTAMP => {
+            let len = take(1)?[0] as usize;
+            let v = take(len)?;
+            match len {
+                0 => Value::Date(0, 0, 0, 0, 0, 0, 0),
+                4 => Value::Date(LittleEndian::read_u16(&v[0..2]), v[2], v[3], 0, 0, 0, 0),
+                7 => Value::Date(LittleEndian::read_u16(&v[0..2]), v[2], v[3], v[4], v[5], v[6], 0),
+                11 => Value::Date(LittleEndian::read_u16(&v[0..2]), v[2], v[3], v[4], v[5], v[6], LittleEndian::read_u32(&v[7..11])),
+                _ => return None,
+            }
+        }
+        ColumnType::MYSQL_TYPE_TIME => {
+            let len = take(1)?[0] as usize;
+            let v = take(len)?;
+            match len {
+                0 => Value::Time(false, 0, 0, 0, 0, 0),
+                8 => Value::Time(v[0] == 1, LittleEndian::read_u32(&v[1..5]), v[5], v[6], v[7], 0),
+                12 => Value::Time(v[0] == 1, LittleEndian::read_u32(&v[1..5]), v[5], v[6], v[7], LittleEndian::read_u32(&v[8..12])),
+                _ => return None,
+            }
+        }
+        _ => Value::Bytes(read_lenenc_str(payload, pos).ok()?.to_vec()),
+    };
+    Some(value)
+}
+
+#[test]
+fn test_decode() {
+    use crate::protocol::result_set::NOT_NULL_FLAG;
+
+    let mut result_set = ResultSet::with_columns(vec![
+        ColumnDefinition::new("id", ColumnType::MYSQL_TYPE_LONGLONG).table("shop", "users").flags(NOT_NULL_FLAG),
+        ColumnDefinition::new("name", ColumnType::MYSQL_TYPE_VAR_STRING),
+        ColumnDefinition::new("score", ColumnType::MYSQL_TYPE_DOUBLE),
+        ColumnDefinition::new("level", ColumnType::MYSQL_TYPE_TINY).flags(UNSIGNED_FLAG),
+        ColumnDefinition::new("balance", ColumnType::MYSQL_TYPE_NEWDECIMAL),
+        ColumnDefinition::new("birthday", ColumnType::MYSQL_TYPE_DATE),
+        ColumnDefinition::new("created_at", ColumnType::MYSQL_TYPE_DATETIME),
+        ColumnDefinition::new("duration", ColumnType::MYSQL_TYPE_TIME),
+    ]).warnings(1);
+    result_set.add_row(vec![Value::Int(-1), Value::from("alice"), Value::Double(9.5), Value::UInt(200), Value::from("12.30"),
+                            Value::Date(1990, 5, 6, 0, 0, 0, 0), Value::Date(2023, 1, 2, 3, 4, 5, 600), Value::Time(false, 1, 2, 3, 4, 500_000)]);
+    result_set.add_row(vec![Value::Int(2), Value::NULL, Value::NULL, Value::UInt(0), Value::NULL,
+                            Value::NULL, Value::Date(2023, 1, 2, 0, 0, 0, 0), Value::Time(true, 0, 0, 0, 1, 0)]);
+    for deprecate_eof in [false, true] {
+        assert_eq!(result_set, decode_text(&result_set.encode_text(deprecate_eof)).unwrap());
+        assert_eq!(result_set, decode_binary(&result_set.encode_binary(deprecate_eof).unwrap()).unwrap());
+        let empty = ResultSet::with_columns(result_set.columns.clone());
+        assert_eq!(empty, decode_text(&empty.encode_text(deprecate_eof)).unwrap());
+    }
+
+    //超过16M的行被拆成多个包
+    let mut large = ResultSet::new(&["content"]);
+    large.add_row(vec![Value::Bytes(vec![b'a'; U24_MAX + 10])]);
+    assert_eq!(large, decode_text(&large.encode_text(false)).unwrap());
+
+    assert_eq!("an OK packet, not a result set", decode_text(&crate::protocol::Packet::ok_packet(1).bytes).unwrap_err());
+    let error = crate::protocol::Packet::error_packet(1146, *b"42S02", String::from("Table 'shop.users' doesn't exist"));
+    assert_eq!("error 1146: Table 'shop.users' doesn't exist", decode_text(&error.bytes).unwrap_err());
+    let bytes = result_set.encode_text(false);
+    assert!(decode_text(&bytes[..bytes.len() - 9]).is_err());
+}
@@ -12,6 +12,31 @@ use chrono::{DateTime, Local};
 
 use crate::sys_assistant_client::ExecLog;
 
+pub mod decoder;
+pub mod response;
+pub mod result_set;
+
+// capability flags, see https://dev.mysql.com/doc/dev/mysql-server/latest/group__group__cs__capabilities__flags.html
+pub const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+pub const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+pub const CLIENT_SSL: u32 = 0x0000_0800;
+pub const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+pub const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 0x0020_0000;
+pub const CLIENT_DEPRECATE_EOF: u32 = 0x0100_0000;
+
+// server status flags
+pub const SERVER_STATUS_IN_TRANS: u16 = 0x0001;
+pub const SERVER_STATUS_AUTOCOMMIT: u16 = 0x0002;
+pub const SERVER_MORE_RESULTS_EXISTS: u16 = 0x0008;
+pub const SERVER_STATUS_CURSOR_EXISTS: u16 = 0x0040;
+
+/// The interesting part of the HandshakeResponse41 a client sends after the server greeting
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakeResponse {
+    pub capability_flags: u32,
+    pub username: String,
+    pub database: Option<String>,
+}
 
 /// A packet is just a wrapper for a Vec<u8>
 #[derive(Debug, PartialEq)]
@@ -51,10 +76,101 @@ impl Packet {
         Packet { bytes: header }
     }
 
+    /// Create an OK packet answering a command, e.g. a statement handled by the proxy itself
+    pub fn ok_packet(affected_rows: u64) -> Self {
+        let payload = ok_payload(0x00, affected_rows, 0, SERVER_STATUS_AUTOCOMMIT, 0);
+        let mut bytes: Vec<u8> = Vec::with_capacity(4 + payload.len());
+        write_packet(&mut bytes, 1, &payload);
+        Packet { bytes }
+    }
+
+    /// Create a command packet, split into several packets if the payload exceeds `U24_MAX`
+    pub fn command(packet_type: PacketType, body: &[u8]) -> Self {
+        let mut payload: Vec<u8> = Vec::with_capacity(1 + body.len());
+        payload.push(packet_type as u8);
+        payload.extend_from_slice(body);
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(payload.len() + 4);
+        write_packet(&mut bytes, 0, &payload);
+        Packet { bytes }
+    }
+
     pub fn sequence_id(&self) -> u8 {
         self.bytes[3]
     }
 
+    /// Read the connection id from the initial handshake (protocol version 10) sent by the server
+    pub fn server_connection_id(&self) -> Option<u32> {
+        if self.bytes.len() < 5 || self.sequence_id() != 0 || self.bytes[4] != 0x0a {
+            return None;
+        }
+        let payload = &self.bytes[5..];
+        // server version(string[NUL]) + connection id(4)
+        let version_end = payload.iter().position(|b| *b == 0)?;
+        let connection_id = payload.get(version_end + 1..version_end + 5)?;
+        Some(LittleEndian::read_u32(connection_id))
+    }
+
+    /// Clear a flag in the lower capability flags of the initial handshake sent by the server.
+    /// Returns true if the flag was set.
+    pub fn clear_server_capability(&mut self, flag: u32) -> bool {
+        let pos = match self.server_capability_pos() {
+            Some(pos) if flag <= 0xffff => pos,
+            _ => return false,
+        };
+        let lower = LittleEndian::read_u16(&self.bytes[pos..pos + 2]) as u32;
+        if lower & flag == 0 {
+            return false;
+        }
+        LittleEndian::write_u16(&mut self.bytes[pos..pos + 2], (lower & !flag) as u16);
+        true
+    }
+
+    //初始握手包中低2字节capability flags的位置
+    fn server_capability_pos(&self) -> Option<usize> {
+        self.server_connection_id()?;
+        let version_end = self.bytes[5..].iter().position(|b| *b == 0)?;
+        // server version(string[NUL]) + connection id(4) + auth-plugin-data-part-1(8) + filler(1)
+        let pos = 5 + version_end + 1 + 4 + 8 + 1;
+        self.bytes.get(pos..pos + 2)?;
+        Some(pos)
+    }
+
+    /// Parse a HandshakeResponse41 packet.
+    /// Returns None for SSL requests and pre-4.1 clients, whose packets carry no username we can read.
+    pub fn handshake_response(&self) -> Option<HandshakeResponse> {
+        if self.bytes.len() < 4 + 32 {
+            return None;
+        }
+        let payload = &self.bytes[4..];
+        let capability_flags = LittleEndian::read_u32(&payload[0..4]);
+        if capability_flags & CLIENT_PROTOCOL_41 == 0 || payload.len() == 32 {
+            return None;
+        }
+        // capability flags(4) + max packet size(4) + character set(1) + filler(23)
+        let mut pos = 32;
+        let username = read_null_terminated(payload, &mut pos)?;
+        if capability_flags & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+            let auth_len = read_lenenc_int(payload, &mut pos)? as usize;
+            pos += auth_len;
+        } else if capability_flags & CLIENT_SECURE_CONNECTION != 0 {
+            let auth_len = *payload.get(pos)? as usize;
+            pos += 1 + auth_len;
+        } else {
+            read_null_terminated(payload, &mut pos)?;
+        }
+        let database = if capability_flags & CLIENT_CONNECT_WITH_DB != 0 && pos < payload.len() {
+            read_null_terminated(payload, &mut pos).filter(|v| !v.is_empty())
+        } else {
+            None
+        };
+        Some(HandshakeResponse {
+            capability_flags,
+            username,
+            database,
+        })
+    }
+
     /// Determine the type of packet
     pub fn packet_type(&self) -> Result<PacketType, Error> {
         match self.bytes[4] {
@@ -93,7 +209,7 @@ impl Packet {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PacketType {
     ComSleep = 0x00,
     ComQuit = 0x01,
@@ -129,3 +245,189 @@ pub enum PacketType {
 
 // max payload size 2^(24-1)
 pub const U24_MAX: usize = 16_777_215;
+
+/// Length of the first command in `bytes`, including the packets continuing a payload of `U24_MAX` bytes.
+/// Returns None until all of its packets are received.
+pub fn command_len(bytes: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let len = LittleEndian::read_u24(bytes.get(pos..pos + 3)?) as usize;
+        pos += 4 + len;
+        if pos > bytes.len() {
+            return None;
+        }
+        if len < U24_MAX {
+            return Some(pos);
+        }
+    }
+}
+
+/// Payload of the first command in `bytes` without the packet headers, `bytes` must hold the whole command
+pub fn command_payload(bytes: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos + 4 <= bytes.len() {
+        let len = LittleEndian::read_u24(&bytes[pos..pos + 3]) as usize;
+        payload.extend_from_slice(&bytes[(pos + 4).min(bytes.len())..(pos + 4 + len).min(bytes.len())]);
+        pos += 4 + len;
+        if len < U24_MAX {
+            break;
+        }
+    }
+    payload
+}
+
+/// Read a length-encoded integer, advancing `pos`
+pub fn read_lenenc_int(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let first = *buf.get(*pos)?;
+    let (len, value) = match first {
+        0xfc => (3, LittleEndian::read_u16(buf.get(*pos + 1..*pos + 3)?) as u64),
+        0xfd => (4, LittleEndian::read_u24(buf.get(*pos + 1..*pos + 4)?) as u64),
+        0xfe => (9, LittleEndian::read_u64(buf.get(*pos + 1..*pos + 9)?)),
+        0xfb | 0xff => return None,
+        v => (1, v as u64),
+    };
+    *pos += len;
+    Some(value)
+}
+
+/// Payload of an OK packet, `header` is 0xfe when it ends a result set sent to a CLIENT_DEPRECATE_EOF client
+pub fn ok_payload(header: u8, affected_rows: u64, last_insert_id: u64, status_flags: u16, warnings: u16) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::with_capacity(7);
+    payload.push(header);
+    write_lenenc_int(&mut payload, affected_rows);
+    write_lenenc_int(&mut payload, last_insert_id);
+    payload.write_u16::<LittleEndian>(status_flags).unwrap();
+    payload.write_u16::<LittleEndian>(warnings).unwrap();
+    payload
+}
+
+/// Payload of an EOF packet
+pub fn eof_payload(warnings: u16, status_flags: u16) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::with_capacity(5);
+    payload.push(0xfe);
+    payload.write_u16::<LittleEndian>(warnings).unwrap();
+    payload.write_u16::<LittleEndian>(status_flags).unwrap();
+    payload
+}
+
+/// Write a length-encoded integer
+pub fn write_lenenc_int(buf: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=250 => buf.push(value as u8),
+        251..=0xffff => {
+            buf.push(0xfc);
+            buf.write_u16::<LittleEndian>(value as u16).unwrap();
+        }
+        0x1_0000..=0xff_ffff => {
+            buf.push(0xfd);
+            buf.write_u24::<LittleEndian>(value as u32).unwrap();
+        }
+        _ => {
+            buf.push(0xfe);
+            buf.write_u64::<LittleEndian>(value).unwrap();
+        }
+    }
+}
+
+/// Write a length-encoded string
+pub fn write_lenenc_str(buf: &mut Vec<u8>, value: &[u8]) {
+    write_lenenc_int(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Append `payload` as packets starting at `sequence_id`, split if it exceeds `U24_MAX`.
+/// Returns the next sequence id.
+pub fn write_packet(bytes: &mut Vec<u8>, sequence_id: u8, payload: &[u8]) -> u8 {
+    if payload.is_empty() {
+        bytes.extend_from_slice(&[0, 0, 0, sequence_id]);
+        return sequence_id.wrapping_add(1);
+    }
+    let mut sequence_id = sequence_id;
+    let mut chunks = payload.chunks(U24_MAX).peekable();
+    while let Some(chunk) = chunks.next() {
+        bytes.write_u32::<LittleEndian>(chunk.len() as u32).unwrap();
+        bytes.pop();
+        bytes.push(sequence_id);
+        bytes.extend_from_slice(chunk);
+        sequence_id = sequence_id.wrapping_add(1);
+        //长度正好为U24_MAX时需要再发一个空包
+        if chunks.peek().is_none() && chunk.len() == U24_MAX {
+            bytes.extend_from_slice(&[0, 0, 0, sequence_id]);
+            sequence_id = sequence_id.wrapping_add(1);
+        }
+    }
+    sequence_id
+}
+
+fn read_null_terminated(buf: &[u8], pos: &mut usize) -> Option<String> {
+    let rest = buf.get(*pos..)?;
+    let end = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+    let v = String::from_utf8_lossy(&rest[..end]).to_string();
+    *pos += end + 1;
+    Some(v)
+}
+
+#[test]
+fn test_handshake_response() {
+    let mut payload = vec![];
+    payload.write_u32::<LittleEndian>(CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_CONNECT_WITH_DB | CLIENT_DEPRECATE_EOF).unwrap();
+    payload.write_u32::<LittleEndian>(16_777_216).unwrap();
+    payload.push(45);
+    payload.extend_from_slice(&[0; 23]);
+    payload.extend_from_slice(b"app\0");
+    payload.push(20);
+    payload.extend_from_slice(&[7; 20]);
+    payload.extend_from_slice(b"shop\0mysql_native_password\0");
+    let mut bytes = vec![payload.len() as u8, 0, 0, 1];
+    bytes.extend_from_slice(&payload);
+
+    let handshake_response = Packet::new(bytes).handshake_response().unwrap();
+    assert_eq!("app", handshake_response.username);
+    assert_eq!(Some("shop".to_string()), handshake_response.database);
+
+    // SSL request: only the fixed 32 bytes
+    let mut bytes = vec![32, 0, 0, 1];
+    bytes.extend_from_slice(&payload[..32]);
+    assert_eq!(None, Packet::new(bytes).handshake_response());
+}
+
+#[test]
+fn test_command_packet() {
+    let packet = Packet::command(PacketType::ComQuery, b"select 1");
+    assert_eq!(vec![9, 0, 0, 0, 0x03, b's', b'e', b'l', b'e', b'c', b't', b' ', b'1'], packet.bytes);
+    assert_eq!(Ok(PacketType::ComQuery), packet.packet_type().map_err(|e| e.kind()));
+
+    assert_eq!(Some(packet.bytes.len()), command_len(&packet.bytes));
+    assert_eq!(None, command_len(&packet.bytes[..8]));
+    let body = vec![b'x'; U24_MAX + 10];
+    let large = Packet::command(PacketType::ComQuery, &body);
+    assert_eq!(None, command_len(&large.bytes[..U24_MAX + 4]));
+    assert_eq!(Some(large.bytes.len()), command_len(&large.bytes));
+    assert_eq!(body, command_payload(&large.bytes)[1..]);
+}
+
+#[test]
+fn test_server_connection_id() {
+    let mut bytes = vec![0, 0, 0, 0, 0x0a];
+    bytes.extend_from_slice(b"8.0.32\0");
+    bytes.write_u32::<LittleEndian>(1234).unwrap();
+    bytes.extend_from_slice(&[0; 8]);
+    assert_eq!(Some(1234), Packet::new(bytes).server_connection_id());
+    assert_eq!(None, Packet::error_packet(1045, *b"28000", "denied".to_string()).server_connection_id());
+}
+
+#[test]
+fn test_clear_server_capability() {
+    let mut bytes = vec![0, 0, 0, 0, 0x0a];
+    bytes.extend_from_slice(b"8.0.32\0");
+    bytes.write_u32::<LittleEndian>(1234).unwrap();
+    bytes.extend_from_slice(&[0; 9]);
+    bytes.write_u16::<LittleEndian>((CLIENT_PROTOCOL_41 | CLIENT_SSL) as u16).unwrap();
+    let mut packet = Packet::new(bytes);
+    assert!(packet.clear_server_capability(CLIENT_SSL));
+    assert!(!packet.clear_server_capability(CLIENT_SSL));
+    assert_eq!(CLIENT_PROTOCOL_41 as u16, LittleEndian::read_u16(&packet.bytes[packet.bytes.len() - 2..]));
+    assert_eq!(Some(1234), packet.server_connection_id());
+    assert!(!Packet::error_packet(1045, *b"28000", "denied".to_string()).clear_server_capability(CLIENT_SSL));
+}
@@ -0,0 +1,362 @@
+//! Follows the server side of the MySQL protocol for one command, so the proxy knows
+//! when the response is complete without buffering the whole result set.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::protocol::{read_lenenc_int, PacketType, SERVER_MORE_RESULTS_EXISTS, SERVER_STATUS_CURSOR_EXISTS};
+
+// enough for the header of an OK packet (0x00 + 2 lenenc ints + status flags)
+const HEAD_LEN: usize = 32;
+const MAX_PAYLOAD_LEN: usize = 0xff_ffff;
+
+/// Shape of the response a command is answered with
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResponseKind {
+    //COM_STMT_CLOSE, COM_STMT_SEND_LONG_DATA... the server sends nothing back
+    None,
+    //a single OK or ERR packet
+    Single,
+    //COM_QUERY, COM_STMT_EXECUTE
+    ResultSet,
+    //COM_STMT_PREPARE
+    Prepare,
+    //COM_FIELD_LIST
+    FieldList,
+}
+
+impl ResponseKind {
+    pub fn of(packet_type: PacketType) -> ResponseKind {
+        match packet_type {
+            PacketType::ComQuery | PacketType::ComStmtExecute => ResponseKind::ResultSet,
+            PacketType::ComStmtPrepare => ResponseKind::Prepare,
+            PacketType::ComFieldList => ResponseKind::FieldList,
+            PacketType::ComQuit
+            | PacketType::ComStmtClose
+            | PacketType::ComStmtSendLongData
+            | PacketType::ComBinlogDump
+            | PacketType::ComBinlogDumpGtid => ResponseKind::None,
+            _ => ResponseKind::Single,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    First,
+    ColumnDefs(u64),
+    ColumnsEof,
+    Rows,
+    PrepareParams(u64, u64),
+    PrepareParamsEof(u64),
+    PrepareColumns(u64),
+    PrepareColumnsEof,
+    Done,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResponseTracker {
+    kind: ResponseKind,
+    deprecate_eof: bool,
+    state: State,
+    header: [u8; 4],
+    header_read: usize,
+    payload_len: usize,
+    payload_read: usize,
+    head: Vec<u8>,
+    //the previous packet was a 16MB chunk, the current one continues it
+    continuation: bool,
+    pub rows: u64,
+    pub bytes: u64,
+    pub error_code: Option<u16>,
+    //status flags of the last OK/EOF packet, e.g. SERVER_STATUS_IN_TRANS
+    pub server_status: Option<u16>,
+}
+
+impl ResponseTracker {
+    pub fn new(kind: ResponseKind, deprecate_eof: bool) -> ResponseTracker {
+        ResponseTracker {
+            kind,
+            deprecate_eof,
+            state: if kind == ResponseKind::None { State::Done } else { State::First },
+            header: [0; 4],
+            header_read: 0,
+            payload_len: 0,
+            payload_read: 0,
+            head: Vec::with_capacity(HEAD_LEN),
+            continuation: false,
+            rows: 0,
+            bytes: 0,
+            error_code: None,
+            server_status: None,
+        }
+    }
+
+    pub fn kind(&self) -> ResponseKind {
+        self.kind
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Feed bytes received from the server.
+    /// Returns how many bytes belong to this response; anything after that is not ours.
+    pub fn feed(&mut self, data: &[u8]) -> usize {
+        let mut pos = 0;
+        while pos < data.len() && !self.is_finished() {
+            if self.header_read < 4 {
+                self.header[self.header_read] = data[pos];
+                self.header_read += 1;
+                pos += 1;
+                if self.header_read == 4 {
+                    self.payload_len = LittleEndian::read_u24(&self.header[0..3]) as usize;
+                    self.payload_read = 0;
+                    self.head.clear();
+                    if self.payload_len == 0 {
+                        self.on_packet();
+                    }
+                }
+                continue;
+            }
+            let n = (self.payload_len - self.payload_read).min(data.len() - pos);
+            let keep = (HEAD_LEN - self.head.len()).min(n);
+            self.head.extend_from_slice(&data[pos..pos + keep]);
+            self.payload_read += n;
+            pos += n;
+            if self.payload_read == self.payload_len {
+                self.on_packet();
+            }
+        }
+        self.bytes += pos as u64;
+        pos
+    }
+
+    fn on_packet(&mut self) {
+        self.header_read = 0;
+        let continuation = self.continuation;
+        self.continuation = self.payload_len == MAX_PAYLOAD_LEN;
+        if continuation {
+            return;
+        }
+        let first = self.head.first().copied();
+        if first == Some(0xff) {
+            self.error_code = self.head.get(1..3).map(LittleEndian::read_u16);
+            self.state = State::Done;
+            return;
+        }
+        self.state = match self.state {
+            State::First => self.on_first_packet(),
+            State::ColumnDefs(remaining) => self.after_column_def(remaining - 1),
+            State::ColumnsEof => {
+                if self.status_flags() & SERVER_STATUS_CURSOR_EXISTS != 0 {
+                    State::Done
+                } else {
+                    State::Rows
+                }
+            }
+            State::Rows => {
+                if self.is_terminator() {
+                    self.after_result_set()
+                } else {
+                    self.rows += 1;
+                    State::Rows
+                }
+            }
+            State::PrepareParams(remaining, columns) => {
+                if remaining > 1 {
+                    State::PrepareParams(remaining - 1, columns)
+                } else if self.deprecate_eof {
+                    self.prepare_columns(columns)
+                } else {
+                    State::PrepareParamsEof(columns)
+                }
+            }
+            State::PrepareParamsEof(columns) => self.prepare_columns(columns),
+            State::PrepareColumns(remaining) => {
+                if remaining > 1 {
+                    State::PrepareColumns(remaining - 1)
+                } else if self.deprecate_eof {
+                    State::Done
+                } else {
+                    State::PrepareColumnsEof
+                }
+            }
+            State::PrepareColumnsEof | State::Done => State::Done,
+        };
+    }
+
+    fn on_first_packet(&mut self) -> State {
+        match self.kind {
+            ResponseKind::None | ResponseKind::Single => {
+                if self.head.first() == Some(&0x00) {
+                    self.server_status = Some(self.status_flags());
+                }
+                State::Done
+            }
+            ResponseKind::FieldList => {
+                if self.is_terminator() {
+                    State::Done
+                } else {
+                    State::ColumnDefs(u64::MAX)
+                }
+            }
+            ResponseKind::Prepare => {
+                let columns = self.head.get(5..7).map(LittleEndian::read_u16).unwrap_or(0) as u64;
+                let params = self.head.get(7..9).map(LittleEndian::read_u16).unwrap_or(0) as u64;
+                if params > 0 {
+                    State::PrepareParams(params, columns)
+                } else {
+                    self.prepare_columns(columns)
+                }
+            }
+            ResponseKind::ResultSet => match self.head.first() {
+                Some(0x00) => self.after_result_set(),
+                //LOCAL INFILE request, the server answers with OK/ERR once the client has sent the file
+                Some(0xfb) => State::First,
+                _ => {
+                    let mut pos = 0;
+                    match read_lenenc_int(&self.head, &mut pos) {
+                        Some(0) | None => State::Done,
+                        Some(column_count) => State::ColumnDefs(column_count),
+                    }
+                }
+            },
+        }
+    }
+
+    fn after_column_def(&mut self, remaining: u64) -> State {
+        if self.kind == ResponseKind::FieldList {
+            return if self.is_terminator() { State::Done } else { State::ColumnDefs(remaining) };
+        }
+        if remaining > 0 {
+            State::ColumnDefs(remaining)
+        } else if self.deprecate_eof {
+            State::Rows
+        } else {
+            State::ColumnsEof
+        }
+    }
+
+    fn prepare_columns(&self, columns: u64) -> State {
+        if columns > 0 {
+            State::PrepareColumns(columns)
+        } else {
+            State::Done
+        }
+    }
+
+    fn after_result_set(&mut self) -> State {
+        let status_flags = self.status_flags();
+        self.server_status = Some(status_flags);
+        if status_flags & SERVER_MORE_RESULTS_EXISTS != 0 {
+            State::First
+        } else {
+            State::Done
+        }
+    }
+
+    // EOF packet, or the OK packet that replaces it when CLIENT_DEPRECATE_EOF is set
+    fn is_terminator(&self) -> bool {
+        if self.head.first() != Some(&0xfe) {
+            return false;
+        }
+        if self.deprecate_eof {
+            self.payload_len < MAX_PAYLOAD_LEN
+        } else {
+            self.payload_len < 9
+        }
+    }
+
+    fn status_flags(&self) -> u16 {
+        let head = &self.head;
+        match head.first() {
+            Some(0xfe) if head.len() <= 5 => head.get(3..5).map(LittleEndian::read_u16).unwrap_or(0),
+            Some(0x00) | Some(0xfe) => {
+                let mut pos = 1;
+                if read_lenenc_int(head, &mut pos).is_none() || read_lenenc_int(head, &mut pos).is_none() {
+                    return 0;
+                }
+                head.get(pos..pos + 2).map(LittleEndian::read_u16).unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+fn packet(seq: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0; 4];
+    LittleEndian::write_u24(&mut bytes[0..3], payload.len() as u32);
+    bytes[3] = seq;
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+#[test]
+fn test_result_set_with_eof() {
+    let mut response = vec![];
+    response.extend(packet(1, &[0x02]));
+    response.extend(packet(2, b"\x03def\x00\x00\x00\x01a\x00\x0c\x3f\x00\x01\x00\x00\x00\x08\x00\x00\x00\x00\x00"));
+    response.extend(packet(3, b"\x03def\x00\x00\x00\x01b\x00\x0c\x3f\x00\x01\x00\x00\x00\x08\x00\x00\x00\x00\x00"));
+    response.extend(packet(4, &[0xfe, 0, 0, 0x02, 0]));
+    response.extend(packet(5, b"\x011\x012"));
+    response.extend(packet(6, b"\x013\x014"));
+    response.extend(packet(7, &[0xfe, 0, 0, 0x02, 0]));
+
+    // feed byte by byte to exercise partial headers and payloads
+    let mut tracker = ResponseTracker::new(ResponseKind::ResultSet, false);
+    for (i, b) in response.iter().enumerate() {
+        assert!(!tracker.is_finished(), "finished early at byte {}", i);
+        assert_eq!(1, tracker.feed(&[*b]));
+    }
+    assert!(tracker.is_finished());
+    assert_eq!(2, tracker.rows);
+    assert_eq!(response.len() as u64, tracker.bytes);
+}
+
+#[test]
+fn test_result_set_deprecate_eof_and_more_results() {
+    let mut response = vec![];
+    // first result set, its OK terminator announces another one
+    response.extend(packet(1, &[0x01]));
+    response.extend(packet(2, b"\x03def\x00\x00\x00\x01a\x00\x0c\x3f\x00\x01\x00\x00\x00\x08\x00\x00\x00\x00\x00"));
+    response.extend(packet(3, b"\x011"));
+    response.extend(packet(4, &[0xfe, 0, 0, 0x0a, 0, 0, 0]));
+    // second one is a plain OK
+    response.extend(packet(5, &[0x00, 0, 0, 0x02, 0, 0, 0]));
+    let trailing = packet(0, &[0x0e]);
+
+    let mut tracker = ResponseTracker::new(ResponseKind::ResultSet, true);
+    let mut data = response.clone();
+    data.extend(trailing);
+    assert_eq!(response.len(), tracker.feed(&data));
+    assert!(tracker.is_finished());
+    assert_eq!(1, tracker.rows);
+    assert_eq!(Some(0x02), tracker.server_status);
+
+    // the OK answering COM_INIT_DB inside a transaction
+    let mut tracker = ResponseTracker::new(ResponseKind::Single, true);
+    tracker.feed(&packet(1, &[0x00, 0, 0, 0x03, 0, 0, 0]));
+    assert_eq!(Some(0x03), tracker.server_status);
+}
+
+#[test]
+fn test_error_and_prepare() {
+    let mut tracker = ResponseTracker::new(ResponseKind::ResultSet, false);
+    tracker.feed(&packet(1, b"\xff\x7a\x04#42S02Table 'a' doesn't exist"));
+    assert!(tracker.is_finished());
+    assert_eq!(Some(1146), tracker.error_code);
+
+    // 1 column, 1 param
+    let mut response = vec![];
+    response.extend(packet(1, &[0x00, 1, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0]));
+    response.extend(packet(2, b"\x03def\x00\x00\x00\x01?\x00\x0c\x3f\x00\x00\x00\x00\x00\xfd\x80\x00\x00\x00\x00"));
+    response.extend(packet(3, &[0xfe, 0, 0, 0x02, 0]));
+    response.extend(packet(4, b"\x03def\x00\x00\x00\x01a\x00\x0c\x3f\x00\x01\x00\x00\x00\x08\x00\x00\x00\x00\x00"));
+    let last = packet(5, &[0xfe, 0, 0, 0x02, 0]);
+    let mut tracker = ResponseTracker::new(ResponseKind::Prepare, false);
+    tracker.feed(&response);
+    assert!(!tracker.is_finished());
+    tracker.feed(&last);
+    assert!(tracker.is_finished());
+}
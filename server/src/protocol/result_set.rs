@@ -0,0 +1,574 @@
+//! Result sets built by the proxy itself, e.g. for `VIRTDB ...` admin commands, mocked responses
+//! or cached rows served in another protocol
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use mysql::consts::ColumnType;
+use mysql::Value;
+
+use crate::protocol::{eof_payload, ok_payload, write_lenenc_int, write_lenenc_str, write_packet, SERVER_STATUS_AUTOCOMMIT};
+
+// column flags, see https://dev.mysql.com/doc/dev/mysql-server/latest/group__group__cs__column__definition__flags.html
+pub const NOT_NULL_FLAG: u16 = 0x0001;
+pub const PRI_KEY_FLAG: u16 = 0x0002;
+pub const UNSIGNED_FLAG: u16 = 0x0020;
+pub const BINARY_FLAG: u16 = 0x0080;
+
+pub const UTF8MB4_GENERAL_CI: u16 = 45;
+pub const BINARY_CHARSET: u16 = 63;
+//FLOAT/DOUBLE没有指定小数位数
+const NOT_FIXED_DEC: u8 = 31;
+//NULL值在文本协议中的表示
+const NULL_VALUE: u8 = 0xfb;
+
+/// Protocol::ColumnDefinition41
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDefinition {
+    pub schema: String,
+    pub table: String,
+    pub org_table: String,
+    pub name: String,
+    pub org_name: String,
+    pub character_set: u16,
+    pub column_length: u32,
+    pub column_type: ColumnType,
+    pub flags: u16,
+    pub decimals: u8,
+}
+
+impl ColumnDefinition {
+    /// A column of `column_type`, numbers and dates use the binary charset like MySQL does
+    pub fn new(name: &str, column_type: ColumnType) -> ColumnDefinition {
+        let (column_length, decimals, is_text) = match column_type {
+            ColumnType::MYSQL_TYPE_TINY => (4, 0, false),
+            ColumnType::MYSQL_TYPE_SHORT => (6, 0, false),
+            ColumnType::MYSQL_TYPE_INT24 => (9, 0, false),
+            ColumnType::MYSQL_TYPE_LONG => (11, 0, false),
+            ColumnType::MYSQL_TYPE_LONGLONG => (20, 0, false),
+            ColumnType::MYSQL_TYPE_FLOAT => (12, NOT_FIXED_DEC, false),
+            ColumnType::MYSQL_TYPE_DOUBLE => (22, NOT_FIXED_DEC, false),
+            ColumnType::MYSQL_TYPE_YEAR => (4, 0, false),
+            ColumnType::MYSQL_TYPE_DATE => (10, 0, false),
+            ColumnType::MYSQL_TYPE_TIME => (17, 6, false),
+            ColumnType::MYSQL_TYPE_DATETIME | ColumnType::MYSQL_TYPE_TIMESTAMP => (26, 6, false),
+            ColumnType::MYSQL_TYPE_NEWDECIMAL => (67, 0, false),
+            _ => (1024 * 1024, 0, true),
+        };
+        ColumnDefinition {
+            schema: String::new(),
+            table: String::new(),
+            org_table: String::new(),
+            name: name.to_string(),
+            org_name: name.to_string(),
+            character_set: if is_text { UTF8MB4_GENERAL_CI } else { BINARY_CHARSET },
+            column_length,
+            column_type,
+            flags: if is_text { 0 } else { BINARY_FLAG },
+            decimals,
+        }
+    }
+
+    pub fn table(mut self, schema: &str, table: &str) -> Self {
+        self.schema = schema.to_string();
+        self.table = table.to_string();
+        self.org_table = table.to_string();
+        self
+    }
+
+    /// Add column flags such as `NOT_NULL_FLAG` or `UNSIGNED_FLAG`
+    pub fn flags(mut self, flags: u16) -> Self {
+        self.flags |= flags;
+        self
+    }
+
+    pub fn decimals(mut self, decimals: u8) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    fn write(&self, payload: &mut Vec<u8>) {
+        write_lenenc_str(payload, b"def"); // catalog
+        write_lenenc_str(payload, self.schema.as_bytes());
+        write_lenenc_str(payload, self.table.as_bytes());
+        write_lenenc_str(payload, self.org_table.as_bytes());
+        write_lenenc_str(payload, self.name.as_bytes());
+        write_lenenc_str(payload, self.org_name.as_bytes());
+        write_lenenc_int(payload, 0x0c); // length of the fixed fields
+        payload.write_u16::<LittleEndian>(self.character_set).unwrap();
+        payload.write_u32::<LittleEndian>(self.column_length).unwrap();
+        payload.push(self.column_type as u8);
+        payload.write_u16::<LittleEndian>(self.flags).unwrap();
+        payload.push(self.decimals);
+        payload.extend_from_slice(&[0, 0]); // filler
+    }
+}
+
+/// A result set answering COM_QUERY (text rows) or COM_STMT_EXECUTE (binary rows).
+/// Values are converted to the column type when encoding, every row must have as many values as there are columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<ColumnDefinition>,
+    pub rows: Vec<Vec<Value>>,
+    //结束包中的状态,如SERVER_MORE_RESULTS_EXISTS
+    pub status_flags: u16,
+    pub warnings: u16,
+}
+
+impl ResultSet {
+    /// A result set of string columns
+    pub fn new(columns: &[&str]) -> ResultSet {
+        ResultSet::with_columns(columns.iter().map(|name| ColumnDefinition::new(name, ColumnType::MYSQL_TYPE_VAR_STRING)).collect())
+    }
+
+    pub fn with_columns(columns: Vec<ColumnDefinition>) -> ResultSet {
+        ResultSet {
+            columns,
+            rows: vec![],
+            status_flags: SERVER_STATUS_AUTOCOMMIT,
+            warnings: 0,
+        }
+    }
+
+    pub fn status_flags(mut self, status_flags: u16) -> Self {
+        self.status_flags = status_flags;
+        self
+    }
+
+    pub fn warnings(mut self, warnings: u16) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    pub fn add_row(&mut self, row: Vec<Value>) {
+        debug_assert_eq!(self.columns.len(), row.len());
+        self.rows.push(row);
+    }
+
+    /// Encode as the response of a COM_QUERY, sequence ids start at 1.
+    /// `deprecate_eof` is whether the client set CLIENT_DEPRECATE_EOF.
+    pub fn encode_text(&self, deprecate_eof: bool) -> Vec<u8> {
+        self.encode(deprecate_eof, |row, payload| {
+            for (column, value) in self.columns.iter().zip(row.iter()) {
+                match text_value(column, value) {
+                    Some(text) => write_lenenc_str(payload, &text),
+                    None => payload.push(NULL_VALUE),
+                }
+            }
+            Ok(())
+        }).unwrap()
+    }
+
+    /// Encode as the response of a COM_STMT_EXECUTE, fails if a value can't be converted to its column type
+    pub fn encode_binary(&self, deprecate_eof: bool) -> Result<Vec<u8>, String> {
+        self.encode(deprecate_eof, |row, payload| {
+            payload.push(0x00); // packet header
+            //NULL位图,前2位保留
+            let bitmap_start = payload.len();
+            payload.resize(bitmap_start + (self.columns.len() + 7 + 2) / 8, 0);
+            for (index, (column, value)) in self.columns.iter().zip(row.iter()).enumerate() {
+                if *value == Value::NULL {
+                    payload[bitmap_start + (index + 2) / 8] |= 1 << ((index + 2) % 8);
+                    continue;
+                }
+                write_binary_value(payload, column, value)
+                    .map_err(|e| format!("column {}: {}", column.name, e))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Encode the response of a COM_STMT_PREPARE for a statement without parameters returning these columns
+    pub fn encode_prepare_ok(&self, statement_id: u32, deprecate_eof: bool) -> Vec<u8> {
+        let mut payload = vec![0x00];
+        payload.write_u32::<LittleEndian>(statement_id).unwrap();
+        payload.write_u16::<LittleEndian>(self.columns.len() as u16).unwrap();
+        payload.write_u16::<LittleEndian>(0).unwrap(); // number of parameters
+        payload.push(0); // filler
+        payload.write_u16::<LittleEndian>(self.warnings).unwrap();
+        let mut bytes = Vec::new();
+        let sequence_id = write_packet(&mut bytes, 1, &payload);
+        self.write_columns(&mut bytes, sequence_id, deprecate_eof);
+        bytes
+    }
+
+    fn write_columns(&self, bytes: &mut Vec<u8>, sequence_id: u8, deprecate_eof: bool) -> u8 {
+        let mut sequence_id = sequence_id;
+        let mut payload = Vec::new();
+        for column in self.columns.iter() {
+            payload.clear();
+            column.write(&mut payload);
+            sequence_id = write_packet(bytes, sequence_id, &payload);
+        }
+        if !deprecate_eof && !self.columns.is_empty() {
+            sequence_id = write_packet(bytes, sequence_id, &eof_payload(0, self.status_flags));
+        }
+        sequence_id
+    }
+
+    fn encode(&self, deprecate_eof: bool, write_row: impl Fn(&[Value], &mut Vec<u8>) -> Result<(), String>) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut payload = Vec::new();
+        write_lenenc_int(&mut payload, self.columns.len() as u64);
+        let mut sequence_id = write_packet(&mut bytes, 1, &payload);
+        sequence_id = self.write_columns(&mut bytes, sequence_id, deprecate_eof);
+
+        for row in self.rows.iter() {
+            payload.clear();
+            write_row(row, &mut payload)?;
+            sequence_id = write_packet(&mut bytes, sequence_id, &payload);
+        }
+
+        //CLIENT_DEPRECATE_EOF时用0xfe开头的OK包结束
+        let end_payload = if deprecate_eof {
+            ok_payload(0xfe, 0, 0, self.status_flags, self.warnings)
+        } else {
+            eof_payload(self.warnings, self.status_flags)
+        };
+        write_packet(&mut bytes, sequence_id, &end_payload);
+        Ok(bytes)
+    }
+}
+
+/// Text protocol form of a value, `None` for NULL
+pub fn text_value(column: &ColumnDefinition, value: &Value) -> Option<Vec<u8>> {
+    let text = match value {
+        Value::NULL => return None,
+        Value::Bytes(bytes) => return Some(bytes.clone()),
+        Value::Int(v) => v.to_string(),
+        Value::UInt(v) => v.to_string(),
+        Value::Float(v) => v.to_string(),
+        Value::Double(v) => v.to_string(),
+        Value::Date(year, month, day, ..) if column.column_type == ColumnType::MYSQL_TYPE_DATE => {
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        }
+        Value::Date(year, month, day, hour, minute, second, micros) => {
+            format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}", year, month, day, hour, minute, second, fraction(*micros))
+        }
+        Value::Time(negative, days, hours, minutes, seconds, micros) => {
+            let sign = if *negative { "-" } else { "" };
+            format!("{}{:02}:{:02}:{:02}{}", sign, *days * 24 + *hours as u32, minutes, seconds, fraction(*micros))
+        }
+    };
+    Some(text.into_bytes())
+}
+
+fn fraction(micros: u32) -> String {
+    if micros == 0 {
+        String::new()
+    } else {
+        format!(".{:06}", micros)
+    }
+}
+
+fn as_i64(value: &Value) -> Result<i64, String> {
+    match value {
+        Value::Int(v) => Ok(*v),
+        Value::UInt(v) => Ok(*v as i64),
+        Value::Bytes(bytes) => {
+            let text = String::from_utf8_lossy(bytes);
+            text.parse::<i64>().or_else(|_| text.parse::<u64>().map(|v| v as i64))
+                .map_err(|_| format!("{:?} is not an integer", text))
+        }
+        _ => Err(format!("{:?} is not an integer", value)),
+    }
+}
+
+fn as_f64(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Float(v) => Ok(*v as f64),
+        Value::Double(v) => Ok(*v),
+        Value::Int(v) => Ok(*v as f64),
+        Value::UInt(v) => Ok(*v as f64),
+        Value::Bytes(bytes) => {
+            let text = String::from_utf8_lossy(bytes);
+            text.parse::<f64>().map_err(|_| format!("{:?} is not a number", text))
+        }
+        _ => Err(format!("{:?} is not a number", value)),
+    }
+}
+
+// ProtocolBinary::*, see https://dev.mysql.com/doc/dev/mysql-server/latest/page_protocol_binary_resultset.html
+fn write_binary_value(payload: &mut Vec<u8>, column: &ColumnDefinition, value: &Value) -> Result<(), String> {
+    match column.column_type {
+        ColumnType::MYSQL_TYPE_TINY => payload.push(as_i64(value)? as u8),
+        ColumnType::MYSQL_TYPE_SHORT | ColumnType::MYSQL_TYPE_YEAR => payload.write_u16::<LittleEndian>(as_i64(value)? as u16).unwrap(),
+        ColumnType::MYSQL_TYPE_INT24 | ColumnType::MYSQL_TYPE_LONG => payload.write_u32::<LittleEndian>(as_i64(value)? as u32).unwrap(),
+        ColumnType::MYSQL_TYPE_LONGLONG => payload.write_u64::<LittleEndian>(as_i64(value)? as u64).unwrap(),
+        ColumnType::MYSQL_TYPE_FLOAT => payload.write_f32::<LittleEndian>(as_f64(value)? as f32).unwrap(),
+        ColumnType::MYSQL_TYPE_DOUBLE => payload.write_f64::<LittleEndian>(as_f64(value)?).unwrap(),
+        ColumnType::MYSQL_TYPE_DATE | ColumnType::MYSQL_TYPE_DATETIME | ColumnType::MYSQL_TYPE_TIMESTAMP => match value {
+            Value::Date(year, month, day, hour, minute, second, micros) => {
+                let len = match (*hour, *minute, *second, *micros) {
+                    (0, 0, 0, 0) if (*year, *month, *day) == (0, 0, 0) => 0,
+                    (0, 0, 0, 0) => 4,
+                    (_, _, _, 0) => 7,
+                    _ => 11,
+                };
+                payload.push(len);
+                if len >= 4 {
+                    payload.write_u16::<LittleEndian>(*year).unwrap();
+                    payload.extend_from_slice(&[*month, *day]);
+                }
+                if len >= 7 {
+                    payload.extend_from_slice(&[*hour, *minute, *second]);
+                }
+                if len == 11 {
+                    payload.write_u32::<LittleEndian>(*micros).unwrap();
+                }
+            }
+            _ => return Err(format!("{:?} is not a date", value)),
+        },
+        ColumnType::MYSQL_TYPE_TIME => match value {
+            Value::Time(negative, days, hours, minutes, seconds, micros) => {
+                let len = match (*days, *hours, *minutes, *seconds, *micros) {
+                    (0, 0, 0, 0, 0) => 0,
+                    (_, _, _, _, 0) => 8,
+                    _ => 12,
+                };
+                payload.push(len);
+                if len >= 8 {
+                    payload.push(*negative as u8);
+                    payload.write_u32::<LittleEndian>(*days).unwrap();
+                    payload.extend_from_slice(&[*hours, *minutes, *seconds]);
+                }
+                if len == 12 {
+                    payload.write_u32::<LittleEndian>(*micros).unwrap();
+                }
+            }
+            _ => return Err(format!("{:?} is not a time", value)),
+        },
+        //字符串,DECIMAL,JSON等都按文本编码
+        _ => write_lenenc_str(payload, &text_value(column, value).unwrap_or_default()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn read_packet(stream: &mut impl std::io::Read) -> Option<Vec<u8>> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).ok()?;
+    let mut payload = vec![0; header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16];
+    stream.read_exact(&mut payload).ok()?;
+    Some(payload)
+}
+
+//只处理测试用到的命令的MySQL服务端,advertise_deprecate_eof为true时声明支持CLIENT_DEPRECATE_EOF,
+//客户端也声明了才按CLIENT_DEPRECATE_EOF返回
+#[cfg(test)]
+fn serve_one(listener: std::net::TcpListener, result_set: ResultSet, advertise_deprecate_eof: bool) {
+    use std::io::Write;
+    use crate::protocol::{CLIENT_DEPRECATE_EOF, CLIENT_PROTOCOL_41, CLIENT_SECURE_CONNECTION, Packet};
+    let (mut stream, _) = listener.accept().unwrap();
+
+    // + CLIENT_PLUGIN_AUTH, CLIENT_LONG_PASSWORD, CLIENT_TRANSACTIONS, CLIENT_MULTI_RESULTS
+    let mut capabilities = CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | 0x0008_0000 | 0x0000_0001 | 0x0000_2000 | 0x0002_0000;
+    if advertise_deprecate_eof {
+        capabilities |= CLIENT_DEPRECATE_EOF;
+    }
+    let mut greeting = vec![0x0a];
+    greeting.extend_from_slice(b"8.0.32\0");
+    greeting.write_u32::<LittleEndian>(1).unwrap();
+    greeting.extend_from_slice(&[1; 8]);
+    greeting.push(0);
+    greeting.write_u16::<LittleEndian>(capabilities as u16).unwrap();
+    greeting.push(UTF8MB4_GENERAL_CI as u8);
+    greeting.write_u16::<LittleEndian>(SERVER_STATUS_AUTOCOMMIT).unwrap();
+    greeting.write_u16::<LittleEndian>((capabilities >> 16) as u16).unwrap();
+    greeting.push(21);
+    greeting.extend_from_slice(&[0; 10]);
+    greeting.extend_from_slice(&[2; 12]);
+    greeting.push(0);
+    greeting.extend_from_slice(b"mysql_native_password\0");
+    let mut bytes = vec![];
+    write_packet(&mut bytes, 0, &greeting);
+    stream.write_all(&bytes).unwrap();
+    let handshake_response = read_packet(&mut stream).unwrap();
+    let client_capabilities = u32::from_le_bytes(handshake_response[..4].try_into().unwrap());
+    let deprecate_eof = advertise_deprecate_eof && client_capabilities & CLIENT_DEPRECATE_EOF != 0;
+    let mut bytes = vec![];
+    write_packet(&mut bytes, 2, &ok_payload(0x00, 0, 0, SERVER_STATUS_AUTOCOMMIT, 0));
+    stream.write_all(&bytes).unwrap();
+
+    while let Some(payload) = read_packet(&mut stream) {
+        let response = match payload[0] {
+            0x03 if payload.ends_with(b"@@max_allowed_packet") => {
+                let mut variables = ResultSet::new(&["@@max_allowed_packet"]);
+                variables.add_row(vec![Value::from("16777216")]);
+                variables.encode_text(deprecate_eof)
+            }
+            0x03 if payload.starts_with(b"\x03VIRTDB") => Packet::ok_packet(3).bytes,
+            0x03 => result_set.encode_text(deprecate_eof),
+            0x16 => result_set.encode_prepare_ok(1, deprecate_eof),
+            0x17 => result_set.encode_binary(deprecate_eof).unwrap(),
+            0x01 => return,
+            // COM_STMT_CLOSE
+            _ => continue,
+        };
+        stream.write_all(&response).unwrap();
+    }
+}
+
+#[test]
+fn test_encode() {
+    use crate::protocol::response::{ResponseKind, ResponseTracker};
+
+    let mut result_set = ResultSet::new(&["name", "value"]);
+    result_set.add_row(vec![Value::from("sessions"), Value::from("3")]);
+    result_set.add_row(vec![Value::from("meta_source"), Value::NULL]);
+    for deprecate_eof in [false, true] {
+        let bytes = result_set.encode_text(deprecate_eof);
+        let mut tracker = ResponseTracker::new(ResponseKind::ResultSet, deprecate_eof);
+        assert_eq!(bytes.len(), tracker.feed(&bytes));
+        assert!(tracker.is_finished());
+        assert_eq!(2, tracker.rows);
+        assert_eq!(1, bytes[3]);
+
+        let bytes = result_set.encode_binary(deprecate_eof).unwrap();
+        let mut tracker = ResponseTracker::new(ResponseKind::ResultSet, deprecate_eof);
+        assert_eq!(bytes.len(), tracker.feed(&bytes));
+        assert!(tracker.is_finished());
+        assert_eq!(2, tracker.rows);
+
+        let bytes = result_set.encode_prepare_ok(1, deprecate_eof);
+        let mut tracker = ResponseTracker::new(ResponseKind::Prepare, deprecate_eof);
+        assert_eq!(bytes.len(), tracker.feed(&bytes));
+        assert!(tracker.is_finished());
+    }
+}
+
+#[cfg(test)]
+fn sample_result_set() -> ResultSet {
+    let mut result_set = ResultSet::with_columns(vec![
+        ColumnDefinition::new("id", ColumnType::MYSQL_TYPE_LONGLONG).table("shop", "users").flags(NOT_NULL_FLAG | PRI_KEY_FLAG),
+        ColumnDefinition::new("name", ColumnType::MYSQL_TYPE_VAR_STRING).table("shop", "users"),
+        ColumnDefinition::new("score", ColumnType::MYSQL_TYPE_DOUBLE),
+        ColumnDefinition::new("level", ColumnType::MYSQL_TYPE_TINY).flags(UNSIGNED_FLAG),
+        ColumnDefinition::new("created_at", ColumnType::MYSQL_TYPE_DATETIME),
+        ColumnDefinition::new("duration", ColumnType::MYSQL_TYPE_TIME),
+    ]).warnings(2);
+    result_set.add_row(vec![Value::Int(1), Value::from("alice"), Value::Double(9.5), Value::UInt(200),
+                            Value::Date(2023, 1, 2, 3, 4, 5, 0), Value::Time(false, 1, 2, 3, 4, 500_000)]);
+    result_set.add_row(vec![Value::Int(2), Value::NULL, Value::NULL, Value::from("7"),
+                            Value::Date(2023, 1, 2, 0, 0, 0, 0), Value::Time(true, 0, 0, 0, 1, 0)]);
+    result_set
+}
+
+#[test]
+fn test_decode_with_mysql_crate() {
+    use mysql::consts::ColumnFlags;
+    use mysql::prelude::Queryable;
+    use mysql::{Conn, OptsBuilder, Row};
+
+    let result_set = sample_result_set();
+
+    //mysql crate不请求CLIENT_DEPRECATE_EOF,服务端声明支持时仍按EOF包返回
+    for advertise_deprecate_eof in [false, true] {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server_result_set = result_set.clone();
+        let server = std::thread::spawn(move || serve_one(listener, server_result_set, advertise_deprecate_eof));
+        let opts = OptsBuilder::new()
+            .ip_or_hostname(Some("127.0.0.1"))
+            .tcp_port(port)
+            .prefer_socket(false)
+            .user(Some("test"));
+        let mut conn = Conn::new(opts).unwrap();
+
+        let rows: Vec<Row> = conn.query("SELECT * FROM users").unwrap();
+        assert_eq!(2, conn.warnings());
+        assert_eq!(2, rows.len());
+        let columns = rows[0].columns_ref();
+        assert_eq!(("users", "id"), (&*columns[0].table_str(), &*columns[0].name_str()));
+        assert_eq!(ColumnType::MYSQL_TYPE_LONGLONG, columns[0].column_type());
+        assert!(columns[0].flags().contains(ColumnFlags::NOT_NULL_FLAG | ColumnFlags::PRI_KEY_FLAG));
+        assert!(columns[3].flags().contains(ColumnFlags::UNSIGNED_FLAG));
+        assert_eq!(Some(1), rows[0].get::<i64, _>(0));
+        assert_eq!(Some(String::from("alice")), rows[0].get::<String, _>(1));
+        assert_eq!(Some(9.5), rows[0].get::<f64, _>(2));
+        assert_eq!(Some(200), rows[0].get::<u8, _>(3));
+        assert_eq!(Value::from("2023-01-02 03:04:05"), rows[0][4]);
+        assert_eq!(Value::from("26:03:04.500000"), rows[0][5]);
+        assert_eq!(Value::NULL, rows[1][1]);
+        assert_eq!(Value::from("-00:00:01"), rows[1][5]);
+
+        let statement = conn.prep("SELECT * FROM users WHERE id > 0").unwrap();
+        let rows: Vec<Row> = conn.exec(&statement, ()).unwrap();
+        assert_eq!(2, rows.len());
+        assert_eq!(Value::Int(1), rows[0][0]);
+        assert_eq!(Value::from("alice"), rows[0][1]);
+        assert_eq!(Value::Double(9.5), rows[0][2]);
+        assert_eq!(Some(200), rows[0].get::<u8, _>(3));
+        assert_eq!(Value::Date(2023, 1, 2, 3, 4, 5, 0), rows[0][4]);
+        assert_eq!(Value::Time(false, 1, 2, 3, 4, 500_000), rows[0][5]);
+        assert_eq!((Value::NULL, Value::NULL), (rows[1][1].clone(), rows[1][2].clone()));
+        assert_eq!(Some(7), rows[1].get::<u8, _>(3));
+        assert_eq!(Value::Date(2023, 1, 2, 0, 0, 0, 0), rows[1][4]);
+        assert_eq!(Value::Time(true, 0, 0, 0, 1, 0), rows[1][5]);
+
+        conn.query_drop("VIRTDB PURGE CACHE FOR TABLE users").unwrap();
+        assert_eq!(3, conn.affected_rows());
+        drop(conn);
+        server.join().unwrap();
+    }
+
+    let mut invalid = ResultSet::with_columns(vec![ColumnDefinition::new("id", ColumnType::MYSQL_TYPE_LONG)]);
+    invalid.add_row(vec![Value::from("abc")]);
+    assert!(invalid.encode_binary(false).is_err());
+}
+
+//mysql crate的Conn在列定义后总是跳过一个包,读不了CLIENT_DEPRECATE_EOF的结果集,
+//用mysql_common逐个解析encode_text(true)和encode_binary(true)的数据包
+#[test]
+fn test_decode_deprecate_eof_with_mysql_common() {
+    use std::sync::Arc;
+    use mysql::consts::CapabilityFlags;
+    use mysql::{Column, Row};
+    use mysql_common::io::ParseBuf;
+    use mysql_common::packets::{OkPacketDeserializer, ResultSetTerminator};
+    use mysql_common::proto::{Binary, MyDeserialize, Text};
+    use mysql_common::row::RowDeserializer;
+    use mysql_common::value::ServerSide;
+
+    let result_set = sample_result_set();
+    for binary in [false, true] {
+        let bytes = if binary { result_set.encode_binary(true).unwrap() } else { result_set.encode_text(true) };
+        let mut reader = bytes.as_slice();
+        let column_count = read_packet(&mut reader).unwrap()[0] as usize;
+        let columns: Arc<[Column]> = (0..column_count)
+            .map(|_| Column::deserialize((), &mut ParseBuf(&read_packet(&mut reader).unwrap())).unwrap())
+            .collect();
+        let mut rows: Vec<Row> = vec![];
+        let warnings = loop {
+            let payload = read_packet(&mut reader).unwrap();
+            if payload[0] == 0xfe {
+                let capabilities = CapabilityFlags::CLIENT_PROTOCOL_41 | CapabilityFlags::CLIENT_DEPRECATE_EOF;
+                break OkPacketDeserializer::<ResultSetTerminator>::deserialize(capabilities, &mut ParseBuf(&payload)).unwrap().into_inner().warnings();
+            }
+            let row = match binary {
+                true => RowDeserializer::<ServerSide, Binary>::deserialize(columns.clone(), &mut ParseBuf(&payload)).unwrap().into_inner(),
+                false => RowDeserializer::<(), Text>::deserialize(columns.clone(), &mut ParseBuf(&payload)).unwrap().into_inner(),
+            };
+            rows.push(row);
+        };
+        assert!(reader.is_empty());
+        assert_eq!(2, warnings);
+        assert_eq!(6, columns.len());
+        assert_eq!(("users", "id"), (&*columns[0].table_str(), &*columns[0].name_str()));
+        assert_eq!(ColumnType::MYSQL_TYPE_TIME, columns[5].column_type());
+        assert_eq!(2, rows.len());
+        assert_eq!(Some(1), rows[0].get::<i64, _>(0));
+        assert_eq!(Some(String::from("alice")), rows[0].get::<String, _>(1));
+        assert_eq!(Some(9.5), rows[0].get::<f64, _>(2));
+        assert_eq!(Some(200), rows[0].get::<u8, _>(3));
+        assert_eq!(Value::NULL, rows[1][1]);
+        assert_eq!(Some(7), rows[1].get::<u8, _>(3));
+        if binary {
+            assert_eq!(Value::Date(2023, 1, 2, 3, 4, 5, 0), rows[0][4]);
+            assert_eq!(Value::Time(false, 1, 2, 3, 4, 500_000), rows[0][5]);
+            assert_eq!(Value::Time(true, 0, 0, 0, 1, 0), rows[1][5]);
+        } else {
+            assert_eq!(Value::from("2023-01-02 03:04:05"), rows[0][4]);
+            assert_eq!(Value::from("26:03:04.500000"), rows[0][5]);
+            assert_eq!(Value::from("-00:00:01"), rows[1][5]);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use mysql::Value;
+use redis::aio::Connection;
+use redis::{AsyncCommands, RedisResult};
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use crate::meta::ConfigSnapshot;
+use crate::protocol::result_set::ResultSet;
+use crate::serve::{cache_admission, cache_hint};
+use crate::sys_cache_purge::PurgeTarget;
+use crate::sys_config::VirtDBConfig;
+use crate::{sys_cache_purge, sys_metric, sys_redis, sys_session, sys_shutdown, utils};
+
+//代理自己处理的命令以VIRTDB开头,不会发送给MySQL
+pub const COMMAND_PREFIX: &str = "VIRTDB";
+pub const USAGE: &str = "VIRTDB SHOW STATUS | SHOW CACHE RULES | SHOW CONNECTIONS | PURGE CACHE FOR '<sql>' | PURGE CACHE FOR TABLE [<db>.]<table> | PURGE CACHE FOR TAG '<tag>' | PURGE CACHE FOR CACHE_CONFIG <id> | EXPLAIN CACHE '<sql>'";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AdminCommand {
+    ShowStatus,
+    ShowCacheRules,
+    ShowConnections,
+    PurgeCache(PurgeTarget),
+    ExplainCache(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminResponse {
+    Rows(ResultSet),
+    //删除的缓存数
+    Affected(u64),
+}
+
+/// Whether a statement, with comments removed, is in the `VIRTDB` namespace
+pub fn is_admin_command(sql: &str) -> bool {
+    let sql = sql.trim_start();
+    sql.len() >= COMMAND_PREFIX.len()
+        && sql[..COMMAND_PREFIX.len()].eq_ignore_ascii_case(COMMAND_PREFIX)
+        && sql[COMMAND_PREFIX.len()..].chars().next().is_none_or(|c| c.is_whitespace() || c == ';')
+}
+
+/// Parse a statement accepted by `is_admin_command`
+pub fn parse(sql: &str) -> Result<AdminCommand, String> {
+    let dialect = MySqlDialect {};
+    let tokens: Vec<Token> = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|e| format!("{:?}", e))?
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_) | Token::SemiColon))
+        .collect();
+    let keywords: Vec<String> = tokens.iter()
+        .map(|token| match token {
+            Token::Word(word) if word.quote_style.is_none() => word.value.to_uppercase(),
+            _ => String::new(),
+        })
+        .collect();
+    let keywords: Vec<&str> = keywords.iter().map(|v| v.as_str()).collect();
+    let command = match (&keywords[..], &tokens[..]) {
+        (["VIRTDB", "SHOW", "STATUS"], _) => AdminCommand::ShowStatus,
+        (["VIRTDB", "SHOW", "CACHE", "RULES"], _) => AdminCommand::ShowCacheRules,
+        (["VIRTDB", "SHOW", "CONNECTIONS"], _) => AdminCommand::ShowConnections,
+        (["VIRTDB", "PURGE", "CACHE", "FOR", ""], [.., Token::SingleQuotedString(sql)]) => {
+            AdminCommand::PurgeCache(PurgeTarget::Sql(sql.clone()))
+        }
+        //缓存的表标签不带库名,db.table取表名
+        (["VIRTDB", "PURGE", "CACHE", "FOR", "TABLE", ..], [_, _, _, _, _, name @ ..]) => match name {
+            [Token::Word(table)] | [Token::Word(_), Token::Period, Token::Word(table)] => {
+                AdminCommand::PurgeCache(PurgeTarget::Table(table.value.to_lowercase()))
+            }
+            [Token::SingleQuotedString(table)] => {
+                let table = table.rsplit('.').next().unwrap_or_default();
+                AdminCommand::PurgeCache(PurgeTarget::Table(table.to_lowercase()))
+            }
+            _ => return Err(format!("invalid table name, usage: {}", USAGE)),
+        },
+        (["VIRTDB", "PURGE", "CACHE", "FOR", "TAG", _], [.., last]) => match last {
+            Token::Word(word) => AdminCommand::PurgeCache(PurgeTarget::Tag(word.value.clone())),
+            Token::SingleQuotedString(tag) => AdminCommand::PurgeCache(PurgeTarget::Tag(tag.clone())),
+            _ => return Err(format!("invalid tag, usage: {}", USAGE)),
+        },
+        (["VIRTDB", "PURGE", "CACHE", "FOR", "CACHE_CONFIG", ""], [.., Token::Number(id, _)]) => {
+            let id = id.parse().map_err(|_| format!("invalid cache_config id {}", id))?;
+            AdminCommand::PurgeCache(PurgeTarget::CacheConfig(id))
+        }
+        (["VIRTDB", "EXPLAIN", "CACHE", ""], [.., Token::SingleQuotedString(sql)]) => AdminCommand::ExplainCache(sql.clone()),
+        _ => return Err(format!("unknown command, usage: {}", USAGE)),
+    };
+    Ok(command)
+}
+
+/// Run a command, errors are returned to the client as error packets
+pub async fn execute(command: &AdminCommand, redis_conn: &mut Connection, sys_config: &VirtDBConfig, config_snapshot: &ConfigSnapshot, user: Option<&str>) -> Result<AdminResponse, String> {
+    let response = match command {
+        AdminCommand::ShowStatus => AdminResponse::Rows(show_status(sys_config, config_snapshot)),
+        AdminCommand::ShowCacheRules => AdminResponse::Rows(show_cache_rules(config_snapshot)),
+        AdminCommand::ShowConnections => AdminResponse::Rows(show_connections()),
+        AdminCommand::PurgeCache(target) => {
+            let deleted = sys_cache_purge::purge_with_nodes(sys_config.redis.nodes.clone(), target.clone()).await
+                .map_err(|e| format!("purge cache fail: {}", e))?;
+            info!("purged {} cache entries for {:?} by VIRTDB command", deleted, target);
+            AdminResponse::Affected(deleted as u64)
+        }
+        AdminCommand::ExplainCache(sql) => AdminResponse::Rows(explain_cache(redis_conn, sys_config, config_snapshot, sql, user).await),
+    };
+    Ok(response)
+}
+
+fn name_value(result_set: &mut ResultSet, name: &str, value: impl ToString) {
+    result_set.add_row(vec![Value::from(name), Value::from(value.to_string())]);
+}
+
+fn show_status(sys_config: &VirtDBConfig, config_snapshot: &ConfigSnapshot) -> ResultSet {
+    let mut result_set = ResultSet::new(&["Variable_name", "Value"]);
+    name_value(&mut result_set, "version", env!("CARGO_PKG_VERSION"));
+    name_value(&mut result_set, "config_version", &config_snapshot.version);
+    name_value(&mut result_set, "meta_source", format!("{:?}", sys_config.meta.source).to_lowercase());
+    name_value(&mut result_set, "cache_rules", config_snapshot.cache_config_index.len());
+    name_value(&mut result_set, "rate_limit_rules", config_snapshot.rate_limit_configs.len());
+    name_value(&mut result_set, "firewall_rules", config_snapshot.firewall_rules.len());
+    name_value(&mut result_set, "query_rewrites", config_snapshot.query_rewrites.len());
+    name_value(&mut result_set, "firewall_mode", format!("{:?}", sys_config.firewall.mode).to_lowercase());
+    name_value(&mut result_set, "backend", format!("{}:{}", sys_config.mysql.ip, sys_config.mysql.port));
+    name_value(&mut result_set, "sessions", sys_shutdown::session_count());
+    name_value(&mut result_set, "running_statements", sys_shutdown::running_statement_count());
+    name_value(&mut result_set, "open_transactions", sys_shutdown::open_transaction_count());
+    name_value(&mut result_set, "draining", sys_shutdown::is_draining());
+    name_value(&mut result_set, "cache_compression", &sys_config.cache_compression.codec);
+    name_value(&mut result_set, "cache_buffer_bytes", sys_metric::cache_buffer_bytes());
+    name_value(&mut result_set, "cache_buffer_abandoned", sys_metric::cache_buffer_abandoned());
+    result_set
+}
+
+fn show_cache_rules(config_snapshot: &ConfigSnapshot) -> ResultSet {
+    let mut result_set = ResultSet::new(&["id", "cache_name", "sql_template", "duration", "admission_mode", "timeout_ms", "max_result_bytes"]);
+    for x in config_snapshot.cache_config_index.values() {
+        result_set.add_row(vec![
+            Value::from(x.id),
+            Value::from(x.cache_name.as_str()),
+            Value::from(x.sql_template.as_str()),
+            Value::from(x.duration),
+            Value::from(x.admission_mode.as_str()),
+            Value::from(x.timeout_ms),
+            Value::from(x.max_result_bytes),
+        ]);
+    }
+    result_set
+}
+
+fn show_connections() -> ResultSet {
+    let mut result_set = ResultSet::new(&["id", "client_addr", "user", "schema", "connected_at", "statement", "elapsed_ms", "bytes_in", "bytes_out", "cache_hits", "in_transaction"]);
+    for session in sys_session::list() {
+        let session = session.view();
+        result_set.add_row(vec![
+            Value::from(session.id),
+            Value::from(session.client_addr),
+            Value::from(session.user),
+            Value::from(session.schema),
+            Value::from(session.connected_at),
+            Value::from(session.statement),
+            Value::from(session.elapsed_ms),
+            Value::from(session.bytes_in),
+            Value::from(session.bytes_out),
+            Value::from(session.cache_hits),
+            Value::from(session.in_transaction as u8),
+        ]);
+    }
+    result_set
+}
+
+//与处理SELECT时的判断相同,说明该语句是否会被缓存以及当前的缓存状态
+async fn explain_cache(redis_conn: &mut Connection, sys_config: &VirtDBConfig, config_snapshot: &ConfigSnapshot, origin_sql: &str, user: Option<&str>) -> ResultSet {
+    let mut result_set = ResultSet::new(&["property", "value"]);
+    let sql = utils::sys_sql::remove_comments(origin_sql.to_string());
+    let mut hints = cache_hint::parse(origin_sql);
+    cache_hint::restrict(&mut hints, &sys_config.cache_hint, user);
+    let dialect = MySqlDialect {};
+    let cache_config_entity = config_snapshot.cache_config_index.find_sql(sql.trim(), &dialect);
+    let admission_config = &sys_config.cache_admission;
+
+    let (duration, admission_mode, reason) = if !sql.trim_start().to_uppercase().starts_with("SELECT") {
+        (None, None, String::from("not a SELECT"))
+    } else if hints.no_cache {
+        (None, None, String::from("VIRTDB_NO_CACHE hint"))
+    } else {
+        match (hints.ttl, cache_config_entity) {
+            (Some(ttl), _) => (Some(ttl), Some(cache_admission::ADMISSION_ALWAYS), String::from("VIRTDB_CACHE hint")),
+            (None, Some(x)) => (Some(x.duration), Some(x.admission_mode.as_str()), format!("cache_config #{}", x.id)),
+            (None, None) if admission_config.all_selects => {
+                (Some(admission_config.duration), Some(cache_admission::ADMISSION_ADAPTIVE), String::from("cache_admission.all_selects"))
+            }
+            (None, None) => (None, None, String::from("no matching cache_config")),
+        }
+    };
+    let cache_key = sys_redis::cache_key(&sql);
+    name_value(&mut result_set, "sql", &sql);
+    name_value(&mut result_set, "cacheable", duration.is_some());
+    name_value(&mut result_set, "reason", reason);
+    result_set.add_row(vec![Value::from("cache_config"), Value::from(cache_config_entity.map(|x| format!("#{} {}", x.id, x.cache_name)))]);
+    result_set.add_row(vec![Value::from("duration"), Value::from(duration.map(|v| v.to_string()))]);
+    result_set.add_row(vec![Value::from("admission_mode"), Value::from(admission_mode)]);
+    if duration.is_none() {
+        return result_set;
+    }
+
+    let mut cache_tags = hints.tags.clone();
+    if let Some(x) = cache_config_entity {
+        cache_tags.push(sys_redis::cache_config_tag(x.id));
+    }
+    cache_tags.extend(utils::sys_sql::table_names(&dialect, &sql).iter().map(|table| sys_redis::table_tag(table)));
+    name_value(&mut result_set, "cache_key", &cache_key);
+    name_value(&mut result_set, "tags", cache_tags.join(","));
+    name_value(&mut result_set, "refresh", hints.refresh);
+    //-2表示不存在,-1表示没有过期时间
+    let ttl: RedisResult<i64> = redis_conn.ttl(&cache_key).await;
+    match ttl {
+        Ok(ttl) => {
+            name_value(&mut result_set, "cached", ttl != -2);
+            result_set.add_row(vec![Value::from("ttl_seconds"), Value::from(Some(ttl).filter(|v| *v >= 0))]);
+        }
+        Err(err) => name_value(&mut result_set, "cached", format!("unknown: {}", err)),
+    }
+    result_set
+}
+
+#[test]
+fn test_parse() {
+    assert!(is_admin_command("  virtdb show status"));
+    assert!(is_admin_command("VIRTDB;"));
+    assert!(!is_admin_command("virtdb_show status"));
+    assert!(!is_admin_command("select 1"));
+
+    assert_eq!(Ok(AdminCommand::ShowStatus), parse("virtdb show status;"));
+    assert_eq!(Ok(AdminCommand::ShowCacheRules), parse("VIRTDB SHOW CACHE RULES"));
+    assert_eq!(Ok(AdminCommand::ShowConnections), parse("VIRTDB SHOW CONNECTIONS"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::Sql(String::from("select * from users where name = 'a'")))),
+               parse("VIRTDB PURGE CACHE FOR 'select * from users where name = ''a'''"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::Table(String::from("users")))), parse("VIRTDB PURGE CACHE FOR TABLE Users"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::Table(String::from("users")))), parse("VIRTDB PURGE CACHE FOR TABLE shop.Users"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::Table(String::from("users")))), parse("VIRTDB PURGE CACHE FOR TABLE `shop`.`users`"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::Table(String::from("users")))), parse("VIRTDB PURGE CACHE FOR TABLE 'shop.users'"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::Tag(String::from("tenant:1")))), parse("VIRTDB PURGE CACHE FOR TAG 'tenant:1'"));
+    assert_eq!(Ok(AdminCommand::PurgeCache(PurgeTarget::CacheConfig(12))), parse("VIRTDB PURGE CACHE FOR CACHE_CONFIG 12"));
+    assert_eq!(Ok(AdminCommand::ExplainCache(String::from("select 1"))), parse("virtdb explain cache 'select 1'"));
+    assert!(parse("VIRTDB SHOW TABLES").is_err());
+    assert!(parse("VIRTDB PURGE CACHE FOR 12").is_err());
+    assert!(parse("VIRTDB PURGE CACHE FOR TABLE shop.users.id").is_err());
+}
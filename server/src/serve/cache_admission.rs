@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+use crate::sys_config::CacheAdmissionConfig;
+use crate::utils::sys_fingerprint::{self, SqlFingerprint};
+
+//命中cache_config就缓存
+pub const ADMISSION_ALWAYS: &str = "ALWAYS";
+//只缓存慢查询或高频查询
+pub const ADMISSION_ADAPTIVE: &str = "ADAPTIVE";
+
+//最多跟踪的指纹数,超过时清理过期的窗口
+const MAX_TRACKED_FINGERPRINTS: usize = 100_000;
+
+//指纹 -> (窗口开始时间, 窗口内出现次数)
+static FINGERPRINT_HITS: Lazy<Mutex<HashMap<u64, (Instant, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Count one more occurrence of the fingerprint of `sql`, returns the occurrences in the current window
+pub fn record_hit(sql_fingerprint: Option<&SqlFingerprint>, sql: &str, window: Duration) -> u32 {
+    let digest = sql_fingerprint
+        .map(|f| f.digest)
+        .unwrap_or_else(|| sys_fingerprint::fnv1a(sql.as_bytes()));
+    let mut hits = FINGERPRINT_HITS.lock().unwrap();
+    count_hit(&mut hits, digest, window, Instant::now())
+}
+
+fn count_hit(hits: &mut HashMap<u64, (Instant, u32)>, digest: u64, window: Duration, now: Instant) -> u32 {
+    if hits.len() >= MAX_TRACKED_FINGERPRINTS && !hits.contains_key(&digest) {
+        hits.retain(|_, (start, _)| now.duration_since(*start) < window);
+    }
+    let entry = hits.entry(digest).or_insert((now, 0));
+    //固定窗口,过期后重新计数
+    if now.duration_since(entry.0) >= window {
+        *entry = (now, 0);
+    }
+    entry.1 += 1;
+    entry.1
+}
+
+/// Whether an adaptively admitted result may be stored:
+/// the backend was slow enough, or the fingerprint was seen often enough in the window.
+/// With both thresholds at 0 every result is admitted.
+pub fn is_admitted(admission_config: &CacheAdmissionConfig, hits: u32, mysql_duration: i64) -> bool {
+    let by_duration = admission_config.min_mysql_duration_ms > 0 && mysql_duration >= admission_config.min_mysql_duration_ms;
+    let by_hits = admission_config.min_hits > 0 && hits >= admission_config.min_hits;
+    (admission_config.min_mysql_duration_ms <= 0 && admission_config.min_hits == 0) || by_duration || by_hits
+}
+
+/// Whether a result of `size` bytes fits the cap, 0 means no limit
+pub fn is_within_size(max_result_bytes: usize, size: usize) -> bool {
+    max_result_bytes == 0 || size <= max_result_bytes
+}
+
+#[test]
+fn test_count_hit() {
+    let mut hits = HashMap::new();
+    let window = Duration::from_secs(60);
+    let start = Instant::now();
+    assert_eq!(1, count_hit(&mut hits, 1, window, start));
+    assert_eq!(2, count_hit(&mut hits, 1, window, start + Duration::from_secs(30)));
+    assert_eq!(1, count_hit(&mut hits, 2, window, start + Duration::from_secs(30)));
+    assert_eq!(1, count_hit(&mut hits, 1, window, start + Duration::from_secs(61)));
+}
+
+#[test]
+fn test_is_admitted() {
+    let mut admission_config = CacheAdmissionConfig { min_mysql_duration_ms: 100, min_hits: 5, ..Default::default() };
+    assert!(!is_admitted(&admission_config, 1, 10));
+    assert!(is_admitted(&admission_config, 1, 100));
+    assert!(is_admitted(&admission_config, 5, 10));
+
+    admission_config.min_hits = 0;
+    assert!(!is_admitted(&admission_config, 100, 10));
+    admission_config.min_mysql_duration_ms = 0;
+    assert!(is_admitted(&admission_config, 1, 0));
+
+    assert!(is_within_size(1024, 1024));
+    assert!(!is_within_size(1024, 1025));
+    assert!(is_within_size(0, 1025));
+}
@@ -0,0 +1,113 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::sys_config::CacheCompressionConfig;
+
+//缓存值的头: MAGIC(4) + 版本(1) + 压缩算法(1) + 原始长度(4)
+//MySQL响应的首个包不会以MAGIC开头,没有头的值按未压缩的旧格式读取
+const MAGIC: &[u8; 4] = b"VDBC";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 10;
+
+pub const CODEC_NONE: u8 = 0;
+pub const CODEC_ZSTD: u8 = 1;
+pub const CODEC_LZ4: u8 = 2;
+
+/// Codec id of a configured codec name, unknown names fall back to no compression
+pub fn codec_of(name: &str) -> u8 {
+    match name.to_ascii_lowercase().as_str() {
+        "zstd" => CODEC_ZSTD,
+        "lz4" => CODEC_LZ4,
+        _ => CODEC_NONE,
+    }
+}
+
+/// Encode a response for Redis, compressing it when it is at least `min_bytes` long
+pub fn encode(compression_config: &CacheCompressionConfig, body: &[u8]) -> Vec<u8> {
+    let codec = codec_of(&compression_config.codec);
+    let compressed = if codec == CODEC_NONE || body.len() < compression_config.min_bytes {
+        None
+    } else {
+        compress(codec, compression_config.level, body)
+    };
+    match compressed {
+        //压缩后没有变小的按原样保存
+        Some(compressed) if compressed.len() < body.len() => with_header(codec, body.len(), &compressed),
+        _ => with_header(CODEC_NONE, body.len(), body),
+    }
+}
+
+/// Decode a value read from Redis, values without a header are returned as is
+pub fn decode(value: Vec<u8>) -> Result<Vec<u8>, String> {
+    if value.len() < HEADER_LEN || &value[..4] != MAGIC {
+        return Ok(value);
+    }
+    if value[4] != FORMAT_VERSION {
+        return Err(format!("unsupported cache format version {}", value[4]));
+    }
+    let codec = value[5];
+    let original_len = LittleEndian::read_u32(&value[6..HEADER_LEN]) as usize;
+    let payload = &value[HEADER_LEN..];
+    let body = match codec {
+        CODEC_NONE => payload.to_vec(),
+        CODEC_ZSTD => zstd::bulk::decompress(payload, original_len).map_err(|e| format!("zstd decompress fail:{:?}", e))?,
+        CODEC_LZ4 => lz4_flex::block::decompress(payload, original_len).map_err(|e| format!("lz4 decompress fail:{:?}", e))?,
+        _ => return Err(format!("unknown cache codec {}", codec)),
+    };
+    if body.len() != original_len {
+        return Err(format!("cache length mismatch, expected {} got {}", original_len, body.len()));
+    }
+    Ok(body)
+}
+
+fn compress(codec: u8, level: i32, body: &[u8]) -> Option<Vec<u8>> {
+    match codec {
+        CODEC_ZSTD => match zstd::bulk::compress(body, level) {
+            Ok(compressed) => Some(compressed),
+            Err(err) => {
+                warn!("zstd compress fail.err:{:?}", err);
+                None
+            }
+        },
+        CODEC_LZ4 => Some(lz4_flex::block::compress(body)),
+        _ => None,
+    }
+}
+
+fn with_header(codec: u8, original_len: usize, payload: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(HEADER_LEN + payload.len());
+    value.extend_from_slice(MAGIC);
+    value.push(FORMAT_VERSION);
+    value.push(codec);
+    let mut len = [0; 4];
+    LittleEndian::write_u32(&mut len, original_len as u32);
+    value.extend_from_slice(&len);
+    value.extend_from_slice(payload);
+    value
+}
+
+#[test]
+fn test_encode_decode() {
+    let body: Vec<u8> = b"\x01\x00\x00\x01\x01".iter().chain(b"def,users,name,".repeat(200).iter()).cloned().collect();
+    let mut compression_config = CacheCompressionConfig::default();
+    for codec in ["zstd", "lz4", "none"] {
+        compression_config.codec = codec.to_string();
+        let value = encode(&compression_config, &body);
+        assert_eq!(codec_of(codec), value[5]);
+        if codec != "none" {
+            assert!(value.len() < body.len());
+        }
+        assert_eq!(body, decode(value).unwrap());
+    }
+
+    //小于阈值的不压缩
+    compression_config.codec = "zstd".to_string();
+    compression_config.min_bytes = body.len() + 1;
+    assert_eq!(CODEC_NONE, encode(&compression_config, &body)[5]);
+
+    //没有头的旧缓存
+    assert_eq!(body, decode(body.clone()).unwrap());
+
+    let mut corrupted = encode(&CacheCompressionConfig::default(), &body);
+    corrupted.truncate(20);
+    assert!(decode(corrupted).is_err());
+}
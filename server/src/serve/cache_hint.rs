@@ -0,0 +1,137 @@
+use crate::sys_config::CacheHintConfig;
+use crate::utils;
+
+pub const HINT_PREFIX: &str = "VIRTDB_";
+//不走缓存,直接转发
+pub const HINT_NO_CACHE: &str = "VIRTDB_NO_CACHE";
+//缓存该语句,ttl单位为秒,没有匹配的cache_config时也生效
+pub const HINT_CACHE: &str = "VIRTDB_CACHE";
+//跳过缓存读取,用MySQL的结果覆盖缓存
+pub const HINT_REFRESH: &str = "VIRTDB_REFRESH";
+//给缓存打标签,用于按标签清除
+pub const HINT_TAG: &str = "VIRTDB_TAG";
+
+/// Cache-control hints in `/*+ ... */` comments of a statement
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CacheHints {
+    pub no_cache: bool,
+    pub ttl: Option<i32>,
+    pub refresh: bool,
+    pub tags: Vec<String>,
+    //无法识别的hint,写入审计日志
+    pub warnings: Vec<String>,
+}
+
+/// Parse the `VIRTDB_*` hints of a statement, other optimizer hints are ignored.
+pub fn parse(sql: &str) -> CacheHints {
+    let mut hints = CacheHints::default();
+    if !sql.contains("/*+") {
+        return hints;
+    }
+    for (name, args) in utils::sys_hint::parse_hints(sql) {
+        if !name.starts_with(HINT_PREFIX) {
+            continue;
+        }
+        match name.as_str() {
+            HINT_NO_CACHE => hints.no_cache = true,
+            HINT_REFRESH => hints.refresh = true,
+            HINT_CACHE => match parse_ttl(&args) {
+                Some(ttl) => hints.ttl = Some(ttl),
+                None => hints.warnings.push(format!("invalid hint {}({}), expected ttl=<seconds>", name, args)),
+            },
+            HINT_TAG => {
+                let tags: Vec<&str> = args.split(',').map(|t| t.trim()).collect();
+                if tags.iter().all(|t| is_valid_tag(t)) {
+                    hints.tags.extend(tags.into_iter().map(String::from));
+                } else {
+                    hints.warnings.push(format!("invalid hint {}({}), tags may only contain letters, digits, '_', '-' and ':'", name, args));
+                }
+            }
+            _ => hints.warnings.push(format!("unknown hint {}", name)),
+        }
+    }
+    if hints.no_cache && (hints.ttl.is_some() || hints.refresh) {
+        hints.warnings.push(format!("{} overrides {} and {}", HINT_NO_CACHE, HINT_CACHE, HINT_REFRESH));
+        hints.ttl = None;
+        hints.refresh = false;
+    }
+    hints
+}
+
+/// Drop the hints that skip cache admission unless `user` is allowed to use them, and cap the ttl
+pub fn restrict(hints: &mut CacheHints, hint_config: &CacheHintConfig, user: Option<&str>) {
+    if hints.ttl.is_none() && !hints.refresh {
+        return;
+    }
+    if !user.is_some_and(|user| hint_config.users.iter().any(|u| u == user)) {
+        hints.warnings.push(format!("{} and {} are not allowed for user {:?}", HINT_CACHE, HINT_REFRESH, user.unwrap_or_default()));
+        hints.ttl = None;
+        hints.refresh = false;
+        return;
+    }
+    if let Some(ttl) = hints.ttl.filter(|ttl| *ttl > hint_config.max_ttl) {
+        hints.warnings.push(format!("{} ttl {} is capped to {}", HINT_CACHE, ttl, hint_config.max_ttl));
+        hints.ttl = Some(hint_config.max_ttl);
+    }
+}
+
+//`ttl=30`或`30`
+fn parse_ttl(args: &str) -> Option<i32> {
+    let value = match args.split_once('=') {
+        Some((key, value)) if key.trim().eq_ignore_ascii_case("ttl") => value,
+        Some(_) => return None,
+        None => args,
+    };
+    value.trim().parse::<i32>().ok().filter(|ttl| *ttl > 0)
+}
+
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == ':')
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(CacheHints::default(), parse("select * from users where id = 1"));
+    assert_eq!(CacheHints::default(), parse("select /*+ MAX_EXECUTION_TIME(100) */ * from users"));
+
+    let hints = parse("select /*+ VIRTDB_CACHE(ttl=30) virtdb_tag(users, tenant:1) */ * from users");
+    assert_eq!(Some(30), hints.ttl);
+    assert_eq!(vec!["users".to_string(), "tenant:1".to_string()], hints.tags);
+    assert!(hints.warnings.is_empty());
+
+    assert!(parse("select /*+ VIRTDB_NO_CACHE */ * from users").no_cache);
+    assert!(parse("select /*+ VIRTDB_REFRESH */ * from users").refresh);
+    assert_eq!(Some(60), parse("select /*+ VIRTDB_CACHE(60) */ 1").ttl);
+
+    let hints = parse("select /*+ VIRTDB_CACHE(ttl=abc) VIRTDB_TAG(a b) VIRTDB_NOCACHE */ 1");
+    assert_eq!(None, hints.ttl);
+    assert!(hints.tags.is_empty());
+    assert_eq!(3, hints.warnings.len());
+
+    let hints = parse("select /*+ VIRTDB_NO_CACHE VIRTDB_REFRESH */ 1");
+    assert!(hints.no_cache && !hints.refresh);
+    assert_eq!(1, hints.warnings.len());
+}
+
+#[test]
+fn test_restrict() {
+    let hint_config = CacheHintConfig { users: vec![String::from("app")], max_ttl: 60 };
+    let sql = "select /*+ VIRTDB_CACHE(ttl=600) VIRTDB_REFRESH VIRTDB_TAG(users) */ * from users";
+
+    let mut hints = parse(sql);
+    restrict(&mut hints, &hint_config, Some("app"));
+    assert_eq!(Some(60), hints.ttl);
+    assert!(hints.refresh);
+    assert_eq!(1, hints.warnings.len());
+
+    let mut hints = parse(sql);
+    restrict(&mut hints, &hint_config, Some("guest"));
+    assert_eq!(None, hints.ttl);
+    assert!(!hints.refresh);
+    assert_eq!(vec![String::from("users")], hints.tags);
+    assert_eq!(1, hints.warnings.len());
+
+    //字符串中的hint不生效
+    let hints = parse("select '/*+ VIRTDB_CACHE(ttl=60) */' from users");
+    assert_eq!(None, hints.ttl);
+}
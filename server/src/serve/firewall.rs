@@ -0,0 +1,225 @@
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use crate::meta::FirewallRuleEntity;
+use crate::sys_config::{FirewallConfig, FirewallMode};
+use crate::utils;
+
+//DELETE/UPDATE没有WHERE条件
+pub const RULE_TYPE_DENY_NO_WHERE: &str = "DENY_NO_WHERE";
+//DROP/TRUNCATE
+pub const RULE_TYPE_DENY_DDL: &str = "DENY_DDL";
+//匹配SQL模板的语句
+pub const RULE_TYPE_DENY_PATTERN: &str = "DENY_PATTERN";
+//白名单,learning/lock模式下生效
+pub const RULE_TYPE_ALLOW_PATTERN: &str = "ALLOW_PATTERN";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FirewallBlock {
+    //0表示不在白名单中
+    pub rule_id: i32,
+    pub reason: String,
+    //只记录日志,不拦截
+    pub dry_run: bool,
+}
+
+/// Check a statement sent by `user` against the firewall rules.
+/// Returns the first rule that blocks it, or None if it may be forwarded.
+pub fn check(rules: &[FirewallRuleEntity], firewall_config: &FirewallConfig, user: Option<&str>, sql: &str) -> Option<FirewallBlock> {
+    let dialect = MySqlDialect {};
+    let rules: Vec<&FirewallRuleEntity> = rules.iter()
+        .filter(|rule| rule.users.is_empty() || user.map(|u| rule.users.iter().any(|v| v == u)).unwrap_or(false))
+        .collect();
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => utils::sys_sql::trim_tokens(tokens),
+        Err(err) => {
+            debug!("firewall can not tokenize sql:{:?},err:{:?}", sql, err);
+            return uninspectable(&rules, firewall_config, &err.message);
+        }
+    };
+
+    //多语句时逐条检查
+    for statement in split_statements(&tokens) {
+        if statement.is_empty() {
+            continue;
+        }
+        if let Some(block) = check_statement(&rules, firewall_config, user, statement) {
+            return Some(block);
+        }
+    }
+    None
+}
+
+/// Check a statement that can not be parsed, e.g. a COM_QUERY that is not UTF-8.
+/// It is blocked by the first deny rule, or by lock mode, because it can not be matched against them.
+pub fn check_uninspectable(rules: &[FirewallRuleEntity], firewall_config: &FirewallConfig, user: Option<&str>, reason: &str) -> Option<FirewallBlock> {
+    let rules: Vec<&FirewallRuleEntity> = rules.iter()
+        .filter(|rule| rule.users.is_empty() || user.map(|u| rule.users.iter().any(|v| v == u)).unwrap_or(false))
+        .collect();
+    uninspectable(&rules, firewall_config, reason)
+}
+
+//无法判断是否命中拒绝规则,有拒绝规则时按命中处理
+fn uninspectable(rules: &[&FirewallRuleEntity], firewall_config: &FirewallConfig, reason: &str) -> Option<FirewallBlock> {
+    let deny_rule = rules.iter()
+        .find(|rule| matches!(rule.rule_type.as_str(), RULE_TYPE_DENY_NO_WHERE | RULE_TYPE_DENY_DDL | RULE_TYPE_DENY_PATTERN));
+    if let Some(rule) = deny_rule {
+        return Some(FirewallBlock {
+            rule_id: rule.id,
+            reason: format!("statement can not be inspected: {}", reason),
+            dry_run: rule.dry_run || firewall_config.dry_run,
+        });
+    }
+    //与check_statement相同,没有适用于该用户的白名单时不锁定
+    let locked = firewall_config.mode == FirewallMode::Lock && rules.iter().any(|rule| rule.rule_type == RULE_TYPE_ALLOW_PATTERN);
+    if !locked {
+        return None;
+    }
+    Some(FirewallBlock {
+        rule_id: 0,
+        reason: format!("statement can not be inspected: {}", reason),
+        dry_run: firewall_config.dry_run,
+    })
+}
+
+fn check_statement(rules: &[&FirewallRuleEntity], firewall_config: &FirewallConfig, user: Option<&str>, statement: &[Token]) -> Option<FirewallBlock> {
+    let dialect = MySqlDialect {};
+    let statement_sql = statement.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(" ");
+    let first_keyword = match statement.first() {
+        Some(Token::Word(w)) => w.keyword,
+        _ => Keyword::NoKeyword,
+    };
+
+    for rule in rules {
+        let reason = match rule.rule_type.as_str() {
+            RULE_TYPE_DENY_NO_WHERE => {
+                if (first_keyword == Keyword::DELETE || first_keyword == Keyword::UPDATE) && !has_top_level_where(statement) {
+                    Some(format!("{:?} without WHERE", first_keyword))
+                } else {
+                    None
+                }
+            }
+            RULE_TYPE_DENY_DDL => {
+                if first_keyword == Keyword::DROP || first_keyword == Keyword::TRUNCATE {
+                    Some(format!("{:?} is not allowed", first_keyword))
+                } else {
+                    None
+                }
+            }
+            RULE_TYPE_DENY_PATTERN => {
+                if utils::sys_sql::is_pattern_match(&rule.cached_sql_parser_token, statement_sql.trim(), &dialect) {
+                    Some(String::from("statement matches a denied pattern"))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            return Some(FirewallBlock {
+                rule_id: rule.id,
+                reason,
+                dry_run: rule.dry_run || firewall_config.dry_run,
+            });
+        }
+    }
+
+    if firewall_config.mode == FirewallMode::Off {
+        return None;
+    }
+    let allow_rules: Vec<&&FirewallRuleEntity> = rules.iter()
+        .filter(|rule| rule.rule_type == RULE_TYPE_ALLOW_PATTERN)
+        .collect();
+    //没有适用于该用户的白名单时不锁定
+    if allow_rules.is_empty() {
+        return None;
+    }
+    let allowed = allow_rules.iter()
+        .any(|rule| utils::sys_sql::is_pattern_match(&rule.cached_sql_parser_token, statement_sql.trim(), &dialect));
+    if allowed {
+        return None;
+    }
+    if firewall_config.mode == FirewallMode::Learning {
+        info!("[firewall learning]user {:?} sent a statement not on the allowlist:{}", user, statement_sql);
+        return None;
+    }
+    Some(FirewallBlock {
+        rule_id: 0,
+        reason: String::from("statement is not on the allowlist"),
+        dry_run: firewall_config.dry_run,
+    })
+}
+
+fn split_statements(tokens: &[Token]) -> Vec<&[Token]> {
+    tokens.split(|t| *t == Token::SemiColon).collect()
+}
+
+//子查询中的WHERE不算
+fn has_top_level_where(statement: &[Token]) -> bool {
+    let mut depth = 0;
+    for token in statement {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Word(w) if depth == 0 && w.keyword == Keyword::WHERE => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+fn rule(id: i32, rule_type: &str, sql_template: &str, users: Vec<&str>) -> FirewallRuleEntity {
+    FirewallRuleEntity {
+        id,
+        rule_type: rule_type.to_string(),
+        sql_template: sql_template.to_string(),
+        users: users.into_iter().map(String::from).collect(),
+        dry_run: false,
+        cached_sql_parser_token: utils::sys_sql::template_tokens(&MySqlDialect {}, sql_template),
+    }
+}
+
+#[test]
+fn test_deny_rules() {
+    let rules = vec![
+        rule(1, RULE_TYPE_DENY_NO_WHERE, "", vec![]),
+        rule(2, RULE_TYPE_DENY_DDL, "", vec!["app"]),
+        rule(3, RULE_TYPE_DENY_PATTERN, "select * from user where password = ?", vec![]),
+    ];
+    let config = FirewallConfig::default();
+    let blocked_rule = |user: &str, sql: &str| check(&rules, &config, Some(user), sql).map(|b| b.rule_id);
+
+    assert_eq!(Some(1), blocked_rule("app", "delete from article"));
+    assert_eq!(Some(1), blocked_rule("app", "update article set a = 1 where id in (select id from t where b = 2) ; update t set c = 1"));
+    assert_eq!(None, blocked_rule("app", "update article set a = (select 1 from t where b = 2) where id = 3"));
+    assert_eq!(Some(2), blocked_rule("app", "select 1; DROP TABLE article"));
+    assert_eq!(None, blocked_rule("dba", "truncate table article"));
+    assert_eq!(Some(3), blocked_rule("dba", "SELECT * FROM user WHERE password = 'x'"));
+    assert_eq!(None, blocked_rule("app", "select * from article where id = 1"));
+    // the tokenizer fails on `'\_'`, MySQL does not
+    assert_eq!(Some(1), blocked_rule("app", r"select '\_'; drop table t"));
+    assert_eq!(Some(1), check_uninspectable(&rules, &config, Some("dba"), "not UTF-8").map(|b| b.rule_id));
+    assert_eq!(None, check_uninspectable(&[], &config, Some("app"), "not UTF-8"));
+}
+
+#[test]
+fn test_lock_mode() {
+    let rules = vec![rule(1, RULE_TYPE_ALLOW_PATTERN, "select * from article where id = ?", vec!["app"])];
+    let mut config = FirewallConfig { mode: FirewallMode::Lock, ..Default::default() };
+
+    assert_eq!(None, check(&rules, &config, Some("app"), "select * from article where id = 1"));
+    assert_eq!(Some(0), check(&rules, &config, Some("app"), "select * from article").map(|b| b.rule_id));
+    // no allowlist for other users
+    assert_eq!(None, check(&rules, &config, Some("dba"), "select * from article"));
+
+    // statements the tokenizer can not read are not let through
+    assert_eq!(Some(0), check(&rules, &config, Some("app"), "select * from article where id = '1").map(|b| b.rule_id));
+    assert_eq!(Some(0), check_uninspectable(&rules, &config, Some("app"), "not UTF-8").map(|b| b.rule_id));
+    assert_eq!(None, check_uninspectable(&rules, &config, Some("dba"), "not UTF-8"));
+
+    config.mode = FirewallMode::Learning;
+    assert_eq!(None, check(&rules, &config, Some("app"), "select * from article"));
+    assert_eq!(None, check(&rules, &config, Some("app"), "select * from article where id = '1"));
+}
@@ -2,8 +2,8 @@
 use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Local};
 // use mysql_common::proto::codec::CompDecoder::Packet;
 
@@ -13,15 +13,33 @@ use sqlparser::dialect::MySqlDialect;
 use tokio::io as async_io;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
 use tokio::net::{TcpListener as AsyncTcpListener, TcpStream as AsyncTcpStream};
-use tokio::sync::{mpsc, Mutex, MutexGuard};
+use tokio::sync::{mpsc, Mutex, MutexGuard, OwnedSemaphorePermit};
 use tokio::sync::mpsc::Sender;
 use crate::meta::CacheConfigEntity;
 // use crate::protocol::{Packet, PacketType};
 use crate::sys_assistant_client::{CacheTaskInfo, ExecLog};
-use crate::sys_config::VirtDBConfig;
-use crate::{meta, utils};
-use crate::protocol::{Packet, PacketType};
-use crate::utils::sys_sql::sql_to_pattern;
+use crate::sys_audit_log;
+use crate::sys_audit_log::AuditLog;
+use crate::sys_config::{FirewallMode, VirtDBConfig};
+use crate::sys_redis;
+use crate::sys_metric;
+use crate::sys_cache_warm;
+use crate::{meta, sys_config, sys_session, sys_shutdown, utils};
+use crate::protocol;
+use crate::protocol::{CLIENT_DEPRECATE_EOF, CLIENT_SSL, Packet, PacketType, SERVER_STATUS_IN_TRANS};
+use crate::serve::admin_command::AdminResponse;
+use crate::protocol::response::{ResponseKind, ResponseTracker};
+use crate::serve::rate_limiter::LimitSubject;
+use crate::utils::sys_fingerprint::{fingerprint, SqlFingerprint};
+
+pub mod admin_command;
+pub mod cache_admission;
+pub mod cache_codec;
+pub mod cache_hint;
+pub mod firewall;
+pub mod query_rewrite;
+pub mod query_timeout;
+pub mod rate_limiter;
 
 const BUFFER_SIZE: usize = 8 * 1024;
 
@@ -43,6 +61,174 @@ pub struct ProxyContext {
     pub total_duration: i64,
     pub mysql_duration: i64,
     pub skip: bool,//不做任何处理,纯代理
+    pub throttled: bool,//被限流拒绝
+    pub rewrite_rule_id: Option<i32>,//命中的改写规则,ctx.sql为改写后的SQL
+    pub rows: u64,//返回的行数
+    pub bytes: u64,//返回给客户端的字节数
+    pub error_code: Option<u16>,
+    pub statement_id: u64,//连接内的语句序号,从1开始
+    pub cache_timeout_ms: i32,//命中的cache_config配置的超时时间
+    pub forwarded: bool,//已经有响应数据发送给客户端
+    pub timed_out: bool,//超时,丢弃MySQL的响应并返回超时错误
+    pub cache_key: Option<String>,//缓存的redis key
+    pub cache_tags: Vec<String>,//VIRTDB_TAG指定的标签
+    pub hint_warnings: Vec<String>,//无法识别的hint
+    pub adaptive_admission: bool,//只有慢查询或高频查询的结果才写入缓存
+    pub fingerprint_hits: u32,//窗口内同一指纹出现的次数
+    pub cache_max_bytes: usize,//可缓存的最大结果,超过后不再暂存,0表示不限制
+    pub config_version: String,//处理该语句时生效的规则版本
+    pub server_status: Option<u16>,//MySQL响应中最后的状态
+    pub sql_fingerprint: Option<SqlFingerprint>,//handle_request中计算,查找cache_config和记录执行日志共用
+}
+
+impl ProxyContext {
+    //记录响应的统计信息
+    pub fn record_response(&mut self, tracker: &ResponseTracker) {
+        self.rows = tracker.rows;
+        self.bytes = tracker.bytes;
+        self.error_code = tracker.error_code;
+        self.server_status = tracker.server_status;
+    }
+}
+
+//为写缓存暂存的响应,字节数计入指标
+struct ResponseBuffer {
+    buf: Vec<u8>,
+}
+
+impl ResponseBuffer {
+    fn new() -> ResponseBuffer {
+        ResponseBuffer { buf: vec![] }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        sys_metric::add_cache_buffer_bytes(data.len() as i64);
+    }
+
+    fn clear(&mut self) {
+        sys_metric::add_cache_buffer_bytes(-(self.buf.len() as i64));
+        //大结果用完后归还内存
+        if self.buf.capacity() > BUFFER_SIZE * 16 {
+            self.buf = vec![];
+        } else {
+            self.buf.clear();
+        }
+    }
+}
+
+impl Drop for ResponseBuffer {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+//只暂存需要写缓存的响应,超过上限后放弃缓存,剩余的数据直接转发
+fn buffer_response(ctx: &mut ProxyContext, cached_buf: &mut ResponseBuffer, data: &[u8]) {
+    if !ctx.should_update_cache || ctx.skip {
+        return;
+    }
+    if ctx.cache_max_bytes > 0 && cached_buf.buf.len() + data.len() > ctx.cache_max_bytes {
+        debug!("response exceeds {} bytes, stop buffering. sql:{:?}", ctx.cache_max_bytes, ctx.sql);
+        ctx.should_update_cache = false;
+        cached_buf.clear();
+        sys_metric::incr_cache_buffer_abandoned();
+        return;
+    }
+    cached_buf.extend(data);
+}
+
+//响应结束,超时的语句在这里补上错误包
+fn finish_response(ctx: &mut ProxyContext, tracker: &ResponseTracker, out: &mut Vec<u8>) {
+    ctx.record_response(tracker);
+    if ctx.timed_out {
+        let msg = String::from("Query execution was interrupted, maximum statement execution time exceeded");
+        out.extend_from_slice(&Packet::error_packet(query_timeout::ER_QUERY_TIMEOUT, *b"HY000", msg).bytes);
+        ctx.error_code = Some(query_timeout::ER_QUERY_TIMEOUT);
+        ctx.should_update_cache = false;
+    }
+}
+
+//超时后在旁路连接上执行KILL QUERY,连接已经关闭或语句已经结束时什么都不做
+async fn watch_statement_timeout(conn_handler: Weak<Mutex<VirtDBConnectionHandler>>, statement_id: u64, timeout_ms: u64) {
+    tokio::time::sleep(Duration::from_millis(timeout_ms)).await;
+    let conn_handler = match conn_handler.upgrade() {
+        None => return,
+        Some(conn_handler) => conn_handler,
+    };
+    let mut conn_handler = conn_handler.lock().await;
+    if conn_handler.running_statement_id != Some(statement_id) {
+        return;
+    }
+    conn_handler.timed_out_statement_id = Some(statement_id);
+    let backend_connection_id = conn_handler.backend_connection_id;
+    let mysql_config = conn_handler.server_config.mysql.clone();
+    warn!("client {} user {:?} statement exceeds {}ms, backend connection:{:?}",
+        conn_handler.client_addr, conn_handler.client_user, timeout_ms, backend_connection_id);
+    drop(conn_handler);
+    if let Some(backend_connection_id) = backend_connection_id {
+        query_timeout::kill_query(&mysql_config, backend_connection_id).await;
+    }
+}
+
+//等待限流时释放连接的锁,避免阻塞该连接的响应转发和超时检查
+async fn process_request<'a>(conn_handler_lock: &'a Mutex<VirtDBConnectionHandler>,
+                             mut conn_handler: MutexGuard<'a, VirtDBConnectionHandler>,
+                             ctx: &mut ProxyContext,
+                             packet_type: PacketType) -> (Action, MutexGuard<'a, VirtDBConnectionHandler>) {
+    if let Some(action) = conn_handler.check_request(ctx, packet_type).await {
+        return (action, conn_handler);
+    }
+    if packet_type == PacketType::ComQuery || packet_type == PacketType::ComStmtExecute {
+        let config_snapshot = conn_handler.config_snapshot.clone();
+        let (client_addr, client_user) = (conn_handler.client_addr, conn_handler.client_user.clone());
+        drop(conn_handler);
+        let subject = LimitSubject {
+            user: client_user.as_deref(),
+            ip: client_addr.ip(),
+            sql: if packet_type == PacketType::ComQuery { ctx.sql.as_deref() } else { None },
+        };
+        let limit_result = rate_limiter::acquire(&config_snapshot.rate_limit_configs, &subject).await;
+        conn_handler = conn_handler_lock.lock().await;
+        match limit_result {
+            Ok(permits) => {
+                conn_handler.statement_permits = permits;
+            }
+            Err(err) => {
+                warn!("client {} user {:?} throttled by rule {}: {}", client_addr, client_user, err.rule_id, err.reason);
+                ctx.throttled = true;
+                ctx.error_code = Some(1226);
+                let msg = format!("Too many queries, rejected by virt-db rate limit rule #{}: {}", err.rule_id, err.reason);
+                return (Action::RESPONSED(Packet::error_packet(1226, *b"42000", msg).bytes), conn_handler);
+            }
+        }
+    }
+    let action = conn_handler.handle_request(ctx, packet_type).await;
+    (action, conn_handler)
+}
+
+//pipelining时前面的命令还在等待MySQL的响应,本地返回的结果要排在这些响应之后
+async fn wait_statements_finished<'a>(conn_handler_lock: &'a Mutex<VirtDBConnectionHandler>,
+                                      mut conn_handler: MutexGuard<'a, VirtDBConnectionHandler>) -> MutexGuard<'a, VirtDBConnectionHandler> {
+    while conn_handler.running_statement_id.is_some() {
+        drop(conn_handler);
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        conn_handler = conn_handler_lock.lock().await;
+    }
+    conn_handler
+}
+
+//取出缓冲区中完整的命令,不完整的部分留到下次读取
+fn take_commands(command_buf: &mut Vec<u8>) -> Vec<Packet> {
+    let mut packets = vec![];
+    while let Some(len) = protocol::command_len(command_buf) {
+        if len == command_buf.len() {
+            packets.push(Packet::new(std::mem::take(command_buf)));
+            break;
+        }
+        packets.push(Packet::new(command_buf.drain(..len).collect()));
+    }
+    packets
 }
 
 pub async fn handle_client(
@@ -50,6 +236,10 @@ pub async fn handle_client(
     remote_addr: SocketAddr,
     conn_handler: VirtDBConnectionHandler,
 ) {
+    let _session_guard = sys_shutdown::SessionGuard::new();
+    let session_handle = conn_handler.session_handle.clone();
+    let session_handle_a = session_handle.clone();
+    let session_handle_b = session_handle.clone();
     let mut remote_stream = match AsyncTcpStream::connect(remote_addr).await {
         Ok(stream) => stream,
         Err(e) => {
@@ -75,79 +265,160 @@ pub async fn handle_client(
         let mut buf = [0; BUFFER_SIZE];
         let mut r_buf = ReadBuf::new(&mut buf);
         let conn_handler_wrapper_a = conn_handler_wrapper_a;
+        //当前数据包剩余未读取的字节数(超过BUFFER_SIZE的数据包会分多次读取)
+        let mut packet_remaining: usize = 0;
+        //超过一次读取的命令包,读取完整后再检查和转发
+        let mut command_buf: Vec<u8> = vec![];
         loop {
             match client_reader.read_buf(&mut r_buf).await {
                 Ok(n) => {
                     if n == 0 {
                         return Ok(());
                     }
-                    let mut ctx = ProxyContext {
-                        sql: None,
-                        should_update_cache: false,
-                        fn_start_time: Instant::now(),
-                        mysql_exec_start_time: None,
-                        redis_duration: 0,
-                        from_cache: false,
-                        cache_duration: 0,
-                        total_duration: 0,
-                        mysql_duration: 0,
-                        skip: false,
-                    };
-                    let data = r_buf.filled();
-
-                    // info!("data:{:?}",String::from_utf8_lossy(data));
-                    let packet = Packet::new(data.to_vec());
-                    if let Err(_) = packet.packet_type() {
-                        remote_writer.write_all(data).await?;
+                    session_handle_a.add_bytes_in(n);
+                    if packet_remaining > 0 {
+                        packet_remaining = packet_remaining.saturating_sub(n);
+                        remote_writer.write_all(r_buf.filled()).await?;
+                        r_buf.clear();
                         continue;
                     }
-                    let packet_type = packet.packet_type().unwrap();
-                    let bytes = &data[5..n];
-                    let sql_result = String::from_utf8(bytes.to_vec());
-                    if let Ok(sql) = sql_result {
-                        ctx.sql = Some(sql.clone());
-                        // info!("current sql:{:?}",sql);
+                    if command_buf.is_empty() {
+                        let data = r_buf.filled();
+                        // info!("data:{:?}",String::from_utf8_lossy(data));
+                        let mut conn_handler = conn_handler_wrapper_a.lock().await;
+                        //命令包的sequence_id总是0,其他的是握手认证阶段的数据包,认证成功之前的数据包都直接转发给MySQL
+                        if n < 5 || data[3] != 0 || conn_handler.tls || !conn_handler.authenticated {
+                            if n >= 4 {
+                                let payload_len = (data[0] as usize) | (data[1] as usize) << 8 | (data[2] as usize) << 16;
+                                packet_remaining = (payload_len + 4).saturating_sub(n);
+                            }
+                            conn_handler.handle_handshake_response(&Packet::new(data.to_vec()));
+                            //lock模式下不转发无法检查的连接
+                            if conn_handler.tls && conn_handler.server_config.firewall.mode == FirewallMode::Lock {
+                                warn!("client {} uses SSL or an old protocol, rejected in firewall lock mode", conn_handler.client_addr);
+                                drop(conn_handler);
+                                let mut error_packet = Packet::error_packet(1227, *b"42000", String::from("SSL connections are not allowed by the virt-db firewall in lock mode"));
+                                error_packet.bytes[3] = 2;
+                                client_writer_lock_a.lock().await.write_all(&error_packet.bytes).await?;
+                                return Ok(());
+                            }
+                            drop(conn_handler);
+                            remote_writer.write_all(data).await?;
+                            r_buf.clear();
+                            continue;
+                        }
                     }
-                    let mut conn_handler = conn_handler_wrapper_a.lock().await;
-                    let action = conn_handler.handle_request(&mut ctx, packet_type).await;
-
-                    let skip = match action {
-                        Action::FORWARD => {
-                            ctx.mysql_exec_start_time = Some(Instant::now());
-                            // info!("before send. current sql:{:?}",ctx.sql.clone());
-                            ctx_sender.send(ctx).await.expect("send ctx fail");
-                            false
+                    command_buf.extend_from_slice(r_buf.filled());
+                    r_buf.clear();
+                    //客户端可能不等响应就发送多个命令(pipelining),每个命令分别检查和转发
+                    for packet in take_commands(&mut command_buf) {
+                        let mut ctx = ProxyContext {
+                            sql: None,
+                            should_update_cache: false,
+                            fn_start_time: Instant::now(),
+                            mysql_exec_start_time: None,
+                            redis_duration: 0,
+                            from_cache: false,
+                            cache_duration: 0,
+                            total_duration: 0,
+                            mysql_duration: 0,
+                            skip: false,
+                            throttled: false,
+                            rewrite_rule_id: None,
+                            rows: 0,
+                            bytes: 0,
+                            error_code: None,
+                            statement_id: 0,
+                            cache_timeout_ms: 0,
+                            forwarded: false,
+                            timed_out: false,
+                            cache_key: None,
+                            cache_tags: vec![],
+                            hint_warnings: vec![],
+                            adaptive_admission: false,
+                            fingerprint_hits: 0,
+                            cache_max_bytes: 0,
+                            config_version: String::new(),
+                            server_status: None,
+                            sql_fingerprint: None,
+                        };
+                        let data = packet.bytes.as_slice();
+                        let conn_handler = conn_handler_wrapper_a.lock().await;
+                        let packet_type = match packet.packet_type() {
+                            Ok(packet_type) => packet_type,
+                            Err(_) => {
+                                drop(conn_handler);
+                                remote_writer.write_all(data).await?;
+                                continue;
+                            }
+                        };
+                        if let Ok(sql) = String::from_utf8(protocol::command_payload(data)[1..].to_vec()) {
+                            ctx.sql = Some(sql);
                         }
-                        Action::DROP => {
-                            conn_handler.handle_response(&mut ctx);
-                            conn_handler.handle_remote_response_finished(ctx.clone(),&vec![]).await;
-                            true
-                        },
-                        Action::RESPONSED(mut bytes) => {
-                            let data = bytes.as_mut_slice();
-                            let mut client_writer = client_writer_lock_a.lock().await;
-                            // println!("sql:{:?},value from cache:true", sql.clone());
-                            // println!("sql:{:?},cache_v:{:X?}", sql.clone(), String::from_utf8_lossy(&*cache_v.clone()));
-                            let r = client_writer.write_all(data).await;
-                            if let Err(err) = r {
-                                info!("write to client fail.err:{:?}", err);
+                        let (action, mut conn_handler) = process_request(&conn_handler_wrapper_a, conn_handler, &mut ctx, packet_type).await;
+                        let rewritten_packet = match (ctx.rewrite_rule_id, ctx.sql.as_deref()) {
+                            (Some(_), Some(sql)) => Some(Packet::command(packet_type, sql.as_bytes())),
+                            _ => None,
+                        };
+
+                        let skip = match action {
+                            Action::FORWARD => {
+                                let response_kind = ResponseKind::of(packet_type);
+                                if response_kind != ResponseKind::None {
+                                    ctx.mysql_exec_start_time = Some(Instant::now());
+                                    let tracker = ResponseTracker::new(response_kind, conn_handler.capability_flags & CLIENT_DEPRECATE_EOF != 0);
+                                    ctx.statement_id = conn_handler.start_statement();
+                                    if packet_type == PacketType::ComQuery || packet_type == PacketType::ComStmtExecute {
+                                        let sql_option = match packet_type {
+                                            PacketType::ComQuery => ctx.sql.as_deref(),
+                                            _ => None,
+                                        };
+                                        let timeout_ms = query_timeout::resolve_timeout_ms(&conn_handler.server_config.query_timeout, conn_handler.client_user.as_deref(), ctx.cache_timeout_ms, sql_option);
+                                        if timeout_ms > 0 {
+                                            tokio::spawn(watch_statement_timeout(Arc::downgrade(&conn_handler_wrapper_a), ctx.statement_id, timeout_ms));
+                                        }
+                                    }
+                                    drop(conn_handler);
+                                    // info!("before send. current sql:{:?}",ctx.sql.clone());
+                                    ctx_sender.send((ctx, tracker)).await.expect("send ctx fail");
+                                }
+                                false
                             }
+                            Action::DROP => {
+                                conn_handler.handle_response(&mut ctx);
+                                conn_handler.handle_remote_response_finished(ctx.clone(),&vec![]).await;
+                                true
+                            },
+                            Action::RESPONSED(mut bytes) => {
+                                let data = bytes.as_mut_slice();
+                                let mut conn_handler = wait_statements_finished(&conn_handler_wrapper_a, conn_handler).await;
+                                let mut client_writer = client_writer_lock_a.lock().await;
+                                // println!("sql:{:?},value from cache:true", sql.clone());
+                                // println!("sql:{:?},cache_v:{:X?}", sql.clone(), String::from_utf8_lossy(&*cache_v.clone()));
+                                let r = client_writer.write_all(data).await;
+                                if let Err(err) = r {
+                                    info!("write to client fail.err:{:?}", err);
+                                }
+                                session_handle_a.add_bytes_out(data.len());
+
+                                ctx.bytes = data.len() as u64;
+                                conn_handler.handle_response(&mut ctx);
+                                conn_handler.handle_remote_response_finished(ctx.clone(),&data.to_vec()).await;
 
-                            conn_handler.handle_response(&mut ctx);
-                            conn_handler.handle_remote_response_finished(ctx.clone(),&data.to_vec()).await;
+                                true
+                            }
+                        };
 
-                            true
+                        if skip {
+                            continue;
                         }
-                    };
 
-                    if skip {
-                        r_buf.clear();
-                        continue;
+                        // println!("Received from client: {:?},type:{:#?}", String::from_utf8_lossy(&*buf[..n].to_vec()), &buf[0]);
+                        match rewritten_packet {
+                            Some(packet) => remote_writer.write_all(&packet.bytes).await?,
+                            None => remote_writer.write_all(data).await?,
+                        }
                     }
-
-                    // println!("Received from client: {:?},type:{:#?}", String::from_utf8_lossy(&*buf[..n].to_vec()), &buf[0]);
-                    remote_writer.write_all(data).await?;
-                    r_buf.clear();
                 }
                 Err(e) => {
                     info!("client_to_remote:{:#?}", e);
@@ -161,8 +432,8 @@ pub async fn handle_client(
     let remote_to_client = async move {
         let mut buf = [0; BUFFER_SIZE];
         let mut r_buf = ReadBuf::new(&mut buf);
-        let mut cached_buf = vec![];
-        let mut cached_ctx: Option<Arc<Mutex<ProxyContext>>> = None;
+        let mut cached_buf = ResponseBuffer::new();
+        let mut current: Option<(ProxyContext, ResponseTracker)> = None;
         let client_writer_lock = client_writer_lock_b;
         let conn_handler_wrapper_b = conn_handler_wrapper_b;
         loop {
@@ -175,49 +446,66 @@ pub async fn handle_client(
 
                     // info!("Received from remote: {:X?}", String::from_utf8_lossy(data).to_string());
 
-                    if let Ok(current_ctx) = ctx_receiver.try_recv() {
-                        //清理上次保存的记录
-                        // info!("recv new ctx:{:?},mysql_start_time:{:?},old exists:{:?}", current_ctx.sql,current_ctx.mysql_exec_start_time, cached_ctx.is_some());
-                        if let Some(old_ctx) = cached_ctx.clone() {
-                            let mut conn_handler = conn_handler_wrapper_b.lock().await;
-                            let old_ctx = old_ctx.lock().await;
-                            let old_ctx = old_ctx.deref();
-                            conn_handler.handle_remote_response_finished(old_ctx.clone(), &cached_buf).await;
+                    let mut conn_handler = conn_handler_wrapper_b.lock().await;
+                    let mut pos = 0;
+                    //发送给客户端的数据,超时语句的响应会被丢弃
+                    let mut out = Vec::with_capacity(data.len());
+                    loop {
+                        //pipelining时客户端不等响应就发送下一条命令,当前的响应结束后才取下一个ctx
+                        if current.is_none() {
+                            if let Ok(next) = ctx_receiver.try_recv() {
+                                current = Some(next);
+                            }
+                        }
+                        if pos >= data.len() {
+                            break;
+                        }
+                        //握手认证阶段的数据包没有对应的ctx
+                        let finished = match current.as_mut() {
+                            None => {
+                                let start = out.len();
+                                out.extend_from_slice(&data[pos..]);
+                                conn_handler.handle_server_handshake(&mut out[start..]);
+                                conn_handler.handle_server_auth_result(&data[pos..]);
+                                break;
+                            }
+                            Some((ctx, tracker)) => {
+                                let consumed = tracker.feed(&data[pos..]);
+                                buffer_response(ctx, &mut cached_buf, &data[pos..pos + consumed]);
+                                if !ctx.forwarded && conn_handler.timed_out_statement_id == Some(ctx.statement_id) {
+                                    ctx.timed_out = true;
+                                }
+                                if !ctx.timed_out {
+                                    out.extend_from_slice(&data[pos..pos + consumed]);
+                                    ctx.forwarded = true;
+                                }
+                                //handle partial response
+                                conn_handler.handle_response(ctx);
+                                pos += consumed;
+                                tracker.is_finished()
+                            }
+                        };
+                        if finished {
+                            let (mut ctx, tracker) = current.take().unwrap();
+                            finish_response(&mut ctx, &tracker, &mut out);
+                            conn_handler.handle_remote_response_finished(ctx, &cached_buf.buf).await;
                             cached_buf.clear();
                         }
-                        // info!("new ctx:{:?}", current_ctx);
-                        cached_ctx = Some(Arc::new(Mutex::new(current_ctx)));
-                    }
-
-                    cached_buf.extend_from_slice(data);
-
-                    if let Some(old_ctx) = cached_ctx.clone() {
-                        let mut old_ctx = old_ctx.lock().await;
-                        // if old_ctx.should_update_cache {
-                        //     if let Some(_) = old_ctx.sql.clone() {
-                        //         if old_ctx.should_update_cache {
-                        //             cached_buf.extend_from_slice(data);
-                        //             // println!("cache response local");
-                        //         }
-                        //     }
-                        // }
-                        //handle partial response
-                        let mut conn_handler = conn_handler_wrapper_b.lock().await;
-                        let tmp_ctx = old_ctx.deref_mut();
-                        conn_handler.handle_response(tmp_ctx);
                     }
-
+                    //先占用client_writer再释放连接的锁,等待中的本地响应排在这次的数据之后
+                    let mut client_writer = client_writer_lock.lock().await;
+                    drop(conn_handler);
 
                     let error_extra_msg = format!(
                         "client_writer write_all() fail.remote write to client.ctx:{:?},v:{:?}",
-                        cached_ctx.clone(),
+                        current.as_ref().map(|(ctx, _)| ctx),
                         String::from_utf8_lossy(data)
                     );
-                    let mut client_writer = client_writer_lock.lock().await;
                     client_writer
-                        .write_all(data)
+                        .write_all(&out)
                         .await
                         .expect(&*error_extra_msg);
+                    session_handle_b.add_bytes_out(out.len());
 
                     r_buf.clear();
                 }
@@ -229,16 +517,45 @@ pub async fn handle_client(
         }
     };
 
-    tokio::select! {
+    let (close_error, killed) = tokio::select! {
         result = client_to_remote => {
             if let Err(e) = result {
                 error!("Error transferring client to remote: {}", e);
             }
+            (None, false)
         }
         result = remote_to_client => {
             if let Err(e) = result {
                 error!("Error transferring remote to client: {}", e);
             }
+            (None, false)
+        }
+        _ = sys_shutdown::wait_closing() => {
+            (Some(Packet::error_packet(sys_shutdown::ER_SERVER_SHUTDOWN, sys_shutdown::SHUTDOWN_SQL_STATE, String::from(sys_shutdown::SHUTDOWN_MESSAGE))), false)
+        }
+        _ = session_handle.wait_killed() => {
+            (Some(Packet::error_packet(sys_session::ER_CONNECTION_KILLED, sys_session::KILLED_SQL_STATE, String::from(sys_session::KILLED_MESSAGE))), true)
+        }
+    };
+    //被kill时同时停止MySQL上执行中的语句,关闭连接后MySQL不一定会立即发现
+    if killed {
+        let conn_handler = conn_handler.lock().await;
+        info!("session {} of client {} user {:?} killed", conn_handler.session_id, conn_handler.client_addr, conn_handler.client_user);
+        if let (Some(_), Some(backend_connection_id)) = (conn_handler.running_statement_id, conn_handler.backend_connection_id) {
+            let mysql_config = conn_handler.server_config.mysql.clone();
+            drop(conn_handler);
+            query_timeout::kill_query(&mysql_config, backend_connection_id).await;
+        }
+    }
+    //停止服务或被kill时告知客户端连接将被关闭,正在返回结果时写入错误包会破坏协议,直接关闭
+    let close_error = match close_error {
+        Some(_) if conn_handler.lock().await.running_statement_id.is_some() => None,
+        close_error => close_error,
+    };
+    if let Some(error_packet) = close_error {
+        let mut client_writer = client_writer_lock.lock().await;
+        if let Err(err) = client_writer.write_all(&error_packet.bytes).await {
+            debug!("write close error to client fail.err:{:?}", err);
         }
     }
 }
@@ -246,36 +563,240 @@ pub async fn handle_client(
 pub struct VirtDBConnectionHandler {
     pub redis_conn: Connection,
     dialect: MySqlDialect,
-    pub server_config: VirtDBConfig,
+    pub server_config: Arc<VirtDBConfig>,
     pub exec_log_channel_sender: Sender<ExecLog>,
     pub cache_load_task_channel_sender: Sender<CacheTaskInfo>,
+    pub audit_log_channel_sender: Sender<AuditLog>,
+    pub client_addr: SocketAddr,
+    pub client_user: Option<String>,
+    pub client_schema: Option<String>,
+    pub capability_flags: u32,
+    handshake_received: bool,
+    //MySQL返回认证成功的OK包之后才处理客户端的命令
+    authenticated: bool,
+    //客户端启用了SSL,数据包无法解析,只做纯代理
+    pub tls: bool,
+    //当前语句占用的并发限流名额,响应结束后释放
+    statement_permits: Vec<OwnedSemaphorePermit>,
+    //MySQL握手包中的连接id,用于KILL QUERY
+    pub backend_connection_id: Option<u32>,
+    next_statement_id: u64,
+    //正在执行的语句,用于判断超时
+    running_statement_id: Option<u64>,
+    timed_out_statement_id: Option<u64>,
+    //sys_session中的连接id
+    pub session_id: u64,
+    //流量统计和kill通知
+    pub session_handle: Arc<sys_session::SessionHandle>,
+    //当前语句使用的规则
+    config_snapshot: Arc<meta::ConfigSnapshot>,
+    //COM_INIT_DB或USE切换的数据库,成功后更新client_schema
+    pending_schema: Option<String>,
+    //最后一个响应的状态中有SERVER_STATUS_IN_TRANS
+    in_transaction: bool,
+}
+
+//连接在语句执行中断开时,语句不会再有结束的响应
+impl Drop for VirtDBConnectionHandler {
+    fn drop(&mut self) {
+        if self.running_statement_id.is_some() {
+            sys_shutdown::statement_finished();
+        }
+        if self.in_transaction {
+            sys_shutdown::transaction_changed(false);
+        }
+        sys_session::unregister(self.session_id);
+    }
 }
 
 impl VirtDBConnectionHandler {
     pub fn new(redis_conn: Connection,
-               server_config: VirtDBConfig,
+               server_config: Arc<VirtDBConfig>,
                exec_log_channel_sender: Sender<ExecLog>,
-               cache_load_task_channel_sender: Sender<CacheTaskInfo>, ) -> VirtDBConnectionHandler {
+               cache_load_task_channel_sender: Sender<CacheTaskInfo>,
+               audit_log_channel_sender: Sender<AuditLog>,
+               client_addr: SocketAddr, ) -> VirtDBConnectionHandler {
+        let (session_id, session_handle) = sys_session::register(client_addr);
         VirtDBConnectionHandler {
             redis_conn,
             dialect: MySqlDialect {},
             server_config,
             exec_log_channel_sender,
             cache_load_task_channel_sender,
+            audit_log_channel_sender,
+            client_addr,
+            client_user: None,
+            client_schema: None,
+            capability_flags: 0,
+            handshake_received: false,
+            authenticated: false,
+            tls: false,
+            statement_permits: vec![],
+            backend_connection_id: None,
+            next_statement_id: 0,
+            running_statement_id: None,
+            timed_out_statement_id: None,
+            session_id,
+            session_handle,
+            config_snapshot: meta::current_snapshot(),
+            pending_schema: None,
+            in_transaction: false,
         }
     }
 
-    pub async fn handle_request(&mut self, ctx: &mut ProxyContext, packet_type: PacketType) -> Action {
+    //解析MySQL发送的第一个数据包,获取连接id
+    //lock模式下去掉CLIENT_SSL,SSL连接无法检查,ssl-mode为PREFERRED的客户端会改用明文连接
+    pub fn handle_server_handshake(&mut self, data: &mut [u8]) {
+        if self.backend_connection_id.is_some() {
+            return;
+        }
+        let mut packet = Packet::new(data.to_vec());
+        self.backend_connection_id = packet.server_connection_id();
+        if self.server_config.firewall.mode == FirewallMode::Lock && packet.clear_server_capability(CLIENT_SSL) {
+            data.copy_from_slice(&packet.bytes);
+        }
+        let backend_connection_id = self.backend_connection_id;
+        sys_session::update(self.session_id, |session| session.backend_connection_id = backend_connection_id);
+        debug!("client {} backend connection id:{:?}", self.client_addr, self.backend_connection_id);
+    }
+
+    //语句发送给MySQL前调用,返回语句序号
+    pub fn start_statement(&mut self) -> u64 {
+        if self.running_statement_id.is_none() {
+            sys_shutdown::statement_started();
+        }
+        self.next_statement_id += 1;
+        self.running_statement_id = Some(self.next_statement_id);
+        self.next_statement_id
+    }
+
+    //解析客户端发送的第一个数据包(HandshakeResponse),获取用户名和数据库
+    pub fn handle_handshake_response(&mut self, packet: &Packet) {
+        if self.handshake_received || packet.bytes.len() < 4 || packet.sequence_id() != 1 {
+            return;
+        }
+        self.handshake_received = true;
+        match packet.handshake_response() {
+            Some(handshake_response) => {
+                debug!("client {} login as {:?}", self.client_addr, handshake_response.username);
+                self.capability_flags = handshake_response.capability_flags;
+                self.client_user = Some(handshake_response.username);
+                self.client_schema = handshake_response.database;
+                let (user, schema) = (self.client_user.clone(), self.client_schema.clone());
+                sys_session::update(self.session_id, |session| {
+                    session.user = user;
+                    session.schema = schema;
+                });
+            }
+            None => {
+                info!("client {} uses SSL or an old protocol, pass through only", self.client_addr);
+                self.tls = true;
+            }
+        }
+    }
+
+    //认证阶段MySQL返回的数据包,客户端发送握手响应后收到OK包即认证成功
+    pub fn handle_server_auth_result(&mut self, data: &[u8]) {
+        if self.authenticated || !self.handshake_received || self.tls {
+            return;
+        }
+        let mut pos = 0;
+        while pos + 4 < data.len() {
+            let payload_len = (data[pos] as usize) | (data[pos + 1] as usize) << 8 | (data[pos + 2] as usize) << 16;
+            if data[pos + 4] == 0x00 {
+                debug!("client {} user {:?} authenticated", self.client_addr, self.client_user);
+                self.authenticated = true;
+                return;
+            }
+            pos += payload_len + 4;
+        }
+    }
+
+    //限流之前的检查,返回Some时直接使用该结果
+    pub async fn check_request(&mut self, ctx: &mut ProxyContext, packet_type: PacketType) -> Option<Action> {
         ctx.fn_start_time = Instant::now();
+        //重新加载配置后,已有的连接从下一个语句开始使用新配置
+        self.server_config = sys_config::current_config();
+        //停止服务时不再执行新的语句,事务中的语句继续转发,客户端可以提交或回滚
+        if sys_shutdown::is_draining() && !self.in_transaction && matches!(packet_type, PacketType::ComQuery | PacketType::ComStmtPrepare | PacketType::ComStmtExecute) {
+            ctx.error_code = Some(sys_shutdown::ER_SERVER_SHUTDOWN);
+            return Some(Action::RESPONSED(Packet::error_packet(sys_shutdown::ER_SERVER_SHUTDOWN, sys_shutdown::SHUTDOWN_SQL_STATE, String::from(sys_shutdown::SHUTDOWN_MESSAGE)).bytes));
+        }
+        //同一个语句只使用一个版本的规则
+        self.config_snapshot = meta::current_snapshot();
+        let config_snapshot = self.config_snapshot.clone();
+        ctx.config_version = config_snapshot.version.clone();
+        self.pending_schema = match packet_type {
+            PacketType::ComInitDb => ctx.sql.clone(),
+            PacketType::ComQuery => ctx.sql.as_deref().and_then(sys_session::use_schema),
+            _ => None,
+        };
+        if packet_type == PacketType::ComQuery {
+            if let Some(origin_sql) = ctx.sql.as_deref() {
+                let statement = (origin_sql.to_string(), ctx.fn_start_time);
+                sys_session::update(self.session_id, |session| session.statement = Some(statement));
+                //VIRTDB管理命令由代理自己处理,不经过防火墙,改写和限流
+                let sql = utils::sys_sql::remove_comments(String::from(origin_sql));
+                if admin_command::is_admin_command(&sql) {
+                    return Some(self.handle_admin_command(ctx, &sql, &config_snapshot).await);
+                }
+            }
+        }
+
+        //与其他阶段一样匹配去掉注释后的SQL,改写后的语句同样经过防火墙检查
+        if packet_type == PacketType::ComQuery {
+            if let Some(origin_sql) = ctx.sql.as_deref() {
+                let sql = utils::sys_sql::remove_comments(String::from(origin_sql));
+                if let Some((rule_id, rewritten_sql)) = query_rewrite::rewrite(&config_snapshot.query_rewrites, &sql) {
+                    info!("client {} user {:?} query rewritten by rule {}, origin sql:{:?}, rewritten sql:{:?}",
+                        self.client_addr, self.client_user, rule_id, origin_sql, rewritten_sql);
+                    ctx.sql = Some(rewritten_sql);
+                    ctx.rewrite_rule_id = Some(rule_id);
+                }
+            }
+        }
 
+        if packet_type == PacketType::ComQuery || packet_type == PacketType::ComStmtPrepare {
+            let block_option = match ctx.sql.as_deref() {
+                Some(origin_sql) => {
+                    let sql = utils::sys_sql::remove_comments(String::from(origin_sql));
+                    firewall::check(&config_snapshot.firewall_rules, &self.server_config.firewall, self.client_user.as_deref(), &sql)
+                }
+                None => firewall::check_uninspectable(&config_snapshot.firewall_rules, &self.server_config.firewall, self.client_user.as_deref(), "not UTF-8"),
+            };
+            if let Some(block) = block_option {
+                warn!("client {} user {:?} blocked by firewall rule {}(dry_run:{}): {}, sql:{:?}",
+                    self.client_addr, self.client_user, block.rule_id, block.dry_run, block.reason, ctx.sql);
+                if !block.dry_run {
+                    ctx.error_code = Some(1227);
+                    let msg = format!("Statement blocked by virt-db firewall rule #{}: {}", block.rule_id, block.reason);
+                    return Some(Action::RESPONSED(Packet::error_packet(1227, *b"42000", msg).bytes));
+                }
+            }
+        }
+
+        None
+    }
+
+    //限流之后的处理,读取缓存
+    pub async fn handle_request(&mut self, ctx: &mut ProxyContext, packet_type: PacketType) -> Action {
+        let config_snapshot = self.config_snapshot.clone();
         if let None = ctx.sql {
             return Action::FORWARD;
         }
         let origin_sql = ctx.sql.clone().unwrap();
         let sql = utils::sys_sql::remove_comments(origin_sql.clone());
+        //每个语句只解析一次
+        ctx.sql_fingerprint = fingerprint(sql.trim());
         // info!("origin_sql:{:?},sql:{:?}",origin_sql, sql.clone());
         let result_action = match packet_type {
             PacketType::ComQuery => {
+                let mut hints = cache_hint::parse(&origin_sql);
+                cache_hint::restrict(&mut hints, &self.server_config.cache_hint, self.client_user.as_deref());
+                if !hints.warnings.is_empty() {
+                    warn!("client {} user {:?} sent invalid hints:{:?}, sql:{:?}", self.client_addr, self.client_user, hints.warnings, origin_sql);
+                    ctx.hint_warnings = hints.warnings.clone();
+                }
                 if !sql.clone().to_uppercase().starts_with("SELECT") {
                     return Action::FORWARD;
                 }
@@ -291,57 +812,97 @@ impl VirtDBConnectionHandler {
                     return Action::FORWARD;
                 }
 
-                let cache_config_entity_list = meta::get_cache_config_entity_list();
+                if hints.no_cache {
+                    return Action::FORWARD;
+                }
 
                 let mysql_dialect = MySqlDialect {};
-                let mut cache_config_entity_option: Option<&CacheConfigEntity> = None;
-                for entity in cache_config_entity_list.into_iter() {
-                    if utils::sys_sql::is_pattern_match(
-                        &entity.cached_sql_parser_token,
-                        sql.to_uppercase().trim(),
-                        &mysql_dialect,
-                    ) {
-                        cache_config_entity_option = Some(entity);
-                        break;
+                //同一个指纹用于查找cache_config和统计出现次数
+                let cache_config_entity_option: Option<&CacheConfigEntity> = config_snapshot.cache_config_index
+                    .find_fingerprint(sql.trim(), ctx.sql_fingerprint.as_ref(), &mysql_dialect);
+                trace!("cache_config_entity_option:{:?}",cache_config_entity_option);
+                //记录具体语句,用于缓存预热,预热连接自己的语句带VIRTDB_REFRESH,不再记录
+                if let Some(cache_config_entity) = cache_config_entity_option.filter(|_| !hints.refresh) {
+                    sys_cache_warm::record_query(cache_config_entity.id, self.client_user.as_deref(), self.client_schema.as_deref(), &sql);
+                }
+                //VIRTDB_CACHE的ttl优先,没有匹配的cache_config时也缓存
+                let admission_config = &self.server_config.cache_admission;
+                ctx.cache_duration = match (hints.ttl, cache_config_entity_option) {
+                    (Some(ttl), _) => ttl,
+                    (None, Some(cache_config_entity)) => {
+                        ctx.adaptive_admission = cache_config_entity.admission_mode == cache_admission::ADMISSION_ADAPTIVE;
+                        cache_config_entity.duration
+                    }
+                    (None, None) if admission_config.all_selects => {
+                        ctx.adaptive_admission = true;
+                        admission_config.duration
+                    }
+                    (None, None) => return Action::FORWARD,
+                };
+                ctx.cache_max_bytes = admission_config.max_result_bytes;
+                if let Some(cache_config_entity) = cache_config_entity_option {
+                    ctx.cache_timeout_ms = cache_config_entity.timeout_ms;
+                    if cache_config_entity.max_result_bytes > 0 {
+                        ctx.cache_max_bytes = cache_config_entity.max_result_bytes as usize;
                     }
                 }
-                trace!("cache_config_entity_option:{:?}",cache_config_entity_option);
-                if cache_config_entity_option.is_none() {
+                //只在需要写缓存时统计指纹出现次数
+                let window = Duration::from_secs(admission_config.window_seconds);
+                let record_hit = |ctx: &mut ProxyContext| {
+                    if ctx.adaptive_admission && admission_config.min_hits > 0 {
+                        ctx.fingerprint_hits = cache_admission::record_hit(ctx.sql_fingerprint.as_ref(), &sql, window);
+                    }
+                };
+                let cache_key = sys_redis::cache_key(&sql);
+                ctx.cache_key = Some(cache_key.clone());
+                ctx.cache_tags = hints.tags;
+                if let Some(cache_config_entity) = cache_config_entity_option {
+                    ctx.cache_tags.push(sys_redis::cache_config_tag(cache_config_entity.id));
+                }
+                if hints.refresh {
+                    ctx.should_update_cache = true;
+                    record_hit(ctx);
                     return Action::FORWARD;
                 }
 
                 let redis_get_start_time = Instant::now();
-                let cache_key = format!("cache:\"{}\"", sql);
                 let cache_exists_check_result: RedisResult<bool> = self.redis_conn.exists(cache_key.clone()).await;
                 if let Err(_) = cache_exists_check_result {
                     // println!("continue2");
-                    ctx.cache_duration = cache_config_entity_option.unwrap().duration;
                     ctx.redis_duration = (Instant::now() - redis_get_start_time).as_millis() as i64;
                     return Action::FORWARD;
                 }
                 let is_exists = cache_exists_check_result.unwrap();
                 if !is_exists {
                     ctx.should_update_cache = true;
-                    ctx.cache_duration = cache_config_entity_option.unwrap().duration;
+                    record_hit(ctx);
                     ctx.redis_duration = (Instant::now() - redis_get_start_time).as_millis() as i64;
                     // println!("continue3");
                     return Action::FORWARD;
                 }
 
                 let cache_v_result: RedisResult<Vec<u8>> =
-                    self.redis_conn.get(cache_key).await;
+                    self.redis_conn.get(cache_key.clone()).await;
                 if let Err(_) = cache_v_result {
                     // println!("continue4");
-                    ctx.cache_duration = cache_config_entity_option.unwrap().duration;
                     ctx.redis_duration = (Instant::now() - redis_get_start_time).as_millis() as i64;
                     return Action::FORWARD;
                 }
 
-                let cache_v = cache_v_result.unwrap();
+                //解压失败按未命中处理,用MySQL的结果覆盖
+                let cache_v = match cache_codec::decode(cache_v_result.unwrap()) {
+                    Ok(cache_v) => cache_v,
+                    Err(err) => {
+                        warn!("decode cache fail. for key:{:?},err:{}", cache_key, err);
+                        ctx.should_update_cache = true;
+                        record_hit(ctx);
+                        ctx.redis_duration = (Instant::now() - redis_get_start_time).as_millis() as i64;
+                        return Action::FORWARD;
+                    }
+                };
 
                 if cache_v.len() < 1 {
                     // println!("continue5");
-                    ctx.cache_duration = cache_config_entity_option.unwrap().duration;
                     ctx.redis_duration = (Instant::now() - redis_get_start_time).as_millis() as i64;
                     return Action::FORWARD;
                 }
@@ -367,6 +928,35 @@ impl VirtDBConnectionHandler {
         result_action
     }
 
+    async fn handle_admin_command(&mut self, ctx: &mut ProxyContext, sql: &str, config_snapshot: &meta::ConfigSnapshot) -> Action {
+        let allowed = self.authenticated && self.client_user.as_ref()
+            .is_some_and(|user| self.server_config.admin_command.users.contains(user));
+        if !allowed {
+            warn!("client {} user {:?} is not allowed to run {:?}", self.client_addr, self.client_user, sql);
+            ctx.error_code = Some(1227);
+            let msg = String::from("Access denied; VIRTDB commands are restricted to admin_command.users");
+            return Action::RESPONSED(Packet::error_packet(1227, *b"42000", msg).bytes);
+        }
+        let result = match admin_command::parse(sql) {
+            Ok(command) => admin_command::execute(&command, &mut self.redis_conn, &self.server_config, config_snapshot, self.client_user.as_deref()).await,
+            Err(err) => {
+                ctx.error_code = Some(1064);
+                return Action::RESPONSED(Packet::error_packet(1064, *b"42000", err).bytes);
+            }
+        };
+        match result {
+            Ok(AdminResponse::Rows(result_set)) => {
+                ctx.rows = result_set.rows.len() as u64;
+                Action::RESPONSED(result_set.encode_text(self.capability_flags & CLIENT_DEPRECATE_EOF != 0))
+            }
+            Ok(AdminResponse::Affected(affected_rows)) => Action::RESPONSED(Packet::ok_packet(affected_rows).bytes),
+            Err(err) => {
+                ctx.error_code = Some(1105);
+                Action::RESPONSED(Packet::error_packet(1105, *b"HY000", err).bytes)
+            }
+        }
+    }
+
     //处理大数据包拆分的单个数据包
     pub fn handle_response(&mut self, ctx: &mut ProxyContext) {
         // info!("mysql_exec_start_time:{:?}",ctx.mysql_exec_start_time);
@@ -379,8 +969,47 @@ impl VirtDBConnectionHandler {
         }
     }
 
-    pub async fn handle_remote_response_finished(&mut self, ctx: ProxyContext, full_response: &Vec<u8>) {
+    //结果大小不超过上限,adaptive模式下还需要是慢查询或高频查询
+    fn is_cache_admitted(&self, ctx: &ProxyContext, size: usize) -> bool {
+        let admission_config = &self.server_config.cache_admission;
+        if !cache_admission::is_within_size(ctx.cache_max_bytes, size) {
+            debug!("result of {} bytes exceeds max_result_bytes, not cached. sql:{:?}", size, ctx.sql);
+            return false;
+        }
+        if ctx.adaptive_admission && !cache_admission::is_admitted(admission_config, ctx.fingerprint_hits, ctx.mysql_duration) {
+            trace!("result not admitted. mysql_duration:{},hits:{},sql:{:?}", ctx.mysql_duration, ctx.fingerprint_hits, ctx.sql);
+            return false;
+        }
+        true
+    }
+
+    pub async fn handle_remote_response_finished(&mut self, mut ctx: ProxyContext, full_response: &Vec<u8>) {
         // info!("handle_remote_response_finished,sql:{:?},total_duration:{:?},mysql_duration:{:?},redis_duration:{:?},start_time:{:?}",ctx.sql,ctx.total_duration,ctx.mysql_duration,ctx.redis_duration,ctx.mysql_exec_start_time);
+        self.statement_permits.clear();
+        if self.running_statement_id == Some(ctx.statement_id) {
+            self.running_statement_id = None;
+            sys_shutdown::statement_finished();
+        }
+        if let (Some(schema), None) = (self.pending_schema.take(), ctx.error_code) {
+            self.client_schema = Some(schema);
+        }
+        let schema = self.client_schema.clone();
+        let in_transaction = ctx.server_status.map(|status| status & SERVER_STATUS_IN_TRANS != 0);
+        if let Some(in_transaction) = in_transaction.filter(|in_transaction| *in_transaction != self.in_transaction) {
+            self.in_transaction = in_transaction;
+            sys_shutdown::transaction_changed(in_transaction);
+        }
+        let from_cache = ctx.from_cache;
+        sys_session::update(self.session_id, |session| {
+            session.statement = None;
+            session.schema = schema;
+            if from_cache {
+                session.cache_hits += 1;
+            }
+            if let Some(in_transaction) = in_transaction {
+                session.in_transaction = in_transaction;
+            }
+        });
         if let None = ctx.sql {
             return;
         }
@@ -393,7 +1022,33 @@ impl VirtDBConnectionHandler {
         // info!("sql:{:?},mysql_duration:{:?},redis_duration:{:?},mysql_exec_start_time:{:?},total_duration:{:?}",ctx.sql.clone(),mysql_duration,ctx.redis_duration,ctx.mysql_exec_start_time,total_duration);
 
         let sql = ctx.sql.clone().unwrap();
-        if ctx.should_update_cache && !ctx.skip {
+        //有hint警告的语句总是记录
+        let has_warnings = self.server_config.audit_log.enabled && !ctx.hint_warnings.is_empty();
+        if has_warnings || sys_audit_log::should_record(&self.server_config.audit_log, total_duration) {
+            let audit_log = AuditLog {
+                time: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+                client_addr: self.client_addr.to_string(),
+                user: self.client_user.clone(),
+                schema: self.client_schema.clone(),
+                backend: format!("{}:{}", self.server_config.mysql.ip, self.server_config.mysql.port),
+                sql: sql.clone(),
+                total_duration,
+                mysql_duration,
+                rows: ctx.rows,
+                bytes: ctx.bytes,
+                from_cache: ctx.from_cache,
+                error_code: ctx.error_code,
+                throttled: ctx.throttled,
+                rewrite_rule_id: ctx.rewrite_rule_id,
+                config_version: ctx.config_version.clone(),
+                warnings: ctx.hint_warnings.clone(),
+            };
+            if let Err(err) = self.audit_log_channel_sender.send(audit_log).await {
+                warn!("Send AuditLog fail.err:{:?}", err);
+            }
+        }
+        let admitted = ctx.should_update_cache && !ctx.skip && self.is_cache_admitted(&ctx, full_response.len());
+        if let (true, Some(cache_key)) = (admitted, ctx.cache_key.clone()) {
             // let cache_key = format!("cache:\"{}\"", sql.clone());
             // let cache_v = full_response.as_slice();
             // println!("save remote response.sql:{:?},v:{:?}", sql.clone(),String::from_utf8_lossy(cache_v));
@@ -403,7 +1058,10 @@ impl VirtDBConnectionHandler {
             //     .await;
             //or
             let cache_v = full_response.as_slice().to_vec();
-            let send_result = self.cache_load_task_channel_sender.send(CacheTaskInfo::new(sql.clone(), cache_v, ctx.cache_duration)).await;
+            //按表清除缓存用的标签
+            let mut cache_tags = ctx.cache_tags.clone();
+            cache_tags.extend(utils::sys_sql::table_names(&self.dialect, &sql).iter().map(|table| sys_redis::table_tag(table)));
+            let send_result = self.cache_load_task_channel_sender.send(CacheTaskInfo::new(cache_key, cache_v, ctx.cache_duration, cache_tags)).await;
             match send_result {
                 Ok(_) => {}
                 Err(err) => {
@@ -412,14 +1070,18 @@ impl VirtDBConnectionHandler {
             }
         }
 
-        //只记录select
-        if let Some(sql_pattern) = sql_to_pattern(sql.clone().as_str()) {
+        //按指纹聚合,无法解析的SQL不记录,被拦截或限流的语句没有经过handle_request,这里再计算
+        let sql_fingerprint = ctx.sql_fingerprint.take().or_else(|| fingerprint(sql.trim()));
+        if let Some(sql_fingerprint) = sql_fingerprint {
             let exec_log = ExecLog {
-                sql_str: sql_pattern,
+                sql_digest: sql_fingerprint.digest_hex(),
+                sql_str: sql_fingerprint.pattern,
                 total_duration,
                 mysql_duration,
                 redis_duration: ctx.redis_duration,
                 from_cache: ctx.from_cache,
+                throttled: ctx.throttled,
+                config_version: ctx.config_version.clone(),
             };
             // info!("handle_remote_response_finished(). sql:{:?}",sql);
             let send_result = self.exec_log_channel_sender.send(exec_log).await;
@@ -431,4 +1093,37 @@ impl VirtDBConnectionHandler {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_take_pipelined_commands() {
+    let listener = AsyncTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = AsyncTcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+    //两个COM_QUERY在一次写入中发送,第三个只发送了一部分
+    let mut bytes = Packet::command(PacketType::ComQuery, b"select 1").bytes;
+    bytes.extend_from_slice(&Packet::command(PacketType::ComQuery, b"drop table t").bytes);
+    let partial = Packet::command(PacketType::ComQuery, b"select 2").bytes;
+    bytes.extend_from_slice(&partial[..6]);
+    client.write_all(&bytes).await.unwrap();
+
+    let mut command_buf = vec![];
+    while command_buf.len() < bytes.len() {
+        let mut buf = [0; BUFFER_SIZE];
+        let n = server.read(&mut buf).await.unwrap();
+        command_buf.extend_from_slice(&buf[..n]);
+    }
+    let sqls: Vec<Vec<u8>> = take_commands(&mut command_buf).iter()
+        .map(|packet| protocol::command_payload(&packet.bytes)[1..].to_vec())
+        .collect();
+    assert_eq!(vec![b"select 1".to_vec(), b"drop table t".to_vec()], sqls);
+    assert_eq!(partial[..6].to_vec(), command_buf);
+
+    client.write_all(&partial[6..]).await.unwrap();
+    let mut buf = [0; BUFFER_SIZE];
+    let n = server.read(&mut buf).await.unwrap();
+    command_buf.extend_from_slice(&buf[..n]);
+    let packets = take_commands(&mut command_buf);
+    assert_eq!(1, packets.len());
+    assert_eq!(partial, packets[0].bytes);
+    assert!(command_buf.is_empty());
+}
@@ -0,0 +1,85 @@
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use crate::meta::QueryRewriteEntity;
+use crate::utils;
+
+/// Rewrite `sql` with the first matching rule.
+/// Returns the rule id and the rewritten sql, or None if no rule matches.
+pub fn rewrite(rules: &[QueryRewriteEntity], sql: &str) -> Option<(i32, String)> {
+    let dialect = MySqlDialect {};
+    for rule in rules {
+        let captures = match utils::sys_sql::capture_placeholders(&rule.cached_sql_parser_token, sql, &dialect) {
+            None => continue,
+            Some(captures) => captures,
+        };
+        match render(&rule.replacement, &captures, &dialect) {
+            Some(rewritten_sql) => return Some((rule.id, rewritten_sql)),
+            None => {
+                warn!("query rewrite rule {} has more placeholders in the replacement than in the template", rule.id);
+            }
+        }
+    }
+    None
+}
+
+//`?`按顺序替换为捕获的值,`$n`替换为第n个捕获的值
+fn render(replacement: &str, captures: &[String], dialect: &MySqlDialect) -> Option<String> {
+    let tokens = Tokenizer::new(dialect, replacement).tokenize().ok()?;
+    let spans = utils::sys_sql::token_spans(replacement, &tokens)?;
+    let mut next = 0;
+    let mut rewritten_sql = String::with_capacity(replacement.len());
+    for (token, span) in tokens.into_iter().zip(spans) {
+        match token {
+            Token::EOF => {}
+            Token::Placeholder(p) if p == "?" => {
+                rewritten_sql.push_str(captures.get(next)?);
+                next += 1;
+            }
+            //MySQL方言下`$n`是一个Word
+            Token::Word(w) if w.quote_style.is_none() && w.value.len() > 1 && w.value.starts_with('$') && w.value[1..].chars().all(|c| c.is_ascii_digit()) => {
+                let index: usize = w.value[1..].parse().ok()?;
+                rewritten_sql.push_str(captures.get(index.checked_sub(1)?)?);
+            }
+            //其余部分保留原文
+            _ => rewritten_sql.push_str(&replacement[span]),
+        }
+    }
+    Some(rewritten_sql)
+}
+
+#[cfg(test)]
+fn rule(id: i32, sql_template: &str, replacement: &str) -> QueryRewriteEntity {
+    QueryRewriteEntity {
+        id,
+        sql_template: sql_template.to_string(),
+        replacement: replacement.to_string(),
+        cached_sql_parser_token: utils::sys_sql::template_tokens(&MySqlDialect {}, sql_template),
+    }
+}
+
+#[test]
+fn test_rewrite() {
+    let rules = vec![
+        rule(1, "select * from article where channel_id = ?", "select * from article where channel_id = ? limit 1000"),
+        rule(2, "select id from article where title = ? and status = ?", "select id from article force index(idx_status) where status = $2 and title = $1"),
+        rule(3, "select * from user where id = ?", "select * from user where id = $2"),
+        rule(4, "select * from article where id in (?...)", "select * from article force index(primary) where id in (?)"),
+    ];
+
+    assert_eq!(Some((1, "select * from article where channel_id = 12 limit 1000".to_string())),
+               rewrite(&rules, "SELECT * FROM article WHERE channel_id = 12"));
+    assert_eq!(Some((2, "select id from article force index(idx_status) where status = 1 and title = 'Hello'".to_string())),
+               rewrite(&rules, "select id from article where title = 'Hello' and status = 1"));
+    assert_eq!(Some((4, "select * from article force index(primary) where id in (1, 2, -3)".to_string())),
+               rewrite(&rules, "select * from article where id in (1, 2, -3)"));
+    // invalid placeholder reference
+    assert_eq!(None, rewrite(&rules, "select * from user where id = 1"));
+    assert_eq!(None, rewrite(&rules, "select * from article"));
+    // captured strings stay single values
+    assert_eq!(Some((1, "select * from article where channel_id = 'x'' or ''1''=''1' limit 1000".to_string())),
+               rewrite(&rules, "select * from article where channel_id = 'x'' or ''1''=''1'"));
+    // escaped literals are kept as written
+    assert_eq!(Some((1, r"select * from article where channel_id = 'C:\\dir\'s' limit 1000".to_string())),
+               rewrite(&rules, r"select * from article where channel_id = 'C:\\dir\'s'"));
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use mysql::{Conn, OptsBuilder};
+use mysql::prelude::Queryable;
+
+use crate::sys_config::{BackendMySQLServerConfig, QueryTimeoutConfig};
+use crate::utils;
+
+//超时返回给客户端的错误,同MySQL的ER_QUERY_TIMEOUT
+pub const ER_QUERY_TIMEOUT: u16 = 3024;
+
+/// The timeout of a statement in ms, 0 means no timeout.
+/// The cache config wins over the user and global settings. A `MAX_EXECUTION_TIME` hint can only lower it.
+pub fn resolve_timeout_ms(query_timeout_config: &QueryTimeoutConfig, user: Option<&str>, cache_timeout_ms: i32, sql: Option<&str>) -> u64 {
+    let configured_ms = if cache_timeout_ms > 0 {
+        cache_timeout_ms as u64
+    } else {
+        user.and_then(|user| query_timeout_config.users.get(user))
+            .copied()
+            .unwrap_or(query_timeout_config.default_ms)
+    };
+    //MAX_EXECUTION_TIME(0)不能取消配置的超时
+    let hint_ms = sql
+        .and_then(|sql| utils::sys_hint::find_hint(sql, "MAX_EXECUTION_TIME"))
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|hint_ms| *hint_ms > 0);
+    match hint_ms {
+        Some(hint_ms) if configured_ms == 0 => hint_ms,
+        Some(hint_ms) => hint_ms.min(configured_ms),
+        None => configured_ms,
+    }
+}
+
+/// Run `KILL QUERY` for the backend connection on a side connection
+pub async fn kill_query(mysql_config: &BackendMySQLServerConfig, connection_id: u32) {
+    if mysql_config.username.is_empty() {
+        warn!("can not kill query of backend connection {}, mysql.username is not configured", connection_id);
+        return;
+    }
+    let opts = OptsBuilder::new()
+        .ip_or_hostname(Some(mysql_config.ip.clone()))
+        .tcp_port(mysql_config.port as u16)
+        .user(Some(mysql_config.username.clone()))
+        .pass(Some(mysql_config.password.clone()))
+        .tcp_connect_timeout(Some(Duration::from_secs(3)));
+    let kill_result = tokio::task::spawn_blocking(move || -> mysql::Result<()> {
+        let mut conn = Conn::new(opts)?;
+        conn.query_drop(format!("KILL QUERY {}", connection_id))
+    }).await;
+    match kill_result {
+        Ok(Ok(_)) => info!("killed query of backend connection {}", connection_id),
+        Ok(Err(err)) => warn!("kill query of backend connection {} fail.err:{:?}", connection_id, err),
+        Err(err) => warn!("kill query of backend connection {} fail.err:{:?}", connection_id, err),
+    }
+}
+
+#[test]
+fn test_resolve_timeout_ms() {
+    let mut config = QueryTimeoutConfig {
+        default_ms: 5000,
+        users: std::collections::HashMap::from([("report".to_string(), 60000)]),
+    };
+
+    assert_eq!(5000, resolve_timeout_ms(&config, Some("app"), 0, Some("select 1")));
+    assert_eq!(60000, resolve_timeout_ms(&config, Some("report"), 0, None));
+    assert_eq!(1000, resolve_timeout_ms(&config, Some("report"), 1000, Some("select 1")));
+    assert_eq!(200, resolve_timeout_ms(&config, Some("report"), 1000, Some("select /*+ MAX_EXECUTION_TIME(200) */ 1")));
+    // hints can not raise or remove the configured limit
+    assert_eq!(5000, resolve_timeout_ms(&config, Some("app"), 0, Some("select /*+ MAX_EXECUTION_TIME(90000) */ 1")));
+    assert_eq!(5000, resolve_timeout_ms(&config, Some("app"), 0, Some("select /*+ MAX_EXECUTION_TIME(0) */ 1")));
+    config.default_ms = 0;
+    assert_eq!(300, resolve_timeout_ms(&config, Some("app"), 0, Some("select /*+ MAX_EXECUTION_TIME(300) */ 1")));
+}
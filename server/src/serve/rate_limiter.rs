@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use sqlparser::dialect::MySqlDialect;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::meta::RateLimitConfigEntity;
+use crate::utils;
+
+pub const LIMIT_TYPE_USER: &str = "USER";
+pub const LIMIT_TYPE_IP: &str = "IP";
+pub const LIMIT_TYPE_PATTERN: &str = "PATTERN";
+const LIMIT_KEY_ANY: &str = "*";
+//超过该时间没有使用的令牌桶已经装满,与新建的相同,可以移除
+const IDLE_EVICT_AFTER: Duration = Duration::from_secs(60);
+
+//所有连接共享的令牌桶和并发信号量,key为`规则id:限流对象`
+static LIMITER_STATE: Lazy<Mutex<LimiterState>> = Lazy::new(|| Mutex::new(LimiterState::default()));
+
+#[derive(Default)]
+struct LimiterState {
+    buckets: HashMap<String, TokenBucket>,
+    semaphores: HashMap<String, (i32, Arc<Semaphore>)>,
+    last_evicted_at: Option<Instant>,
+}
+
+impl LimiterState {
+    //按IP或用户限流时每个客户端都有自己的桶和信号量,定期清理不再使用的
+    fn evict_idle(&mut self, now: Instant) {
+        if self.last_evicted_at.is_some_and(|last_evicted_at| now.saturating_duration_since(last_evicted_at) < IDLE_EVICT_AFTER) {
+            return;
+        }
+        self.last_evicted_at = Some(now);
+        self.buckets.retain(|_, bucket| now.saturating_duration_since(bucket.last_refill) < IDLE_EVICT_AFTER);
+        //没有语句持有名额时只剩这里的引用
+        self.semaphores.retain(|_, (_, semaphore)| Arc::strong_count(semaphore) > 1);
+    }
+}
+
+struct TokenBucket {
+    qps: i32,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(qps: i32) -> TokenBucket {
+        TokenBucket {
+            qps,
+            tokens: qps as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    //成功返回Ok,否则返回下一个令牌可用前需要等待的时间
+    fn try_take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = (now - self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.qps as f64).min(self.qps as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.qps as f64))
+    }
+
+    fn give_back(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.qps as f64);
+    }
+}
+
+//被后面的规则拒绝时,归还前面的规则已经拿到的令牌
+fn give_back_tokens(keys: &[String]) {
+    let mut state = LIMITER_STATE.lock().unwrap();
+    for key in keys {
+        if let Some(bucket) = state.buckets.get_mut(key) {
+            bucket.give_back();
+        }
+    }
+}
+
+/// The statement being checked against the rate limit rules
+pub struct LimitSubject<'a> {
+    pub user: Option<&'a str>,
+    pub ip: IpAddr,
+    pub sql: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ThrottleError {
+    pub rule_id: i32,
+    pub reason: String,
+}
+
+/// Wait for every matching rule to admit the statement.
+/// The returned permits hold the concurrency slots and must be kept until the response is finished.
+pub async fn acquire(rules: &[RateLimitConfigEntity], subject: &LimitSubject<'_>) -> Result<Vec<OwnedSemaphorePermit>, ThrottleError> {
+    let mut permits = vec![];
+    let mut taken_keys: Vec<String> = vec![];
+    for rule in rules {
+        let target = match matched_target(rule, subject) {
+            None => continue,
+            Some(target) => target,
+        };
+        let key = format!("{}:{}", rule.id, target);
+        let deadline = Instant::now() + Duration::from_millis(rule.queue_timeout_ms.max(0) as u64);
+
+        if rule.max_qps > 0 {
+            loop {
+                let take_result = {
+                    let mut state = LIMITER_STATE.lock().unwrap();
+                    state.evict_idle(Instant::now());
+                    let bucket = state.buckets.entry(key.clone()).or_insert_with(|| TokenBucket::new(rule.max_qps));
+                    if bucket.qps != rule.max_qps {
+                        *bucket = TokenBucket::new(rule.max_qps);
+                    }
+                    bucket.try_take()
+                };
+                match take_result {
+                    Ok(_) => {
+                        taken_keys.push(key.clone());
+                        break;
+                    }
+                    Err(wait) => {
+                        if Instant::now() + wait > deadline {
+                            give_back_tokens(&taken_keys);
+                            return Err(ThrottleError {
+                                rule_id: rule.id,
+                                reason: format!("{} {} exceeds {} queries per second", rule.limit_type, target, rule.max_qps),
+                            });
+                        }
+                        trace!("rate limit rule {} delays {} for {:?}", rule.id, target, wait);
+                        tokio::time::sleep(wait).await;
+                    }
+                }
+            }
+        }
+
+        if rule.max_concurrency > 0 {
+            let semaphore = {
+                let mut state = LIMITER_STATE.lock().unwrap();
+                let entry = state.semaphores.entry(key.clone())
+                    .or_insert_with(|| (rule.max_concurrency, Arc::new(Semaphore::new(rule.max_concurrency as usize))));
+                if entry.0 != rule.max_concurrency {
+                    *entry = (rule.max_concurrency, Arc::new(Semaphore::new(rule.max_concurrency as usize)));
+                }
+                entry.1.clone()
+            };
+            let permit = match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match tokio::time::timeout(timeout, semaphore.acquire_owned()).await {
+                        Ok(Ok(permit)) => Some(permit),
+                        _ => None,
+                    }
+                }
+            };
+            match permit {
+                Some(permit) => permits.push(permit),
+                None => {
+                    give_back_tokens(&taken_keys);
+                    return Err(ThrottleError {
+                        rule_id: rule.id,
+                        reason: format!("{} {} exceeds {} concurrent queries", rule.limit_type, target, rule.max_concurrency),
+                    });
+                }
+            }
+        }
+    }
+    Ok(permits)
+}
+
+//返回限流对象,不匹配时返回None
+fn matched_target(rule: &RateLimitConfigEntity, subject: &LimitSubject<'_>) -> Option<String> {
+    match rule.limit_type.as_str() {
+        LIMIT_TYPE_USER => {
+            let user = subject.user?;
+            if rule.limit_key == LIMIT_KEY_ANY || rule.limit_key == user {
+                Some(format!("'{}'", user))
+            } else {
+                None
+            }
+        }
+        LIMIT_TYPE_IP => {
+            let ip = subject.ip.to_string();
+            if rule.limit_key == LIMIT_KEY_ANY || rule.limit_key == ip {
+                Some(ip)
+            } else {
+                None
+            }
+        }
+        LIMIT_TYPE_PATTERN => {
+            let sql = subject.sql?;
+            if utils::sys_sql::is_pattern_match(&rule.cached_sql_parser_token, sql.trim(), &MySqlDialect {}) {
+                Some(format!("#{}", rule.id))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+fn rule(id: i32, limit_type: &str, limit_key: &str, max_qps: i32, max_concurrency: i32) -> RateLimitConfigEntity {
+    RateLimitConfigEntity {
+        id,
+        limit_type: limit_type.to_string(),
+        limit_key: limit_key.to_string(),
+        max_qps,
+        max_concurrency,
+        queue_timeout_ms: 0,
+        cached_sql_parser_token: vec![],
+    }
+}
+
+#[tokio::test]
+async fn test_acquire() {
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+    let rules = vec![rule(9001, LIMIT_TYPE_USER, "app", 3, 0), rule(9002, LIMIT_TYPE_IP, LIMIT_KEY_ANY, 0, 1)];
+    let other_user = LimitSubject { user: Some("report"), ip: "10.0.0.2".parse().unwrap(), sql: None };
+    let subject = LimitSubject { user: Some("app"), ip, sql: None };
+
+    let permits = acquire(&rules, &subject).await.unwrap();
+    assert_eq!(1, permits.len());
+    // the only concurrency slot of 10.0.0.1 is taken
+    assert_eq!(9002, acquire(&rules, &subject).await.unwrap_err().rule_id);
+    drop(permits);
+    acquire(&rules, &subject).await.unwrap();
+    // the token taken by the throttled statement was given back
+    acquire(&rules, &subject).await.unwrap();
+    // the bucket of 'app' is empty now, other users are not affected
+    assert_eq!(9001, acquire(&rules, &subject).await.unwrap_err().rule_id);
+    acquire(&rules, &other_user).await.unwrap();
+}
+
+#[test]
+fn test_evict_idle() {
+    let mut state = LimiterState::default();
+    state.buckets.insert(String::from("1:10.0.0.1"), TokenBucket::new(1));
+    let now = Instant::now() + IDLE_EVICT_AFTER;
+    let mut recent_bucket = TokenBucket::new(1);
+    recent_bucket.last_refill = now;
+    state.buckets.insert(String::from("1:10.0.0.2"), recent_bucket);
+    let semaphore = Arc::new(Semaphore::new(1));
+    let permit = semaphore.clone().try_acquire_owned().unwrap();
+    state.semaphores.insert(String::from("2:10.0.0.1"), (1, semaphore));
+    state.semaphores.insert(String::from("2:10.0.0.2"), (1, Arc::new(Semaphore::new(1))));
+
+    state.evict_idle(now);
+    assert_eq!(vec!["1:10.0.0.2"], state.buckets.keys().collect::<Vec<_>>());
+    assert_eq!(vec!["2:10.0.0.1"], state.semaphores.keys().collect::<Vec<_>>());
+    // the permit holder keeps its semaphore until the next sweep
+    drop(permit);
+    state.evict_idle(now);
+    assert_eq!(1, state.semaphores.len());
+    state.evict_idle(now + IDLE_EVICT_AFTER);
+    assert!(state.semaphores.is_empty());
+}
@@ -20,40 +20,60 @@ use tokio::runtime::Builder;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 
-use crate::{meta, sys_assistant_client, utils};
+use crate::{meta, sys_assistant_client, sys_shutdown, utils};
 use crate::meta::CacheConfigEntity;
 // use crate::protocol::{Action, ConnectionContext, ConnReader, ConnWriter, Packet, PacketHandler, PacketType, Pipe};
 // use crate::protocol::packet_writer::PacketWriter;
 use crate::serve::{handle_client, VirtDBConnectionHandler};
 use crate::sys_assistant_client::{CacheTaskInfo, ExecLog};
+use crate::sys_audit_log::AuditLog;
 
+use crate::sys_config;
 use crate::sys_config::{ServerConfig, VirtDBConfig};
 // use crate::sys_assistant_client::{add_cache_task, CacheTaskInfo, ExecLog};
 use crate::sys_redis::SysRedisClient;
-use crate::utils::sys_sql::sql_to_pattern;
 
 
-pub async fn start(sys_config: VirtDBConfig, exec_log_channel_sender: Sender<ExecLog>, cache_load_task_channel_sender: Sender<CacheTaskInfo>) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn start(sys_config: VirtDBConfig, exec_log_channel_sender: Sender<ExecLog>, cache_load_task_channel_sender: Sender<CacheTaskInfo>, audit_log_channel_sender: Sender<AuditLog>) -> Result<(), Box<dyn std::error::Error>> {
     let server_addr = format!("0.0.0.0:{:?}", sys_config.clone().server.port);
     let mysql_addr_str = format!("{}:{}", sys_config.mysql.ip, sys_config.mysql.port);
 
     let listener = TcpListener::bind(server_addr.clone()).await?;
     info!("Listening on: {}", server_addr);
 
-    let redis_config = sys_config.redis.clone();
-    let redis_client = Client::open(redis_config.nodes)?;
+    let mut redis_nodes = sys_config.redis.nodes.clone();
+    let mut redis_client = Client::open(redis_nodes.as_str())?;
     loop {
+        let (client_stream, client_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            //停止服务,不再接受新连接
+            _ = sys_shutdown::wait_draining() => {
+                info!("stop listening on {}", server_addr);
+                return Ok(());
+            }
+        };
         let mysql_addr_str = mysql_addr_str.clone();
-        let sys_config = sys_config.clone();
+        //新连接使用重新加载后的Redis
+        let sys_config = sys_config::current_config();
+        if sys_config.redis.nodes != redis_nodes {
+            match Client::open(sys_config.redis.nodes.as_str()) {
+                Ok(client) => {
+                    info!("new connections use redis {}", sys_config.redis.nodes);
+                    redis_client = client;
+                    redis_nodes = sys_config.redis.nodes.clone();
+                }
+                Err(err) => warn!("open redis {} fail, keep {}.err:{:?}", sys_config.redis.nodes, redis_nodes, err),
+            }
+        }
         let exec_log_channel_sender = exec_log_channel_sender.clone();
         let cache_load_task_channel_sender = cache_load_task_channel_sender.clone();
+        let audit_log_channel_sender = audit_log_channel_sender.clone();
         let redis_client = redis_client.clone();
-        let (client_stream, client_addr) = listener.accept().await?;
 
         info!("Accepted connection from {}", client_addr);
         tokio::spawn(async move {
             let redis_conn = redis_client.get_async_connection().await.unwrap();
-            let conn_handler = VirtDBConnectionHandler::new(redis_conn, sys_config, exec_log_channel_sender, cache_load_task_channel_sender);
+            let conn_handler = VirtDBConnectionHandler::new(redis_conn, sys_config, exec_log_channel_sender, cache_load_task_channel_sender, audit_log_channel_sender, client_addr);
             handle_client(client_stream, mysql_addr_str.clone().parse().unwrap(), conn_handler).await;
         });
     }
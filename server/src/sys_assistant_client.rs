@@ -12,22 +12,37 @@ use reqwest::{Error, Response};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::task::JoinHandle;
 
 use crate::math::avg::AveragedCollection;
-use crate::sys_config::VirtDBConfig;
+use crate::sys_config;
+use crate::sys_config::{AdminConfig, VirtDBConfig};
+use crate::serve::cache_codec;
+use crate::sys_cache_purge;
+use crate::sys_cache_purge::PurgeTarget;
+use crate::sys_cache_warm;
+use crate::sys_cache_warm::WarmQuery;
+use crate::sys_redis;
+use crate::sys_reload;
 use crate::sys_redis::SysRedisClient;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct ExecLog {
+    pub sql_digest: String,
     pub sql_str: String,
     pub total_duration: i64,
     pub mysql_duration: i64,
     pub redis_duration: i64,
     pub from_cache: bool,
+    pub throttled: bool,
+    //处理该语句时生效的规则版本
+    pub config_version: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MetricHistory {
+    pub sql_digest: String,
     pub sql_str: String,
     pub db_server_port: String,
     pub database_name: String,
@@ -36,27 +51,46 @@ pub struct MetricHistory {
     pub min_duration: i64,
     pub exec_count: usize,
     pub cache_hit_count: i32,
+    pub throttle_count: i32,
     pub created_at: i64,
+    //同一指纹在不同规则版本下分开统计,用于对比规则变更前后的表现
+    pub config_version: String,
+}
+
+/**
+ * admin在注册响应中下发给节点的指令
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "type")]
+pub enum VtNodeCommand {
+    //预热一个cache_config的缓存
+    WarmCache { cache_config_id: i32 },
+    //清除缓存
+    PurgeCache { target: PurgeTarget },
+    //重新加载config.toml
+    ReloadConfig,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct CacheTaskInfo {
-    sql: String,
+    cache_key: String,
     body: Vec<u8>,
     duration: i32,
+    tags: Vec<String>,
 }
 
 impl CacheTaskInfo {
-    pub fn new(sql: String, body: Vec<u8>, duration: i32) -> CacheTaskInfo {
+    pub fn new(cache_key: String, body: Vec<u8>, duration: i32, tags: Vec<String>) -> CacheTaskInfo {
         CacheTaskInfo {
-            sql,
+            cache_key,
             body,
             duration,
+            tags,
         }
     }
 }
 
-pub async fn handle_messages<T, F>(mut rx: mpsc::Receiver<T>, sys_config: VirtDBConfig, handler: impl Fn(Vec<T>, VirtDBConfig) -> F)
+pub async fn handle_messages<T, F>(mut rx: mpsc::Receiver<T>, handler: impl Fn(Vec<T>, VirtDBConfig) -> F)
     where
         F: Future<Output=()>,
 {
@@ -64,30 +98,43 @@ pub async fn handle_messages<T, F>(mut rx: mpsc::Receiver<T>, sys_config: VirtDB
     loop {
         interval.tick().await;
         let mut messages = Vec::new();
-        while let Ok(msg) = rx.try_recv() {
-            messages.push(msg);
+        let mut closed = false;
+        loop {
+            match rx.try_recv() {
+                Ok(msg) => messages.push(msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    closed = true;
+                    break;
+                }
+            }
         }
 
-        if !messages.is_empty() {
-            handler(messages, sys_config.clone()).await;
+        //没有消息时也调用,注册同时作为心跳,每次使用最新的配置
+        handler(messages, (*sys_config::current_config()).clone()).await;
+        //停止服务时所有连接都已关闭,发送完剩余的消息后结束
+        if closed {
+            return;
         }
     }
 }
 
 pub async fn handle_metrics(exec_log_list: Vec<ExecLog>, sys_config: VirtDBConfig) {
     let metric_history_list = exec_log_list.iter()
-        .sorted_by_key(|v| v.sql_str.clone())
-        .group_by(|v| v.sql_str.to_string())
+        .sorted_by_key(|v| (v.sql_digest.clone(), v.config_version.clone()))
+        .group_by(|v| (v.sql_digest.to_string(), v.config_version.to_string()))
         .into_iter()
-        .map(|(sql_str, group)| {
+        .map(|((sql_digest, config_version), group)| {
             let mut avg_calculator = AveragedCollection::new();
             let mut max_duration = -1;
             let mut min_duration = i64::MAX;
             let mut cache_hit_count = 0;
+            let mut throttle_count = 0;
             let mut total_count = 0;
             let list = group.into_iter()
                 .map(|v| v.to_owned())
                 .collect::<Vec<ExecLog>>();
+            let sql_str = list[0].sql_str.clone();
 
             for exec_log in list {
                 avg_calculator.add(exec_log.total_duration);
@@ -101,10 +148,14 @@ pub async fn handle_metrics(exec_log_list: Vec<ExecLog>, sys_config: VirtDBConfi
                 if exec_log.from_cache {
                     cache_hit_count += 1;
                 }
+                if exec_log.throttled {
+                    throttle_count += 1;
+                }
                 total_count += 1;
             }
 
             let metric_history = MetricHistory {
+                sql_digest,
                 sql_str,
                 db_server_port: sys_config.server.port.to_string(),
                 database_name: "".to_string(),//TODO
@@ -113,7 +164,9 @@ pub async fn handle_metrics(exec_log_list: Vec<ExecLog>, sys_config: VirtDBConfi
                 min_duration,
                 exec_count: total_count,
                 cache_hit_count: cache_hit_count as i32,
+                throttle_count,
                 created_at: Local::now().timestamp(),
+                config_version,
             };
             metric_history
         })
@@ -125,13 +178,16 @@ pub async fn handle_metrics(exec_log_list: Vec<ExecLog>, sys_config: VirtDBConfi
             if response.status().as_u16() != 200 {
                 warn!("register vt_node fail. response:{:?}",response);
             } else {
-                let data_wrapper_result = response.json::<DataWrapper<String>>().await;
+                let data_wrapper_result = response.json::<DataWrapper<Vec<VtNodeCommand>>>().await;
                 match data_wrapper_result {
                     Ok(data_wrapper) => {
                         debug!("handle_metrics. response:{:?}",data_wrapper);
                         if !data_wrapper.success {
                             warn!("register vt_node fail. response:{:?}",data_wrapper);
                         }
+                        for command in data_wrapper.data.unwrap_or_default() {
+                            handle_command(&sys_config, command);
+                        }
                     }
                     Err(e) => {
                         warn!("register vt_node fail:{:?}",e);
@@ -145,58 +201,111 @@ pub async fn handle_metrics(exec_log_list: Vec<ExecLog>, sys_config: VirtDBConfi
     };
 }
 
-pub fn enable_metric_writing_job(sys_config: VirtDBConfig, channel_receiver: Receiver<ExecLog>) {
+fn handle_command(sys_config: &VirtDBConfig, command: VtNodeCommand) {
+    info!("received command from admin:{:?}", command);
+    match command {
+        VtNodeCommand::WarmCache { cache_config_id } => {
+            tokio::spawn(sys_cache_warm::warm(sys_config.clone(), Some(cache_config_id)));
+        }
+        VtNodeCommand::PurgeCache { target } => {
+            tokio::spawn(sys_cache_purge::purge_with_config(sys_config.clone(), target));
+        }
+        VtNodeCommand::ReloadConfig => sys_reload::reload_and_report("admin"),
+    }
+}
+
+pub fn enable_metric_writing_job(channel_receiver: Receiver<ExecLog>) -> JoinHandle<()> {
     info!("metric data writing task started.");
     tokio::spawn(async move {
-        handle_messages(channel_receiver, sys_config, handle_metrics).await;
-    });
+        handle_messages(channel_receiver, handle_metrics).await;
+    })
 }
 
-pub fn enable_cache_task_handle_job(sys_config: VirtDBConfig, cache_load_task_channel_receiver: Receiver<CacheTaskInfo>) {
-    let nodes = sys_config.redis.nodes;
+pub fn enable_cache_task_handle_job(sys_config: VirtDBConfig, cache_load_task_channel_receiver: Receiver<CacheTaskInfo>) -> JoinHandle<()> {
     info!("cache handle task started.");
     tokio::spawn(async move {
-        let nodes = nodes.clone();
+        let mut nodes = sys_config.redis.nodes.clone();
 
         let mut cache_load_task_channel_receiver = cache_load_task_channel_receiver;
         // let mut sys_redis_client = SysRedisClient::new(nodes.as_str()).unwrap();
         let client = Client::open(nodes.as_str()).unwrap();
         let mut redis_conn = client.clone().get_async_connection().await.unwrap();
 
-        loop {
-            if let Some(cache_task_info) = cache_load_task_channel_receiver.recv().await {
-                let sql = cache_task_info.sql;
-                let redis_key = format!("cache:{:?}", sql);
-                let redis_v = cache_task_info.body;
-                let cache_duration = cache_task_info.duration;
-                let cache_duration = max(60,cache_duration);
-                debug!("[cache_task_handle_job]sql:{:?},redis_key:{:?},cache_duration:{:?}",sql.clone(),redis_key,cache_duration);
-
-                let rv: RedisResult<()> = redis_conn
-                    .set_ex(
-                        &*redis_key.clone(),
-                        redis_v,
-                        cache_duration as usize,
-                    ).await;
+        //所有连接关闭后写完剩余的缓存再结束
+        while let Some(cache_task_info) = cache_load_task_channel_receiver.recv().await {
+            //重新加载配置后使用新的Redis和压缩配置
+            let sys_config = sys_config::current_config();
+            if sys_config.redis.nodes != nodes {
+                match Client::open(sys_config.redis.nodes.as_str()) {
+                    Ok(client) => match client.get_async_connection().await {
+                        Ok(conn) => {
+                            info!("cache handle task switched to redis {}", sys_config.redis.nodes);
+                            redis_conn = conn;
+                            nodes = sys_config.redis.nodes.clone();
+                        }
+                        Err(err) => warn!("connect redis {} fail, keep {}.err:{:?}", sys_config.redis.nodes, nodes, err),
+                    },
+                    Err(err) => warn!("open redis {} fail, keep {}.err:{:?}", sys_config.redis.nodes, nodes, err),
+                }
+            }
+            let redis_key = cache_task_info.cache_key;
+            let redis_v = cache_codec::encode(&sys_config.cache_compression, &cache_task_info.body);
+            let cache_duration = cache_task_info.duration;
+            let cache_duration = max(60,cache_duration);
+            debug!("[cache_task_handle_job]redis_key:{:?},cache_duration:{:?},tags:{:?},size:{}/{}",redis_key,cache_duration,cache_task_info.tags,redis_v.len(),cache_task_info.body.len());
+
+            let rv: RedisResult<()> = redis_conn
+                .set_ex(
+                    &*redis_key.clone(),
+                    redis_v,
+                    cache_duration as usize,
+                ).await;
+            if let Err(err) = rv {
+                warn!("redis set cmd fail. for key:{:?},err:{:?}",redis_key,err);
+                // match err.code() {
+                //     None => {}
+                //     Some(err_code) => {
+                //         warn!("redis set cmd fail. for sql:{:?},err:{:?}",sql,err_code)
+                //     }
+                // }
+            }
+            //标签集合至少与其中最晚过期的缓存一样久
+            for tag in cache_task_info.tags.iter() {
+                let tag_key = sys_redis::cache_tag_key(tag);
+                let rv = sys_redis::add_to_tag(&mut redis_conn, &tag_key, &redis_key, cache_duration as usize).await;
                 if let Err(err) = rv {
-                    warn!("redis set cmd fail. for sql:{:?},err:{:?}",sql,err);
-                    // match err.code() {
-                    //     None => {}
-                    //     Some(err_code) => {
-                    //         warn!("redis set cmd fail. for sql:{:?},err:{:?}",sql,err_code)
-                    //     }
-                    // }
+                    warn!("redis sadd cmd fail. for tag:{:?},err:{:?}",tag,err);
                 }
             }
-        };
-    });
+        }
+    })
 }
 
 
-static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+pub static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
     reqwest::Client::new()
 });
 
+//节点与admin之间互相调用时携带的请求头,值为admin.token
+pub const VT_NODE_TOKEN_HEADER: &str = "X-VirtDB-Token";
+
+/// A POST to an admin api of `path` carrying the node token
+pub fn admin_post(admin_config: &AdminConfig, path: &str) -> reqwest::RequestBuilder {
+    HTTP_CLIENT
+        .post(format!("{}{}", admin_config.address, path))
+        .header(VT_NODE_TOKEN_HEADER, admin_config.token.as_str())
+}
+
+/// Whether `token` sent by the admin equals `admin.token`, always false when no token is configured.
+/// Compared in constant time so the token can't be guessed byte by byte.
+pub fn is_valid_node_token(admin_config: &AdminConfig, token: &str) -> bool {
+    let configured_token = admin_config.token.as_bytes();
+    if configured_token.is_empty() || configured_token.len() != token.len() {
+        return false;
+    }
+    configured_token.iter().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataWrapper<V> {
     pub code: i32,
@@ -209,22 +318,44 @@ pub struct DataWrapper<V> {
 #[serde(rename_all = "camelCase")]
 pub struct VtNodeRegisterParam {
     pub port: String,
+    //admin通过该端口预览缓存
+    pub metric_port: u16,
     pub metric_history_list: Vec<MetricHistory>,
+    pub warm_query_list: Vec<WarmQuery>,
 }
 
 async fn register(sys_config: &VirtDBConfig, metric_history_list: Vec<MetricHistory>) -> Result<Response, Error> {
-    let address = sys_config.admin.address.clone();
-    let register_api_url = format!("{}/vt_node/register", address);
     let params = VtNodeRegisterParam {
         port: sys_config.server.port.to_string(),
+        metric_port: sys_config.metric.expose_port,
         metric_history_list,
+        warm_query_list: sys_cache_warm::take_recorded_queries(),
     };
 
     let request_body = serde_json::to_string(&params).unwrap();
     debug!("[job]request body:{}",request_body);
-    HTTP_CLIENT
-        .post(register_api_url)
+    admin_post(&sys_config.admin, "/vt_node/register")
         .header("Content-Type", "application/json")
         .body(request_body)
         .send().await
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VtNodeDeregisterParam {
+    pub port: String,
+}
+
+/// Remove this node from the admin node list so it is not shown as alive after a shutdown
+pub async fn deregister(sys_config: &VirtDBConfig) {
+    let params = VtNodeDeregisterParam {
+        port: sys_config.server.port.to_string(),
+    };
+    let result = admin_post(&sys_config.admin, "/vt_node/deregister")
+        .json(&params)
+        .timeout(Duration::from_secs(5))
+        .send().await;
+    match result {
+        Ok(_) => info!("deregistered from admin {}", sys_config.admin.address),
+        Err(err) => warn!("deregister from admin {} fail.err:{:?}", sys_config.admin.address, err),
+    }
+}
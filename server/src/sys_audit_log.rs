@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinHandle;
+
+use crate::sys_config::{AuditLogConfig, VirtDBConfig};
+use crate::sys_log;
+
+//采样计数器,所有连接共享
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// One line of the audit log
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct AuditLog {
+    pub time: String,
+    pub client_addr: String,
+    pub user: Option<String>,
+    pub schema: Option<String>,
+    pub backend: String,
+    pub sql: String,
+    pub total_duration: i64,
+    pub mysql_duration: i64,
+    pub rows: u64,
+    pub bytes: u64,
+    pub from_cache: bool,
+    pub error_code: Option<u16>,
+    pub throttled: bool,
+    pub rewrite_rule_id: Option<i32>,
+    //处理该语句时生效的规则版本
+    #[serde(default)]
+    pub config_version: String,
+    //无法识别的hint
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Whether a statement that took `total_duration` ms should be written to the audit log
+pub fn should_record(audit_log_config: &AuditLogConfig, total_duration: i64) -> bool {
+    if !audit_log_config.enabled || total_duration < audit_log_config.slow_threshold_ms {
+        return false;
+    }
+    is_sampled(SAMPLE_COUNTER.fetch_add(1, Ordering::Relaxed), audit_log_config.sample_rate)
+}
+
+//按比例均匀采样,第n条记录在n*rate跨过整数时被选中
+fn is_sampled(counter: u64, sample_rate: f64) -> bool {
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    if sample_rate <= 0.0 {
+        return false;
+    }
+    ((counter + 1) as f64 * sample_rate).floor() > (counter as f64 * sample_rate).floor()
+}
+
+pub fn enable_audit_log_job(sys_config: VirtDBConfig, channel_receiver: Receiver<AuditLog>) -> Option<JoinHandle<()>> {
+    let audit_log_config = sys_config.audit_log;
+    if !audit_log_config.enabled {
+        return None;
+    }
+    info!("audit log task started. slow_threshold_ms:{},sample_rate:{}", audit_log_config.slow_threshold_ms, audit_log_config.sample_rate);
+    Some(tokio::spawn(async move {
+        let mut channel_receiver = channel_receiver;
+        while let Some(audit_log) = channel_receiver.recv().await {
+            match serde_json::to_string(&audit_log) {
+                Ok(line) => info!(target: sys_log::AUDIT_LOG_TARGET, "{}", line),
+                Err(err) => warn!("serialize audit log fail.err:{:?}", err),
+            }
+        }
+    }))
+}
+
+#[test]
+fn test_is_sampled() {
+    assert_eq!(1000, (0..1000).filter(|n| is_sampled(*n, 1.0)).count());
+    assert_eq!(100, (0..1000).filter(|n| is_sampled(*n, 0.1)).count());
+    assert_eq!(0, (0..1000).filter(|n| is_sampled(*n, 0.0)).count());
+}
@@ -0,0 +1,227 @@
+use mysql::Value;
+use redis::RedisResult;
+use serde::Serialize;
+
+use crate::protocol::decoder;
+use crate::protocol::result_set::{text_value, ColumnDefinition};
+use crate::serve::cache_codec;
+use crate::sys_config::VirtDBConfig;
+use crate::sys_redis;
+use crate::sys_redis::SysRedisClient;
+
+//按pattern预览时默认最多返回的key数
+const DEFAULT_KEY_LIMIT: usize = 20;
+//每个缓存默认最多返回的行数
+const DEFAULT_MAX_ROWS: usize = 100;
+
+/**
+ * 要预览的缓存,key和pattern二选一
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CachePreviewParam {
+    pub key: Option<String>,
+    //Redis的glob,只匹配cache:开头的key
+    pub pattern: Option<String>,
+    pub limit: usize,
+    pub max_rows: usize,
+}
+
+impl CachePreviewParam {
+    /// Read the parameters from the query string of `GET /cache/preview`
+    pub fn from_query(query: &str) -> Result<CachePreviewParam, String> {
+        let url = reqwest::Url::parse(&format!("http://localhost/?{}", query)).map_err(|e| e.to_string())?;
+        let mut param = CachePreviewParam {
+            key: None,
+            pattern: None,
+            limit: DEFAULT_KEY_LIMIT,
+            max_rows: DEFAULT_MAX_ROWS,
+        };
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "key" => param.key = Some(value.to_string()),
+                "pattern" => param.pattern = Some(value.to_string()),
+                "limit" => param.limit = value.parse().map_err(|_| format!("invalid limit {:?}", value))?,
+                "max_rows" => param.max_rows = value.parse().map_err(|_| format!("invalid max_rows {:?}", value))?,
+                _ => {}
+            }
+        }
+        if param.key.is_none() == param.pattern.is_none() {
+            return Err(String::from("one of key and pattern is required"));
+        }
+        Ok(param)
+    }
+}
+
+/**
+ * 缓存的列
+ */
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ColumnPreview {
+    pub name: String,
+    pub schema: String,
+    pub table: String,
+    //如MYSQL_TYPE_LONG
+    pub column_type: String,
+    pub flags: u16,
+    pub decimals: u8,
+}
+
+/**
+ * 一个缓存的内容
+ */
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CachePreview {
+    pub key: String,
+    //Redis中保存的字节数,压缩时为压缩后的大小
+    pub byte_size: usize,
+    //解压后的响应字节数
+    pub body_size: usize,
+    //None表示没有过期时间
+    pub ttl_seconds: Option<i64>,
+    pub columns: Vec<ColumnPreview>,
+    pub row_count: usize,
+    //最多max_rows行
+    pub rows: Vec<Vec<serde_json::Value>>,
+    //不存在或无法解码的原因
+    pub error: Option<String>,
+}
+
+impl CachePreview {
+    fn new(key: &str) -> CachePreview {
+        CachePreview {
+            key: key.to_string(),
+            byte_size: 0,
+            body_size: 0,
+            ttl_seconds: None,
+            columns: vec![],
+            row_count: 0,
+            rows: vec![],
+            error: None,
+        }
+    }
+
+    /// Decode a value read from Redis
+    pub fn decode(key: &str, value: Vec<u8>, max_rows: usize) -> CachePreview {
+        let mut preview = CachePreview::new(key);
+        preview.byte_size = value.len();
+        let result_set = match cache_codec::decode(value).and_then(|body| {
+            preview.body_size = body.len();
+            decoder::decode_text(&body)
+        }) {
+            Ok(result_set) => result_set,
+            Err(err) => {
+                preview.error = Some(err);
+                return preview;
+            }
+        };
+        preview.row_count = result_set.rows.len();
+        preview.rows = result_set.rows.iter()
+            .take(max_rows)
+            .map(|row| result_set.columns.iter().zip(row.iter()).map(|(column, value)| json_value(column, value)).collect())
+            .collect();
+        preview.columns = result_set.columns.into_iter()
+            .map(|column| ColumnPreview {
+                column_type: format!("{:?}", column.column_type),
+                name: column.name,
+                schema: column.schema,
+                table: column.table,
+                flags: column.flags,
+                decimals: column.decimals,
+            })
+            .collect();
+        preview
+    }
+}
+
+//数字保持为JSON数字,其他值按文本协议的格式输出
+fn json_value(column: &ColumnDefinition, value: &Value) -> serde_json::Value {
+    match value {
+        Value::NULL => serde_json::Value::Null,
+        Value::Int(v) => serde_json::json!(v),
+        Value::UInt(v) => serde_json::json!(v),
+        Value::Float(v) => serde_json::json!(v),
+        Value::Double(v) => serde_json::json!(v),
+        _ => serde_json::Value::String(String::from_utf8_lossy(&text_value(column, value).unwrap_or_default()).to_string()),
+    }
+}
+
+/// Preview one cached entry
+pub fn preview_key(redis_client: &mut SysRedisClient, key: &str, max_rows: usize) -> RedisResult<CachePreview> {
+    let value = redis_client.get_bytes(key)?;
+    let mut preview = match value {
+        Some(value) => CachePreview::decode(key, value, max_rows),
+        None => {
+            let mut preview = CachePreview::new(key);
+            preview.error = Some(String::from("not cached"));
+            return Ok(preview);
+        }
+    };
+    //-2表示不存在,-1表示没有过期时间
+    let ttl = redis_client.ttl(key)?;
+    preview.ttl_seconds = Some(ttl).filter(|v| *v >= 0);
+    Ok(preview)
+}
+
+/// Preview the cached entries matching `param`, single node or cluster.
+/// The Redis client is blocking so the preview runs on the blocking pool.
+pub async fn preview(sys_config: &VirtDBConfig, param: &CachePreviewParam) -> Result<Vec<CachePreview>, String> {
+    let (nodes, param) = (sys_config.redis.nodes.clone(), param.clone());
+    tokio::task::spawn_blocking(move || preview_with_nodes(&nodes, &param))
+        .await
+        .map_err(|e| format!("preview task fail:{:?}", e))?
+}
+
+fn preview_with_nodes(nodes: &str, param: &CachePreviewParam) -> Result<Vec<CachePreview>, String> {
+    let mut redis_client = SysRedisClient::new(nodes).map_err(|e| format!("connect redis fail:{:?}", e))?;
+    let keys = match (&param.key, &param.pattern) {
+        (Some(key), _) => vec![key.clone()],
+        (None, Some(pattern)) => {
+            //tag的集合等其他key不是缓存的响应
+            let pattern = if pattern.starts_with("cache:") { pattern.clone() } else { format!("cache:{}", pattern) };
+            sys_redis::scan_match(nodes, &pattern, param.limit).map_err(|e| format!("scan fail:{:?}", e))?
+        }
+        (None, None) => vec![],
+    };
+    let mut previews = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        let preview = preview_key(&mut redis_client, key, param.max_rows).map_err(|e| format!("read {} fail:{:?}", key, e))?;
+        previews.push(preview);
+    }
+    Ok(previews)
+}
+
+#[test]
+fn test_decode_preview() {
+    use mysql::consts::ColumnType;
+    use crate::protocol::result_set::ResultSet;
+    use crate::sys_config::CacheCompressionConfig;
+
+    let param = CachePreviewParam::from_query("pattern=*users*&max_rows=1").unwrap();
+    assert_eq!((Some(String::from("*users*")), DEFAULT_KEY_LIMIT, 1), (param.pattern, param.limit, param.max_rows));
+    let param = CachePreviewParam::from_query("key=cache%3A%22select%201%22").unwrap();
+    assert_eq!(Some(String::from("cache:\"select 1\"")), param.key);
+    assert!(CachePreviewParam::from_query("limit=1").is_err());
+    assert!(CachePreviewParam::from_query("key=a&limit=x").is_err());
+
+    let mut result_set = ResultSet::with_columns(vec![
+        ColumnDefinition::new("id", ColumnType::MYSQL_TYPE_LONG).table("shop", "users"),
+        ColumnDefinition::new("name", ColumnType::MYSQL_TYPE_VAR_STRING),
+        ColumnDefinition::new("created_at", ColumnType::MYSQL_TYPE_DATETIME),
+    ]);
+    result_set.add_row(vec![Value::Int(1), Value::from("alice"), Value::Date(2023, 1, 2, 3, 4, 5, 0)]);
+    result_set.add_row(vec![Value::Int(2), Value::NULL, Value::NULL]);
+    let compression_config = CacheCompressionConfig { min_bytes: 0, ..Default::default() };
+    let value = cache_codec::encode(&compression_config, &result_set.encode_text(false));
+    let byte_size = value.len();
+
+    let preview = CachePreview::decode("cache:\"select * from users\"", value, 1);
+    assert_eq!(None, preview.error);
+    assert_eq!(byte_size, preview.byte_size);
+    assert_eq!(result_set.encode_text(false).len(), preview.body_size);
+    assert_eq!(2, preview.row_count);
+    assert_eq!(vec![vec![serde_json::json!(1), serde_json::json!("alice"), serde_json::json!("2023-01-02 03:04:05")]], preview.rows);
+    assert_eq!(("users", "MYSQL_TYPE_LONG"), (preview.columns[0].table.as_str(), preview.columns[0].column_type.as_str()));
+
+    let preview = CachePreview::decode("cache:\"select 1\"", b"garbage".to_vec(), 1);
+    assert!(preview.error.is_some());
+}
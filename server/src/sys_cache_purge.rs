@@ -0,0 +1,77 @@
+use redis::{ErrorKind, RedisError, RedisResult};
+use serde::{Deserialize, Serialize};
+
+use crate::sys_config::VirtDBConfig;
+use crate::sys_redis::SysRedisClient;
+use crate::{sys_redis, utils};
+
+//每次从标签集合中取出并删除的key数
+const DELETE_BATCH_SIZE: usize = 500;
+
+/**
+ * 要清除的缓存
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "purge_type", content = "value", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PurgeTarget {
+    //一个cache_config缓存的所有语句
+    CacheConfig(i32),
+    //读取了该表的所有语句
+    Table(String),
+    //VIRTDB_TAG打的标签
+    Tag(String),
+    //一条具体的语句
+    Sql(String),
+}
+
+/// Delete the cached entries of `target`, returns the number of deleted keys.
+/// Tagged keys are popped from the tag set in batches, a key tagged during the purge is either deleted or stays in the set.
+pub fn purge(redis_client: &mut SysRedisClient, target: &PurgeTarget) -> RedisResult<usize> {
+    let tag = match target {
+        PurgeTarget::Sql(sql) => {
+            let cache_key = sys_redis::cache_key(&utils::sys_sql::remove_comments(sql.clone()));
+            return redis_client.del_each(&[cache_key]);
+        }
+        PurgeTarget::CacheConfig(cache_config_id) => sys_redis::cache_config_tag(*cache_config_id),
+        PurgeTarget::Table(table) => sys_redis::table_tag(&table.to_lowercase()),
+        PurgeTarget::Tag(tag) => tag.clone(),
+    };
+    let tag_key = sys_redis::cache_tag_key(&tag);
+    let mut deleted = 0;
+    //集合为空时Redis会自动删除集合
+    loop {
+        let cache_keys = redis_client.spop(&tag_key, DELETE_BATCH_SIZE)?;
+        if cache_keys.is_empty() {
+            break;
+        }
+        deleted += redis_client.del_each(&cache_keys)?;
+    }
+    Ok(deleted)
+}
+
+/// Run `purge` with a new connection to `nodes`, single node or cluster.
+/// The Redis client is blocking so the purge runs on the blocking pool.
+pub async fn purge_with_nodes(nodes: String, target: PurgeTarget) -> RedisResult<usize> {
+    tokio::task::spawn_blocking(move || {
+        let mut redis_client = SysRedisClient::new(&nodes)?;
+        purge(&mut redis_client, &target)
+    })
+        .await
+        .map_err(|e| RedisError::from((ErrorKind::IoError, "purge task fail", e.to_string())))?
+}
+
+/// Run a purge requested by admin
+pub async fn purge_with_config(sys_config: VirtDBConfig, target: PurgeTarget) {
+    match purge_with_nodes(sys_config.redis.nodes.clone(), target.clone()).await {
+        Ok(deleted) => info!("purged {} cache entries for {:?}", deleted, target),
+        Err(err) => warn!("purge cache fail for {:?}.err:{:?}", target, err),
+    }
+}
+
+#[test]
+fn test_purge_target_json() {
+    let target: PurgeTarget = serde_json::from_str(r#"{"purge_type":"CACHE_CONFIG","value":12}"#).unwrap();
+    assert_eq!(PurgeTarget::CacheConfig(12), target);
+    let target: PurgeTarget = serde_json::from_str(r#"{"purge_type":"SQL","value":"select 1"}"#).unwrap();
+    assert_eq!(PurgeTarget::Sql(String::from("select 1")), target);
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use mysql::prelude::Queryable;
+use mysql::{Conn, OptsBuilder};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::serve::cache_hint::{HINT_CACHE, HINT_REFRESH};
+use crate::sys_assistant_client::{self, DataWrapper};
+use crate::sys_config::VirtDBConfig;
+use crate::utils::sys_fingerprint;
+
+//两次上报之间最多记录的语句数,超过后不再记录新的语句
+const MAX_RECORDED_QUERIES: usize = 10_000;
+
+//(cache_config_id, sql_hash) -> 语句
+static RECORDED_QUERIES: Lazy<Mutex<HashMap<(i32, String), WarmQuery>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/**
+ * 命中cache_config的具体语句,随心跳上报给admin
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct WarmQuery {
+    pub cache_config_id: i32,
+    //执行语句的MySQL用户,预热只回放mysql.username自己的语句
+    #[serde(default)]
+    pub user_name: String,
+    pub schema_name: String,
+    //user_name、schema_name和sql_str的fnv1a
+    pub sql_hash: String,
+    pub sql_str: String,
+    pub hit_count: i64,
+}
+
+/**
+ * 一个cache_config的预热任务,来自admin
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarmTask {
+    pub cache_config_id: i32,
+    pub sql_template: String,
+    pub duration: i32,
+    //按出现次数排序的历史语句
+    #[serde(default)]
+    pub queries: Vec<WarmQuery>,
+    //admin中配置的参数,每组对应模板中的占位符,`?...`对应数组
+    #[serde(default)]
+    pub params: Vec<Vec<Value>>,
+}
+
+#[derive(Serialize, Debug)]
+struct WarmTaskParam {
+    cache_config_id: Option<i32>,
+    top_n: u64,
+    user_name: String,
+}
+
+/// Count one execution of `sql` by `user_name` matched by a cache config
+pub fn record_query(cache_config_id: i32, user_name: Option<&str>, schema_name: Option<&str>, sql: &str) {
+    let user_name = user_name.unwrap_or("");
+    let schema_name = schema_name.unwrap_or("");
+    let sql_hash = format!("{:016x}", sys_fingerprint::fnv1a(format!("{}\0{}\0{}", user_name, schema_name, sql).as_bytes()));
+    let mut recorded_queries = RECORDED_QUERIES.lock().unwrap();
+    let len = recorded_queries.len();
+    match recorded_queries.get_mut(&(cache_config_id, sql_hash.clone())) {
+        Some(query) => query.hit_count += 1,
+        None if len < MAX_RECORDED_QUERIES => {
+            recorded_queries.insert((cache_config_id, sql_hash.clone()), WarmQuery {
+                cache_config_id,
+                user_name: user_name.to_string(),
+                schema_name: schema_name.to_string(),
+                sql_hash,
+                sql_str: sql.to_string(),
+                hit_count: 1,
+            });
+        }
+        None => {}
+    }
+}
+
+/// Take the queries recorded since the last report
+pub fn take_recorded_queries() -> Vec<WarmQuery> {
+    let mut recorded_queries = RECORDED_QUERIES.lock().unwrap();
+    recorded_queries.drain().map(|(_, query)| query).collect()
+}
+
+/// Fill the placeholders of a cache config template with one parameter tuple.
+/// Strings are quoted, arrays fill `?...`, returns None when the tuple doesn't fit the template.
+pub fn render_template(template: &str, params: &[Value]) -> Option<String> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut result = String::with_capacity(template.len());
+    let mut params = params.iter();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        match c {
+            //引号内的?不是占位符
+            '\'' | '"' | '`' => {
+                let start = pos;
+                pos += 1;
+                while pos < chars.len() && chars[pos] != c {
+                    if chars[pos] == '\\' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+                pos = (pos + 1).min(chars.len());
+                result.extend(&chars[start..pos]);
+            }
+            '?' => {
+                let variadic = chars[pos + 1..].iter().collect::<String>().starts_with("...");
+                let value = params.next()?;
+                let literal = match (variadic, value) {
+                    (true, Value::Array(values)) if !values.is_empty() => {
+                        values.iter().map(to_literal).collect::<Option<Vec<String>>>()?.join(", ")
+                    }
+                    (true, Value::Array(_)) => return None,
+                    (_, value) => to_literal(value)?,
+                };
+                result.push_str(&literal);
+                pos += if variadic { 4 } else { 1 };
+            }
+            _ => {
+                result.push(c);
+                pos += 1;
+            }
+        }
+    }
+    if params.next().is_some() {
+        return None;
+    }
+    Some(result)
+}
+
+fn to_literal(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => Some(String::from("NULL")),
+        Value::Bool(v) => Some(String::from(if *v { "TRUE" } else { "FALSE" })),
+        Value::Number(v) => Some(v.to_string()),
+        Value::String(v) => Some(format!("'{}'", v.replace('\\', "\\\\").replace('\'', "\\'"))),
+        _ => None,
+    }
+}
+
+/// The statement sent through the proxy to refresh the cache of `sql`
+pub fn warm_sql(sql: &str, duration: i32) -> String {
+    format!("/*+ {} {}(ttl={}) */ {}", HINT_REFRESH, HINT_CACHE, duration, sql)
+}
+
+async fn fetch_warm_tasks(sys_config: &VirtDBConfig, cache_config_id: Option<i32>) -> Result<Vec<WarmTask>, String> {
+    let param = WarmTaskParam {
+        cache_config_id,
+        top_n: sys_config.cache_warm.top_n,
+        user_name: sys_config.mysql.username.clone(),
+    };
+    let response = sys_assistant_client::admin_post(&sys_config.admin, "/vt_node/cache_warm_tasks")
+        .json(&param)
+        .send().await
+        .map_err(|e| format!("{:?}", e))?;
+    let data_wrapper = response.json::<DataWrapper<Vec<WarmTask>>>().await
+        .map_err(|e| format!("{:?}", e))?;
+    if !data_wrapper.success {
+        return Err(data_wrapper.message);
+    }
+    Ok(data_wrapper.data.unwrap_or_default())
+}
+
+/// Fetch the warm tasks from admin and replay them through the proxy port.
+/// `cache_config_id` None warms every enabled cache config.
+pub async fn warm(sys_config: VirtDBConfig, cache_config_id: Option<i32>) {
+    //预热语句依赖VIRTDB_REFRESH和VIRTDB_CACHE
+    if !sys_config.cache_hint.users.contains(&sys_config.mysql.username) {
+        warn!("cache warm skipped, mysql.username {:?} is not in cache_hint.users", sys_config.mysql.username);
+        return;
+    }
+    let tasks = match fetch_warm_tasks(&sys_config, cache_config_id).await {
+        Ok(tasks) => tasks,
+        Err(err) => {
+            warn!("fetch cache warm tasks fail.cache_config_id:{:?},err:{}", cache_config_id, err);
+            return;
+        }
+    };
+    //mysql客户端是阻塞的,在单独的线程中执行
+    let _ = tokio::task::spawn_blocking(move || run_warm_tasks(&sys_config, tasks)).await;
+}
+
+fn run_warm_tasks(sys_config: &VirtDBConfig, tasks: Vec<WarmTask>) {
+    let interval = Duration::from_millis(1000 / sys_config.cache_warm.queries_per_second.max(1) as u64);
+    //按schema复用连接
+    let mut conns: HashMap<String, Conn> = HashMap::new();
+    let mut warmed = 0;
+    for task in tasks {
+        let mut statements: Vec<(String, String)> = task.queries.iter()
+            .filter(|query| query.user_name == sys_config.mysql.username)
+            .map(|query| (query.schema_name.clone(), query.sql_str.clone()))
+            .collect();
+        for params in task.params.iter() {
+            match render_template(&task.sql_template, params) {
+                Some(sql) => statements.push((String::new(), sql)),
+                None => warn!("cache warm params {:?} don't fit template {:?}", params, task.sql_template),
+            }
+        }
+        for (schema_name, sql) in statements {
+            if !conns.contains_key(&schema_name) {
+                let opts = OptsBuilder::new()
+                    .ip_or_hostname(Some("127.0.0.1"))
+                    .tcp_port(sys_config.server.port as u16)
+                    .user(Some(sys_config.mysql.username.clone()))
+                    .pass(Some(sys_config.mysql.password.clone()))
+                    .db_name(if schema_name.is_empty() { None } else { Some(schema_name.clone()) });
+                match Conn::new(opts) {
+                    Ok(conn) => {
+                        conns.insert(schema_name.clone(), conn);
+                    }
+                    Err(err) => {
+                        warn!("connect proxy for cache warm fail.schema:{:?},err:{:?}", schema_name, err);
+                        continue;
+                    }
+                }
+            }
+            let conn = conns.get_mut(&schema_name).unwrap();
+            if let Err(err) = conn.query_drop(warm_sql(&sql, task.duration)) {
+                warn!("cache warm query fail.sql:{:?},err:{:?}", sql, err);
+            } else {
+                warmed += 1;
+            }
+            thread::sleep(interval);
+        }
+    }
+    info!("cache warm finished, {} statements executed.", warmed);
+}
+
+pub fn enable_cache_warm_on_start(sys_config: VirtDBConfig) {
+    if !sys_config.cache_warm.on_start {
+        return;
+    }
+    tokio::spawn(async move {
+        //等待服务端口启动
+        tokio::time::sleep(Duration::from_secs(sys_config.cache_warm.start_delay_seconds)).await;
+        info!("cache warm on start.");
+        warm(sys_config, None).await;
+    });
+}
+
+#[test]
+fn test_render_template() {
+    let template = "select * from users where tenant_id = ? and id in (?...) and name != '?'";
+    let params = serde_json::from_str::<Vec<Value>>(r#"[1, ["a'b", 2], null]"#).unwrap();
+    assert_eq!(None, render_template(template, &params));
+    let params = serde_json::from_str::<Vec<Value>>(r#"[1, ["a'b", 2]]"#).unwrap();
+    assert_eq!(Some(String::from("select * from users where tenant_id = 1 and id in ('a\\'b', 2) and name != '?'")), render_template(template, &params));
+    let params = serde_json::from_str::<Vec<Value>>(r#"[1, []]"#).unwrap();
+    assert_eq!(None, render_template(template, &params));
+    assert_eq!(None, render_template(template, &[Value::from(1)]));
+
+    assert_eq!("/*+ VIRTDB_REFRESH VIRTDB_CACHE(ttl=60) */ select 1", warm_sql("select 1", 60));
+}
+
+#[test]
+fn test_record_query() {
+    record_query(-1, Some("app"), Some("db1"), "select 1");
+    record_query(-1, Some("app"), Some("db1"), "select 1");
+    record_query(-1, Some("app"), None, "select 1");
+    record_query(-1, Some("report"), Some("db1"), "select 1");
+    let queries: Vec<WarmQuery> = take_recorded_queries().into_iter().filter(|q| q.cache_config_id == -1).collect();
+    assert_eq!(3, queries.len());
+    assert_eq!(4, queries.iter().map(|q| q.hit_count).sum::<i64>());
+    assert_eq!(1, queries.iter().filter(|q| q.user_name == "report").count());
+}
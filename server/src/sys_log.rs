@@ -1,18 +1,49 @@
 #![allow(unused_variables)]
 use std::env;
+use std::path::Path;
 
 use log4rs::append::console::ConsoleAppender;
 use log4rs::append::file::FileAppender;
 use log4rs::append::rolling_file::{policy, RollingFileAppender};
 use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
-use log4rs::Config;
-use log4rs::config::{Appender, Root};
+use log4rs::{Config, Handle};
+use log4rs::config::{Appender, Logger, Root};
 use log4rs::encode::pattern::PatternEncoder;
 use log::LevelFilter;
+use once_cell::sync::OnceCell;
+use std::str::FromStr;
+
+use crate::sys_config::{AuditLogConfig, VirtDBConfig};
 
 const SIZE_MB: u64 = 1024 * 1024;
+//审计日志使用的target,只写入audit.log
+pub const AUDIT_LOG_TARGET: &str = "virtdb::audit";
+
+static LOG_HANDLE: OnceCell<Handle> = OnceCell::new();
 
 pub fn init_logger() -> anyhow::Result<()> {
+    let config = build_config(None, LevelFilter::Info)?;
+    let handle = log4rs::init_config(config).unwrap();
+    let _ = LOG_HANDLE.set(handle);
+    anyhow::Ok(())
+}
+
+/// Apply the log level and add the `audit.log` appender once the config is loaded, called again on reload.
+/// `audit.log` rolls the same way as `server.log`
+pub fn apply_log_config(sys_config: &VirtDBConfig) -> anyhow::Result<()> {
+    let audit_log_config = if sys_config.audit_log.enabled { Some(&sys_config.audit_log) } else { None };
+    let level = LevelFilter::from_str(&sys_config.log.level)?;
+    let config = build_config(audit_log_config, level)?;
+    match LOG_HANDLE.get() {
+        Some(handle) => handle.set_config(config),
+        None => {
+            log4rs::init_config(config).unwrap();
+        }
+    }
+    anyhow::Ok(())
+}
+
+fn build_config(audit_log_config: Option<&AuditLogConfig>, level: LevelFilter) -> anyhow::Result<Config> {
     let stdout = ConsoleAppender::builder().build();
 
     let file_count = 30;
@@ -21,12 +52,53 @@ pub fn init_logger() -> anyhow::Result<()> {
     let log_path = exec_file_path.parent()
         .unwrap()
         .join("logs");
-    let log_pattern = log_path.join("server.log");
+
+    let file_appender = rolling_file_appender(
+        &log_path,
+        "server",
+        "log",
+        "{d(%Y-%m-%d %H:%M:%S.%3f %Z)} {l} [{t} - {T}] {m}{n}",
+        file_size,
+        file_count,
+    );
+
+    let mut config_builder = Config::builder()
+        .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .appender(Appender::builder().build("file", Box::new(file_appender)));
+    // .logger(Logger::builder()
+    //     .appender("file")
+    //     .additive(false)
+    //     .build("app::requests", LevelFilter::Info))
+    if let Some(audit_log_config) = audit_log_config {
+        //每行一条JSON
+        let audit_appender = rolling_file_appender(
+            &log_path,
+            "audit",
+            "audit",
+            "{m}{n}",
+            SIZE_MB * audit_log_config.file_size_mb,
+            audit_log_config.file_count,
+        );
+        config_builder = config_builder
+            .appender(Appender::builder().build("audit", Box::new(audit_appender)))
+            .logger(Logger::builder()
+                .appender("audit")
+                .additive(false)
+                .build(AUDIT_LOG_TARGET, LevelFilter::Info));
+    }
+    let config = config_builder
+        .build(Root::builder().appender("stdout").appender("file").build(level))
+        .unwrap();
+    anyhow::Ok(config)
+}
+
+fn rolling_file_appender(log_path: &Path, file_name: &str, roll_name: &str, pattern: &str, file_size: u64, file_count: u32) -> RollingFileAppender {
+    let log_pattern = log_path.join(format!("{}.log", file_name));
 
     #[cfg(feature = "gzip")]
-        let roll_pattern = format!("{}/{}", log_path.to_string_lossy(), "log.{}.gz");
+        let roll_pattern = format!("{}/{}.{{}}.gz", log_path.to_string_lossy(), roll_name);
     #[cfg(not(feature = "gzip"))]
-        let roll_pattern = format!("{}/{}", log_path.to_string_lossy(), "log.{}");
+        let roll_pattern = format!("{}/{}.{{}}", log_path.to_string_lossy(), roll_name);
 
     let trigger = policy::compound::trigger::size::SizeTrigger::new(file_size);
     let roller = policy::compound::roll::fixed_window::FixedWindowRoller::builder()
@@ -34,23 +106,8 @@ pub fn init_logger() -> anyhow::Result<()> {
         .unwrap();
     let policy = policy::compound::CompoundPolicy::new(Box::new(trigger), Box::new(roller));
 
-    let file_appender = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S.%3f %Z)} {l} [{t} - {T}] {m}{n}",
-        )))
+    RollingFileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(pattern)))
         .build(&log_pattern, Box::new(policy))
-        .unwrap();
-
-    let config = Config::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .appender(Appender::builder().build("file", Box::new(file_appender)))
-        // .logger(Logger::builder()
-        //     .appender("file")
-        //     .additive(false)
-        //     .build("app::requests", LevelFilter::Info))
-        .build(Root::builder().appender("stdout").appender("file").build(LevelFilter::Info))
-        .unwrap();
-
-    let handle = log4rs::init_config(config).unwrap();
-    anyhow::Ok(())
+        .unwrap()
 }
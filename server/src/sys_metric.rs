@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::sys_assistant_client::{is_valid_node_token, VT_NODE_TOKEN_HEADER};
+use crate::sys_cache_preview::CachePreviewParam;
+use crate::sys_config::VirtDBConfig;
+use crate::{sys_cache_preview, sys_config, sys_session};
+
+//为写缓存暂存的响应字节数,所有连接共享
+static CACHE_BUFFER_BYTES: AtomicI64 = AtomicI64::new(0);
+//超过上限后放弃暂存的响应数
+static CACHE_BUFFER_ABANDONED: AtomicU64 = AtomicU64::new(0);
+
+pub fn add_cache_buffer_bytes(bytes: i64) {
+    CACHE_BUFFER_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn incr_cache_buffer_abandoned() {
+    CACHE_BUFFER_ABANDONED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn cache_buffer_bytes() -> i64 {
+    CACHE_BUFFER_BYTES.load(Ordering::Relaxed)
+}
+
+pub fn cache_buffer_abandoned() -> u64 {
+    CACHE_BUFFER_ABANDONED.load(Ordering::Relaxed)
+}
+
+/// Metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut text = String::new();
+    text.push_str("# HELP virtdb_cache_buffer_bytes Bytes of in-flight responses buffered for caching.\n");
+    text.push_str("# TYPE virtdb_cache_buffer_bytes gauge\n");
+    text.push_str(&format!("virtdb_cache_buffer_bytes {}\n", CACHE_BUFFER_BYTES.load(Ordering::Relaxed)));
+    text.push_str("# HELP virtdb_cache_buffer_abandoned_total Responses that exceeded the cacheable size and were streamed without caching.\n");
+    text.push_str("# TYPE virtdb_cache_buffer_abandoned_total counter\n");
+    text.push_str(&format!("virtdb_cache_buffer_abandoned_total {}\n", CACHE_BUFFER_ABANDONED.load(Ordering::Relaxed)));
+    text
+}
+
+//请求头中的节点token,头名不区分大小写
+fn request_token(request: &str) -> Option<&str> {
+    request.lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(VT_NODE_TOKEN_HEADER))
+        .map(|(_, value)| value.trim())
+}
+
+// GET /cache/preview?key=..|pattern=..[&limit=..&max_rows=..]
+async fn cache_preview(query: &str) -> (&'static str, &'static str, String) {
+    let param = match CachePreviewParam::from_query(query) {
+        Ok(param) => param,
+        Err(err) => return ("400 Bad Request", "text/plain", err),
+    };
+    match sys_cache_preview::preview(&sys_config::current_config(), &param).await {
+        Ok(previews) => ("200 OK", "application/json", serde_json::to_string(&previews).unwrap()),
+        Err(err) => {
+            warn!("preview cache {:?} fail.err:{}", param, err);
+            ("500 Internal Server Error", "text/plain", err)
+        }
+    }
+}
+
+// POST /sessions/kill?id=..
+fn kill_session(query: &str) -> (&'static str, &'static str, String) {
+    let id = query.split('&')
+        .filter_map(|v| v.strip_prefix("id="))
+        .next()
+        .and_then(|v| v.parse::<u64>().ok());
+    match id {
+        Some(id) => {
+            let killed = sys_session::kill(id);
+            info!("kill session {} from metric port, killed:{}", id, killed);
+            ("200 OK", "application/json", serde_json::json!({ "killed": killed }).to_string())
+        }
+        None => ("400 Bad Request", "text/plain", format!("invalid id in {:?}", query)),
+    }
+}
+
+//除/metrics外都只响应带有节点token的请求
+async fn respond(method: &str, path: &str, query: &str, authorized: bool) -> (&'static str, &'static str, String) {
+    match (method, path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", render()),
+        _ if !authorized => {
+            warn!("reject metric request {} {} without a valid {}", method, path, VT_NODE_TOKEN_HEADER);
+            ("401 Unauthorized", "text/plain", format!("invalid {}", VT_NODE_TOKEN_HEADER))
+        }
+        ("GET", "/cache/preview") => cache_preview(query).await,
+        ("GET", "/sessions") => {
+            let sessions = sys_session::list().iter().map(|session| session.view()).collect::<Vec<_>>();
+            ("200 OK", "application/json", serde_json::to_string(&sessions).unwrap())
+        }
+        ("POST", "/sessions/kill") => kill_session(query),
+        _ => ("404 Not Found", "text/plain", String::new()),
+    }
+}
+
+/// Serve `GET /metrics`, `GET /cache/preview`, `GET /sessions` and `POST /sessions/kill` on `metric.expose_port`,
+/// all but `/metrics` require the node token of the admin
+pub fn enable_metric_expose_job(sys_config: VirtDBConfig) {
+    let port = sys_config.metric.expose_port;
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("bind metric port {} fail.err:{:?}", port, err);
+                return;
+            }
+        };
+        info!("metric endpoint listening on {}", port);
+        loop {
+            let (mut socket, _) = match listener.accept().await {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("accept metric connection fail.err:{:?}", err);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                //请求行: GET /path?query HTTP/1.1
+                let mut request_line = request.split(' ');
+                let method = request_line.next().unwrap_or_default();
+                let target = request_line.next().unwrap_or_default();
+                let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                let authorized = is_valid_node_token(&sys_config::current_config().admin, request_token(&request).unwrap_or_default());
+                let (status, content_type, body) = respond(method, path, query, authorized).await;
+                let response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                       status, content_type, body.len(), body);
+                if let Err(err) = socket.write_all(response.as_bytes()).await {
+                    debug!("write metric response fail.err:{:?}", err);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_render() {
+    add_cache_buffer_bytes(1024);
+    incr_cache_buffer_abandoned();
+    let text = render();
+    assert!(text.contains("# TYPE virtdb_cache_buffer_bytes gauge\n"));
+    assert!(text.contains("virtdb_cache_buffer_abandoned_total "));
+    add_cache_buffer_bytes(-1024);
+
+    let request = "GET /sessions HTTP/1.1\r\nHost: 127.0.0.1\r\nx-virtdb-token:  s3cret \r\n\r\nX-VirtDB-Token: body";
+    assert_eq!(Some("s3cret"), request_token(request));
+    assert_eq!(None, request_token("GET /sessions HTTP/1.1\r\n\r\nX-VirtDB-Token: body"));
+
+    assert_eq!("400 Bad Request", kill_session("id=x").0);
+    assert_eq!("{\"killed\":false}", kill_session("id=0").2);
+}
+
+#[test]
+fn test_node_token() {
+    let mut admin_config = sys_config::AdminConfig { address: String::from("http://127.0.0.1:8080"), token: String::new() };
+    assert!(!is_valid_node_token(&admin_config, ""));
+    admin_config.token = String::from("s3cret");
+    assert!(is_valid_node_token(&admin_config, "s3cret"));
+    assert!(!is_valid_node_token(&admin_config, "s3cre"));
+    assert!(!is_valid_node_token(&admin_config, "s3creT"));
+}
+
+#[tokio::test]
+async fn test_respond() {
+    assert_eq!("200 OK", respond("GET", "/metrics", "", false).await.0);
+    assert_eq!("401 Unauthorized", respond("GET", "/sessions", "", false).await.0);
+    assert_eq!("401 Unauthorized", respond("POST", "/sessions/kill", "id=0", false).await.0);
+    assert_eq!("401 Unauthorized", respond("GET", "/cache/preview", "key=cache:1", false).await.0);
+    assert_eq!("200 OK", respond("GET", "/sessions", "", true).await.0);
+    assert_eq!("{\"killed\":false}", respond("POST", "/sessions/kill", "id=0", true).await.2);
+}
@@ -1,6 +1,60 @@
 #![allow(unused_imports, dead_code)]
+use once_cell::sync::Lazy;
 use redis::cluster::{ClusterClient, ClusterClientBuilder, ClusterConnection};
-use redis::{Client, Commands, Connection, RedisResult};
+use redis::{Client, Commands, Connection, RedisResult, Script};
+
+//把缓存key加入标签集合,集合的过期时间只延长不缩短,避免短ttl的缓存让集合先于长ttl的缓存过期
+static TAG_ADD_SCRIPT: Lazy<Script> = Lazy::new(|| Script::new(r"
+redis.call('SADD', KEYS[1], ARGV[1])
+if redis.call('TTL', KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+"));
+
+/// Redis key of the cached response of a SQL, comments must be removed from the SQL first
+pub fn cache_key(sql: &str) -> String {
+    format!("cache:\"{}\"", sql)
+}
+
+/// Redis set holding the cache keys tagged with `tag`
+pub fn cache_tag_key(tag: &str) -> String {
+    format!("cache_tag:{}", tag)
+}
+
+/// Implicit tag of the entries cached by a cache config
+pub fn cache_config_tag(cache_config_id: i32) -> String {
+    format!("cache_config:{}", cache_config_id)
+}
+
+/// Implicit tag of the entries read from a table, `table` is lowercased
+pub fn table_tag(table: &str) -> String {
+    format!("table:{}", table)
+}
+
+/// Keys matching `pattern`, at most `limit`. Each node of `nodes` is scanned on its own,
+/// a cluster routes SCAN to a single node only
+pub fn scan_match(nodes: &str, pattern: &str, limit: usize) -> RedisResult<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    for node in nodes.split(',').map(|node| node.trim()).filter(|node| !node.is_empty()) {
+        let mut con = Client::open(node)?.get_connection()?;
+        for key in con.scan_match::<_, String>(pattern)? {
+            //从节点上的key和主节点重复
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+            if keys.len() >= limit {
+                return Ok(keys);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Add `cache_key` to the set of `tag_key` and make the set live at least `seconds`
+pub async fn add_to_tag(redis_conn: &mut redis::aio::Connection, tag_key: &str, cache_key: &str, seconds: usize) -> RedisResult<()> {
+    TAG_ADD_SCRIPT.key(tag_key).arg(cache_key).arg(seconds).invoke_async(redis_conn).await
+}
 
 pub enum SysRedisClient {
     Single(Connection),
@@ -52,10 +106,55 @@ impl SysRedisClient {
         }
     }
 
+    pub fn get_bytes(&mut self, key: &str) -> RedisResult<Option<Vec<u8>>> {
+        match self {
+            Self::Single(con) => con.get(key),
+            Self::Cluster(con) => con.get(key),
+        }
+    }
+
+    pub fn ttl(&mut self, key: &str) -> RedisResult<i64> {
+        match self {
+            Self::Single(con) => con.ttl(key),
+            Self::Cluster(con) => con.ttl(key),
+        }
+    }
+
     pub fn exists(&mut self, key: &str) -> RedisResult<bool> {
         match self {
             Self::Single(con) => con.exists(key),
             Self::Cluster(con) => con.exists(key),
         }
     }
-}
\ No newline at end of file
+
+    /// Remove and return up to `count` random members of a set
+    pub fn spop(&mut self, key: &str, count: usize) -> RedisResult<Vec<String>> {
+        let mut cmd = redis::cmd("SPOP");
+        cmd.arg(key).arg(count);
+        match self {
+            Self::Single(con) => cmd.query(con),
+            Self::Cluster(con) => cmd.query(con),
+        }
+    }
+
+    /// Delete each key with its own DEL, keys in different cluster slots can't share one
+    pub fn del_each(&mut self, keys: &[String]) -> RedisResult<usize> {
+        match self {
+            Self::Single(con) => {
+                let mut pipe = redis::pipe();
+                keys.iter().for_each(|key| {
+                    pipe.del(key);
+                });
+                let counts: Vec<usize> = pipe.query(con)?;
+                Ok(counts.into_iter().sum())
+            }
+            Self::Cluster(con) => keys.iter().try_fold(0, |deleted, key| Ok(deleted + con.del::<_, usize>(key)?)),
+        }
+    }
+}
+
+#[test]
+fn test_cache_key() {
+    //与旧版本写入的key保持一致,升级后已有的缓存仍然可用
+    assert_eq!("cache:\"select * from t where name = 'a\\b'\"", cache_key("select * from t where name = 'a\\b'"));
+}
@@ -0,0 +1,53 @@
+use std::sync::Mutex;
+
+use once_cell::sync::{Lazy, OnceCell};
+
+use crate::{sys_config, sys_log};
+
+//启动时使用的配置文件和--set参数,环境变量在每次加载时重新读取
+static CONFIG_SOURCE: OnceCell<(String, Vec<String>)> = OnceCell::new();
+//SIGHUP和admin同时触发时依次执行
+static RELOAD_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Re-read the config file and apply the fields that can change without a restart.
+/// Returns the changed fields that need a restart, the current config is kept when the file is invalid.
+pub fn reload() -> Result<Vec<&'static str>, String> {
+    let (config_file, cli_overrides) = CONFIG_SOURCE.get().ok_or_else(|| String::from("config file not set"))?;
+    let _guard = RELOAD_LOCK.lock().unwrap();
+    let new_config = sys_config::parse_config(config_file, cli_overrides)?;
+    sys_config::validate(&new_config)?;
+    let old_config = sys_config::current_config();
+    let restart_required = sys_config::restart_required_fields(&old_config, &new_config);
+    let merged = sys_config::merge_live_fields(&old_config, new_config);
+    sys_log::apply_log_config(&merged).map_err(|e| e.to_string())?;
+    sys_config::set_current_config(merged);
+    Ok(restart_required)
+}
+
+/// Reload and log the result, `trigger` is SIGHUP or admin
+pub fn reload_and_report(trigger: &str) {
+    match reload() {
+        Ok(restart_required) if restart_required.is_empty() => info!("config reloaded by {}", trigger),
+        Ok(restart_required) => warn!("config reloaded by {}, changes of {:?} need a restart", trigger, restart_required),
+        Err(err) => warn!("config reload by {} fail, keep the current config.err:{}", trigger, err),
+    }
+}
+
+//收到SIGHUP时重新加载配置,已有的连接在下一个语句使用新配置
+pub fn enable_config_reload_job(config_file: String, cli_overrides: Vec<String>) {
+    let _ = CONFIG_SOURCE.set((config_file, cli_overrides));
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                warn!("listen SIGHUP fail.err:{:?}", err);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            reload_and_report("SIGHUP");
+        }
+    });
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::Notify;
+
+//MySQL的ER_CONNECTION_KILLED
+pub const ER_CONNECTION_KILLED: u16 = 1927;
+pub const KILLED_SQL_STATE: [u8; 5] = *b"70100";
+pub const KILLED_MESSAGE: &str = "Connection was killed";
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+//所有客户端连接,用于VIRTDB SHOW CONNECTIONS和metric端口的/sessions
+static SESSIONS: Lazy<Mutex<HashMap<u64, SessionEntry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+type SessionEntry = (SessionInfo, Arc<SessionHandle>);
+
+/// A client connection seen by this proxy
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub client_addr: SocketAddr,
+    pub user: Option<String>,
+    pub schema: Option<String>,
+    pub connected_at: DateTime<Local>,
+    //正在执行的语句和开始时间
+    pub statement: Option<(String, Instant)>,
+    //从客户端读取和发送给客户端的字节数,list()时从SessionHandle读取
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub cache_hits: u64,
+    //MySQL最近一次返回的状态中有SERVER_STATUS_IN_TRANS
+    pub in_transaction: bool,
+    pub backend_connection_id: Option<u32>,
+}
+
+/// Parts of a session used on every read and write, so they don't need the session lock
+#[derive(Debug, Default)]
+pub struct SessionHandle {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    killed: Notify,
+}
+
+impl SessionHandle {
+    pub fn add_bytes_in(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_out(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Resolves when the session is killed, e.g. from the admin
+    pub async fn wait_killed(&self) {
+        self.killed.notified().await
+    }
+}
+
+/**
+ * 连接的JSON格式,metric端口GET /sessions返回
+ */
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SessionView {
+    pub id: u64,
+    pub client_addr: String,
+    pub user: Option<String>,
+    pub schema: Option<String>,
+    pub connected_at: String,
+    pub statement: Option<String>,
+    pub elapsed_ms: Option<u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub cache_hits: u64,
+    pub in_transaction: bool,
+    pub backend_connection_id: Option<u32>,
+}
+
+impl SessionInfo {
+    pub fn view(&self) -> SessionView {
+        SessionView {
+            id: self.id,
+            client_addr: self.client_addr.to_string(),
+            user: self.user.clone(),
+            schema: self.schema.clone(),
+            connected_at: self.connected_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            statement: self.statement.as_ref().map(|(sql, _)| sql.clone()),
+            elapsed_ms: self.statement.as_ref().map(|(_, started_at)| started_at.elapsed().as_millis() as u64),
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            cache_hits: self.cache_hits,
+            in_transaction: self.in_transaction,
+            backend_connection_id: self.backend_connection_id,
+        }
+    }
+}
+
+/// Register a new connection, returns its session id and handle
+pub fn register(client_addr: SocketAddr) -> (u64, Arc<SessionHandle>) {
+    let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    let session = SessionInfo {
+        id,
+        client_addr,
+        user: None,
+        schema: None,
+        connected_at: Local::now(),
+        statement: None,
+        bytes_in: 0,
+        bytes_out: 0,
+        cache_hits: 0,
+        in_transaction: false,
+        backend_connection_id: None,
+    };
+    let session_handle = Arc::new(SessionHandle::default());
+    SESSIONS.lock().unwrap().insert(id, (session, session_handle.clone()));
+    (id, session_handle)
+}
+
+pub fn unregister(id: u64) {
+    SESSIONS.lock().unwrap().remove(&id);
+}
+
+/// The handle of a registered session
+pub fn handle(id: u64) -> Option<Arc<SessionHandle>> {
+    SESSIONS.lock().unwrap().get(&id).map(|(_, handle)| handle.clone())
+}
+
+pub fn update(id: u64, f: impl FnOnce(&mut SessionInfo)) {
+    if let Some((session, _)) = SESSIONS.lock().unwrap().get_mut(&id) {
+        f(session);
+    }
+}
+
+/// Close a session, returns false if it doesn't exist
+pub fn kill(id: u64) -> bool {
+    match handle(id) {
+        Some(handle) => {
+            //连接还没开始等待时保留通知
+            handle.killed.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+/// All sessions ordered by id
+pub fn list() -> Vec<SessionInfo> {
+    let mut sessions: Vec<SessionInfo> = SESSIONS.lock().unwrap().values()
+        .map(|(session, handle)| {
+            let mut session = session.clone();
+            session.bytes_in = handle.bytes_in.load(Ordering::Relaxed);
+            session.bytes_out = handle.bytes_out.load(Ordering::Relaxed);
+            session
+        })
+        .collect();
+    sessions.sort_by_key(|session| session.id);
+    sessions
+}
+
+/// The schema selected by a `USE` statement
+pub fn use_schema(sql: &str) -> Option<String> {
+    let sql = sql.trim().trim_end_matches(';').trim_end();
+    let (keyword, schema) = sql.split_once(char::is_whitespace)?;
+    if !keyword.eq_ignore_ascii_case("USE") {
+        return None;
+    }
+    let schema = schema.trim().trim_matches('`');
+    if schema.is_empty() {
+        None
+    } else {
+        Some(schema.to_string())
+    }
+}
+
+#[test]
+fn test_sessions() {
+    let client_addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+    let (id, _) = register(client_addr);
+    update(id, |session| session.user = Some(String::from("app")));
+    let session = list().into_iter().find(|session| session.id == id).unwrap();
+    assert_eq!(Some(String::from("app")), session.user);
+    unregister(id);
+    assert!(list().iter().all(|session| session.id != id));
+}
+
+#[tokio::test]
+async fn test_session_handle() {
+    let client_addr: SocketAddr = "127.0.0.1:50001".parse().unwrap();
+    let (id, session_handle) = register(client_addr);
+    assert!(Arc::ptr_eq(&session_handle, &handle(id).unwrap()));
+    session_handle.add_bytes_in(10);
+    session_handle.add_bytes_out(300);
+    update(id, |session| session.statement = Some((String::from("select 1"), Instant::now())));
+    let view = list().into_iter().find(|session| session.id == id).unwrap().view();
+    assert_eq!((10, 300), (view.bytes_in, view.bytes_out));
+    assert_eq!(Some(String::from("select 1")), view.statement);
+    assert!(view.elapsed_ms.is_some());
+
+    assert!(kill(id));
+    let killed = tokio::time::timeout(std::time::Duration::from_secs(1), session_handle.wait_killed()).await;
+    assert!(killed.is_ok());
+    unregister(id);
+    assert!(!kill(id));
+
+    assert_eq!(Some(String::from("shop")), use_schema("use `shop`;"));
+    assert_eq!(Some(String::from("shop")), use_schema(" USE shop"));
+    assert_eq!(None, use_schema("user_defined()"));
+    assert_eq!(None, use_schema("select 1"));
+}
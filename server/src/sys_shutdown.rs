@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::sys_config::ShutdownConfig;
+
+//MySQL的ER_SERVER_SHUTDOWN
+pub const ER_SERVER_SHUTDOWN: u16 = 1053;
+pub const SHUTDOWN_SQL_STATE: [u8; 5] = *b"08S01";
+pub const SHUTDOWN_MESSAGE: &str = "Server shutdown in progress";
+
+const PHASE_RUNNING: u8 = 0;
+//不再接受新连接,新语句返回错误,等待执行中的语句结束
+const PHASE_DRAINING: u8 = 1;
+//关闭剩余的连接
+const PHASE_CLOSING: u8 = 2;
+
+static PHASE: Lazy<watch::Sender<u8>> = Lazy::new(|| watch::channel(PHASE_RUNNING).0);
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+static RUNNING_STATEMENTS: AtomicUsize = AtomicUsize::new(0);
+//处于事务中的连接数,停止服务时等待事务结束
+static OPEN_TRANSACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+//客户端连接的生命周期内持有
+pub struct SessionGuard;
+
+impl SessionGuard {
+    pub fn new() -> SessionGuard {
+        SESSIONS.fetch_add(1, Ordering::SeqCst);
+        SessionGuard
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        SESSIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn statement_started() {
+    RUNNING_STATEMENTS.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn statement_finished() {
+    RUNNING_STATEMENTS.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// A session entered or left a transaction
+pub fn transaction_changed(in_transaction: bool) {
+    if in_transaction {
+        OPEN_TRANSACTIONS.fetch_add(1, Ordering::SeqCst);
+    } else {
+        OPEN_TRANSACTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn session_count() -> usize {
+    SESSIONS.load(Ordering::SeqCst)
+}
+
+pub fn running_statement_count() -> usize {
+    RUNNING_STATEMENTS.load(Ordering::SeqCst)
+}
+
+pub fn open_transaction_count() -> usize {
+    OPEN_TRANSACTIONS.load(Ordering::SeqCst)
+}
+
+pub fn is_draining() -> bool {
+    *PHASE.borrow() >= PHASE_DRAINING
+}
+
+async fn wait_phase(phase: u8) {
+    let mut receiver = PHASE.subscribe();
+    while *receiver.borrow_and_update() < phase {
+        if receiver.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Resolves when the server stops accepting connections
+pub async fn wait_draining() {
+    wait_phase(PHASE_DRAINING).await
+}
+
+/// Resolves when the remaining sessions should be closed
+pub async fn wait_closing() {
+    wait_phase(PHASE_CLOSING).await
+}
+
+/// Resolves on SIGTERM or Ctrl-C
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => info!("received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => info!("received SIGINT"),
+                }
+                return;
+            }
+            Err(err) => warn!("listen SIGTERM fail.err:{:?}", err),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    info!("received SIGINT");
+}
+
+//每隔一段时间检查一次,条件满足时返回true,超时返回false
+async fn wait_until(condition: impl Fn() -> bool, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    true
+}
+
+/// Stop accepting connections and reject new statements
+pub fn begin_drain() {
+    PHASE.send_replace(PHASE_DRAINING);
+    info!("shutdown: stop accepting connections, {} sessions, {} running statements, {} open transactions",
+        SESSIONS.load(Ordering::SeqCst), RUNNING_STATEMENTS.load(Ordering::SeqCst), OPEN_TRANSACTIONS.load(Ordering::SeqCst));
+}
+
+/// Wait for the running statements and open transactions up to the drain deadline, then close the remaining sessions
+pub async fn drain(shutdown_config: &ShutdownConfig) {
+    let is_idle = || RUNNING_STATEMENTS.load(Ordering::SeqCst) == 0 && OPEN_TRANSACTIONS.load(Ordering::SeqCst) == 0;
+    let drained = wait_until(is_idle, Duration::from_secs(shutdown_config.drain_timeout_seconds)).await;
+    if !drained {
+        warn!("shutdown: {} statements still running, {} transactions still open after {}s",
+            RUNNING_STATEMENTS.load(Ordering::SeqCst), OPEN_TRANSACTIONS.load(Ordering::SeqCst), shutdown_config.drain_timeout_seconds);
+    }
+    PHASE.send_replace(PHASE_CLOSING);
+    let closed = wait_until(|| SESSIONS.load(Ordering::SeqCst) == 0, Duration::from_secs(shutdown_config.close_timeout_seconds)).await;
+    if !closed {
+        warn!("shutdown: {} sessions not closed", SESSIONS.load(Ordering::SeqCst));
+    }
+}
+
+/// Wait for the jobs to write what is left in their channels, the channels close when all sessions are gone
+pub async fn flush(shutdown_config: &ShutdownConfig, jobs: Vec<JoinHandle<()>>) {
+    let all_jobs = async move {
+        for job in jobs {
+            let _ = job.await;
+        }
+    };
+    if tokio::time::timeout(Duration::from_secs(shutdown_config.flush_timeout_seconds), all_jobs).await.is_err() {
+        warn!("shutdown: channels not flushed after {}s", shutdown_config.flush_timeout_seconds);
+    }
+}
+
+#[tokio::test]
+async fn test_wait_until() {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    assert!(!wait_until(|| COUNTER.load(Ordering::SeqCst) > 0, Duration::from_millis(250)).await);
+    tokio::spawn(async {
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        COUNTER.fetch_add(1, Ordering::SeqCst);
+    });
+    assert!(wait_until(|| COUNTER.load(Ordering::SeqCst) > 0, Duration::from_secs(5)).await);
+}
@@ -1,3 +1,6 @@
 pub mod sys_sql;
 pub mod sys_path;
-pub mod sys_datetime;
\ No newline at end of file
+pub mod sys_datetime;
+pub mod sys_hint;
+pub mod sys_pattern_index;
+pub mod sys_fingerprint;
\ No newline at end of file
@@ -0,0 +1,294 @@
+use sqlparser::ast::{Expr, FunctionArg, FunctionArgExpr, JoinConstraint, JoinOperator, OnInsert, OrderByExpr, Query, Select, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, UnaryOperator, Value};
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::parser::Parser;
+
+use super::sys_sql::VARIADIC_PLACEHOLDER;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// SQL指纹,字面量全部替换为占位符,IN列表折叠为`?...`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SqlFingerprint {
+    //可以直接作为缓存模板使用
+    pub pattern: String,
+    //pattern的FNV-1a哈希
+    pub digest: u64,
+}
+
+impl SqlFingerprint {
+    pub fn digest_hex(&self) -> String {
+        format!("{:016x}", self.digest)
+    }
+}
+
+/// Parse the SQL and replace every literal in the AST with a placeholder,
+/// including subqueries, UNION branches, CTEs, JOIN ON, HAVING and LIMIT.
+/// Returns None if the SQL can not be parsed.
+pub fn fingerprint(sql: &str) -> Option<SqlFingerprint> {
+    let mut statements = Parser::parse_sql(&MySqlDialect {}, sql).ok()?;
+    if statements.is_empty() {
+        return None;
+    }
+    statements.iter_mut().for_each(normalize_statement);
+    let pattern = statements.iter().map(|s| s.to_string()).collect::<Vec<String>>().join("; ");
+    Some(SqlFingerprint {
+        digest: fnv1a(pattern.as_bytes()),
+        pattern,
+    })
+}
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, b| (hash ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn placeholder() -> Expr {
+    Expr::Value(Value::Placeholder(String::from("?")))
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Value(Value::Null) => false,
+        Expr::Value(_) => true,
+        Expr::UnaryOp { op: UnaryOperator::Minus | UnaryOperator::Plus, expr } => is_literal(expr),
+        Expr::TypedString { .. } => true,
+        _ => false,
+    }
+}
+
+fn normalize_statement(statement: &mut Statement) {
+    match statement {
+        Statement::Query(query) => normalize_query(query),
+        Statement::Insert { source, partitioned, on, .. } => {
+            normalize_query(source);
+            partitioned.iter_mut().flatten().for_each(normalize_expr);
+            if let Some(OnInsert::DuplicateKeyUpdate(assignments)) = on {
+                assignments.iter_mut().for_each(|a| normalize_expr(&mut a.value));
+            }
+        }
+        Statement::Update { table, assignments, from, selection } => {
+            normalize_table(table);
+            assignments.iter_mut().for_each(|a| normalize_expr(&mut a.value));
+            from.iter_mut().for_each(normalize_table);
+            selection.iter_mut().for_each(normalize_expr);
+        }
+        Statement::Delete { selection, .. } => selection.iter_mut().for_each(normalize_expr),
+        _ => {}
+    }
+}
+
+fn normalize_query(query: &mut Query) {
+    if let Some(with) = query.with.as_mut() {
+        with.cte_tables.iter_mut().for_each(|cte| normalize_query(&mut cte.query));
+    }
+    normalize_set_expr(&mut query.body);
+    query.order_by.iter_mut().for_each(normalize_order_by);
+    query.limit.iter_mut().for_each(normalize_expr);
+    if let Some(offset) = query.offset.as_mut() {
+        normalize_expr(&mut offset.value);
+    }
+    if let Some(fetch) = query.fetch.as_mut() {
+        fetch.quantity.iter_mut().for_each(normalize_expr);
+    }
+}
+
+fn normalize_set_expr(set_expr: &mut SetExpr) {
+    match set_expr {
+        SetExpr::Select(select) => normalize_select(select),
+        SetExpr::Query(query) => normalize_query(query),
+        SetExpr::SetOperation { left, right, .. } => {
+            normalize_set_expr(left);
+            normalize_set_expr(right);
+        }
+        SetExpr::Values(values) => {
+            //多行VALUES只保留第一行
+            values.0.truncate(1);
+            values.0.iter_mut().flatten().for_each(normalize_expr);
+        }
+        SetExpr::Insert(statement) => normalize_statement(statement),
+    }
+}
+
+fn normalize_select(select: &mut Select) {
+    if let Some(top) = select.top.as_mut() {
+        top.quantity.iter_mut().for_each(normalize_expr);
+    }
+    for item in select.projection.iter_mut() {
+        match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => normalize_expr(expr),
+            _ => {}
+        }
+    }
+    select.from.iter_mut().for_each(normalize_table);
+    select.lateral_views.iter_mut().for_each(|v| normalize_expr(&mut v.lateral_view));
+    select.selection.iter_mut().for_each(normalize_expr);
+    select.group_by.iter_mut().for_each(normalize_expr);
+    select.cluster_by.iter_mut().for_each(normalize_expr);
+    select.distribute_by.iter_mut().for_each(normalize_expr);
+    select.sort_by.iter_mut().for_each(normalize_expr);
+    select.having.iter_mut().for_each(normalize_expr);
+    select.qualify.iter_mut().for_each(normalize_expr);
+}
+
+fn normalize_table(table: &mut TableWithJoins) {
+    normalize_table_factor(&mut table.relation);
+    for join in table.joins.iter_mut() {
+        normalize_table_factor(&mut join.relation);
+        match &mut join.join_operator {
+            JoinOperator::Inner(JoinConstraint::On(expr))
+            | JoinOperator::LeftOuter(JoinConstraint::On(expr))
+            | JoinOperator::RightOuter(JoinConstraint::On(expr))
+            | JoinOperator::FullOuter(JoinConstraint::On(expr)) => normalize_expr(expr),
+            _ => {}
+        }
+    }
+}
+
+fn normalize_table_factor(factor: &mut TableFactor) {
+    match factor {
+        TableFactor::Table { args, with_hints, .. } => {
+            args.iter_mut().flatten().for_each(normalize_function_arg);
+            with_hints.iter_mut().for_each(normalize_expr);
+        }
+        TableFactor::Derived { subquery, .. } => normalize_query(subquery),
+        TableFactor::TableFunction { expr, .. } => normalize_expr(expr),
+        TableFactor::UNNEST { array_expr, .. } => normalize_expr(array_expr),
+        TableFactor::NestedJoin(table) => normalize_table(table),
+    }
+}
+
+fn normalize_function_arg(arg: &mut FunctionArg) {
+    match arg {
+        FunctionArg::Named { arg: FunctionArgExpr::Expr(expr), .. }
+        | FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => normalize_expr(expr),
+        _ => {}
+    }
+}
+
+fn normalize_order_by(order_by: &mut OrderByExpr) {
+    normalize_expr(&mut order_by.expr)
+}
+
+fn normalize_expr(expr: &mut Expr) {
+    if is_literal(expr) {
+        *expr = placeholder();
+        return;
+    }
+    match expr {
+        Expr::InList { expr, list, .. } => {
+            normalize_expr(expr);
+            list.iter_mut().for_each(normalize_expr);
+            //值列表折叠为一个`?...`,不同长度的IN得到相同的指纹
+            if !list.is_empty() && list.iter().all(|e| matches!(e, Expr::Value(Value::Placeholder(_)))) {
+                *list = vec![Expr::Value(Value::Placeholder(String::from(VARIADIC_PLACEHOLDER)))];
+            }
+        }
+        Expr::InSubquery { expr, subquery, .. } => {
+            normalize_expr(expr);
+            normalize_query(subquery);
+        }
+        Expr::Exists(query) | Expr::Subquery(query) => normalize_query(query),
+        Expr::JsonAccess { left, right, .. } => {
+            normalize_expr(left);
+            normalize_expr(right);
+        }
+        Expr::IsDistinctFrom(left, right) | Expr::IsNotDistinctFrom(left, right)
+        | Expr::BinaryOp { left, right, .. } => {
+            normalize_expr(left);
+            normalize_expr(right);
+        }
+        Expr::InUnnest { expr, array_expr, .. } => {
+            normalize_expr(expr);
+            normalize_expr(array_expr);
+        }
+        Expr::Between { expr, low, high, .. } => {
+            normalize_expr(expr);
+            normalize_expr(low);
+            normalize_expr(high);
+        }
+        Expr::CompositeAccess { expr, .. }
+        | Expr::IsFalse(expr) | Expr::IsTrue(expr) | Expr::IsNull(expr) | Expr::IsNotNull(expr)
+        | Expr::AnyOp(expr) | Expr::AllOp(expr)
+        | Expr::UnaryOp { expr, .. }
+        | Expr::Cast { expr, .. } | Expr::TryCast { expr, .. }
+        | Expr::Extract { expr, .. }
+        | Expr::Collate { expr, .. }
+        | Expr::Nested(expr) => normalize_expr(expr),
+        Expr::Position { expr, r#in } => {
+            normalize_expr(expr);
+            normalize_expr(r#in);
+        }
+        Expr::Substring { expr, substring_from, substring_for } => {
+            normalize_expr(expr);
+            substring_from.iter_mut().for_each(|e| normalize_expr(e));
+            substring_for.iter_mut().for_each(|e| normalize_expr(e));
+        }
+        Expr::Trim { expr, trim_where } => {
+            normalize_expr(expr);
+            if let Some((_, e)) = trim_where {
+                normalize_expr(e);
+            }
+        }
+        Expr::MapAccess { column, keys } => {
+            normalize_expr(column);
+            keys.iter_mut().for_each(normalize_expr);
+        }
+        Expr::Function(function) => {
+            function.args.iter_mut().for_each(normalize_function_arg);
+            if let Some(over) = function.over.as_mut() {
+                over.partition_by.iter_mut().for_each(normalize_expr);
+                over.order_by.iter_mut().for_each(normalize_order_by);
+            }
+        }
+        Expr::Case { operand, conditions, results, else_result } => {
+            operand.iter_mut().for_each(|e| normalize_expr(e));
+            conditions.iter_mut().for_each(normalize_expr);
+            results.iter_mut().for_each(normalize_expr);
+            else_result.iter_mut().for_each(|e| normalize_expr(e));
+        }
+        Expr::ListAgg(list_agg) => {
+            normalize_expr(&mut list_agg.expr);
+            list_agg.separator.iter_mut().for_each(|e| normalize_expr(e));
+            list_agg.within_group.iter_mut().for_each(normalize_order_by);
+        }
+        Expr::GroupingSets(sets) | Expr::Cube(sets) | Expr::Rollup(sets) => {
+            sets.iter_mut().flatten().for_each(normalize_expr);
+        }
+        Expr::Tuple(list) => list.iter_mut().for_each(normalize_expr),
+        Expr::ArrayIndex { obj, indexes } => {
+            normalize_expr(obj);
+            indexes.iter_mut().for_each(normalize_expr);
+        }
+        Expr::Array(array) => array.elem.iter_mut().for_each(normalize_expr),
+        _ => {}
+    }
+}
+
+#[test]
+fn test_fingerprint() {
+    let pattern = |sql: &str| fingerprint(sql).map(|f| f.pattern);
+
+    assert_eq!(Some(String::from("SELECT * FROM article WHERE id = ? LIMIT ?")), pattern("select * from article where id = 1 limit 10"));
+    assert_eq!(
+        fingerprint("select * from article where id = 1 limit 10").map(|f| f.digest),
+        fingerprint("SELECT *   FROM article WHERE id = 2 LIMIT 20").map(|f| f.digest),
+    );
+    assert_eq!(
+        Some(String::from("SELECT a.id FROM article AS a JOIN channel AS c ON a.channel_id = c.id AND c.status = ? WHERE a.id IN (?...) AND a.tenant_id IN (SELECT id FROM tenant WHERE level > ?) GROUP BY a.id HAVING count(?) > ? ORDER BY a.id DESC LIMIT ? OFFSET ?")),
+        pattern("select a.id from article a join channel c on a.channel_id = c.id and c.status = 'on' where a.id in (1, 2, -3) and a.tenant_id in (select id from tenant where level > 2) group by a.id having count(1) > 5 order by a.id desc limit 10 offset 20"),
+    );
+    assert_eq!(pattern("select id from a where id in (1)"), pattern("select id from a where id in (1, 2, 3)"));
+    assert_eq!(
+        Some(String::from("WITH t AS (SELECT id FROM a WHERE b = ?) SELECT id FROM t UNION SELECT id FROM c WHERE d = ?")),
+        pattern("with t as (select id from a where b = 'x') select id from t union select id from c where d = 4"),
+    );
+    assert_eq!(Some(String::from("INSERT INTO article (id, title) VALUES (?, ?)")), pattern("insert into article (id, title) values (1, 'a'), (2, 'b')"));
+    assert_eq!(Some(String::from("UPDATE article SET title = ? WHERE id = ? AND deleted IS NULL")), pattern("update article set title = 'x' where id = 1 and deleted is null"));
+    assert_eq!(None, pattern("select from where"));
+}
+
+#[test]
+fn test_fnv1a() {
+    assert_eq!(0xcbf29ce484222325, fnv1a(b""));
+    assert_eq!(0xaf63dc4c8601ec8c, fnv1a(b"a"));
+}
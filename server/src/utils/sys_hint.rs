@@ -0,0 +1,84 @@
+use super::sys_sql;
+
+/// Parse the optimizer hints in `/*+ ... */` comments,
+/// e.g. `/*+ MAX_EXECUTION_TIME(1000) BKA(t1) */` returns `[("MAX_EXECUTION_TIME", "1000"), ("BKA", "t1")]`.
+/// Hint names are uppercased, arguments are kept as written, a hint without parentheses has empty arguments.
+/// Only real comments are parsed, `/*+` inside a string literal is not a hint.
+pub fn parse_hints(sql: &str) -> Vec<(String, String)> {
+    let mut hints = vec![];
+    for comment in sys_sql::split_comments(sql).1 {
+        //未闭合的注释不是hint
+        if let Some(body) = comment.strip_prefix("/*+").and_then(|c| c.strip_suffix("*/")) {
+            parse_hint_body(body, &mut hints);
+        }
+    }
+    hints
+}
+
+/// Return the argument of the first hint called `name`
+pub fn find_hint(sql: &str, name: &str) -> Option<String> {
+    parse_hints(sql)
+        .into_iter()
+        .find(|(hint_name, _)| hint_name.eq_ignore_ascii_case(name))
+        .map(|(_, args)| args)
+}
+
+//NAME(args) NAME2 ...,不认识的内容直接跳过
+fn parse_hint_body(body: &str, hints: &mut Vec<(String, String)>) {
+    let chars: Vec<char> = body.chars().collect();
+    let mut pos = 0;
+    while pos < chars.len() {
+        if !(chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+            pos += 1;
+            continue;
+        }
+        let name_start = pos;
+        while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+            pos += 1;
+        }
+        let name: String = chars[name_start..pos].iter().collect();
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        if pos >= chars.len() || chars[pos] != '(' {
+            hints.push((name.to_uppercase(), String::new()));
+            continue;
+        }
+        let args_start = pos + 1;
+        let mut depth = 0;
+        while pos < chars.len() {
+            match chars[pos] {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            pos += 1;
+        }
+        let args: String = chars[args_start..pos.min(chars.len())].iter().collect();
+        hints.push((name.to_uppercase(), String::from(args.trim())));
+        pos += 1;
+    }
+}
+
+#[test]
+fn test_parse_hints() {
+    let sql = "select /*+ max_execution_time(1000) BKA(t1) */ * from t1 /* normal comment(1) */ where id in (select /*+ SET_VAR(sort_buffer_size = 16M) */ id from t2)";
+    assert_eq!(vec![
+        ("MAX_EXECUTION_TIME".to_string(), "1000".to_string()),
+        ("BKA".to_string(), "t1".to_string()),
+        ("SET_VAR".to_string(), "sort_buffer_size = 16M".to_string()),
+    ], parse_hints(sql));
+    assert_eq!(Some("1000".to_string()), find_hint(sql, "MAX_EXECUTION_TIME"));
+    assert_eq!(None, find_hint("select 1", "MAX_EXECUTION_TIME"));
+    assert_eq!(vec![
+        ("NO_ICP".to_string(), "".to_string()),
+        ("BKA".to_string(), "t1".to_string()),
+    ], parse_hints("select /*+ no_icp BKA(t1) */ 1"));
+    assert!(parse_hints("select '/*+ VIRTDB_CACHE(ttl=60) */' from t1 # /*+ BKA(t1) */").is_empty());
+    assert!(parse_hints("select 1 /*+ BKA(t1)").is_empty());
+}
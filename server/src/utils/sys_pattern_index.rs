@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use super::sys_fingerprint::{self, SqlFingerprint};
+use super::sys_sql;
+
+/// SQL模板索引,按SQL指纹查找候选模板,解析器不支持的模板(如`?...`)放在前缀树中
+/// 匹配规则同`sys_sql::is_pattern_match`,多个模板都匹配时返回最先加入的
+pub struct PatternIndex<T> {
+    //模板指纹(忽略大小写和引号) -> values中的下标
+    fingerprints: HashMap<u64, Vec<usize>>,
+    root: Node,
+    //每个模板的token,用于校验前缀树找到的候选模板
+    patterns: Vec<Vec<Token>>,
+    values: Vec<T>,
+}
+
+#[derive(Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    //模板中的占位符,可以匹配任意一个token
+    wildcard: Option<Box<Node>>,
+    //模板中的`?...`,可以匹配一个或多个逗号分隔的值
+    variadic: Option<Box<Node>>,
+    //在此结束的模板,values中的下标
+    terminal: Vec<usize>,
+}
+
+impl<T> PatternIndex<T> {
+    /// Build the index, `patterns` are the tokens of each template from `sys_sql::template_tokens`
+    pub fn new(patterns: Vec<(Vec<Token>, T)>) -> PatternIndex<T> {
+        let mut fingerprints: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut root = Node::default();
+        let mut values = Vec::with_capacity(patterns.len());
+        let mut pattern_tokens = Vec::with_capacity(patterns.len());
+        for (tokens, value) in patterns {
+            let index = values.len();
+            values.push(value);
+            let template = tokens.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(" ");
+            if let Some(template_fingerprint) = sys_fingerprint::fingerprint(&template) {
+                fingerprints.entry(lookup_key(&template_fingerprint)).or_default().push(index);
+                pattern_tokens.push(tokens);
+                continue;
+            }
+            let mut node = &mut root;
+            for token in tokens.iter() {
+                node = match token_key(token) {
+                    None if sys_sql::is_variadic_placeholder(token) => node.variadic.get_or_insert_with(Box::default),
+                    None => node.wildcard.get_or_insert_with(Box::default),
+                    Some(key) => node.children.entry(key).or_default(),
+                };
+            }
+            node.terminal.push(index);
+            pattern_tokens.push(tokens);
+        }
+        PatternIndex { fingerprints, root, patterns: pattern_tokens, values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn values(&self) -> &Vec<T> {
+        &self.values
+    }
+
+    /// Find the template matching a SQL by its fingerprint
+    pub fn find_sql(&self, sql: &str, dialect: &MySqlDialect) -> Option<&T> {
+        self.find_fingerprint(sql, sys_fingerprint::fingerprint(sql).as_ref(), dialect)
+    }
+
+    /// Same as `find_sql` with the fingerprint already computed, None if the SQL can not be parsed
+    pub fn find_fingerprint(&self, sql: &str, sql_fingerprint: Option<&SqlFingerprint>, dialect: &MySqlDialect) -> Option<&T> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let tokens = Tokenizer::new(dialect, sql).tokenize().ok()?;
+        let tokens = sys_sql::normalize_tokens(sys_sql::trim_tokens(tokens));
+        //指纹忽略了大小写和引号,候选模板需要逐个校验
+        let by_fingerprint = sql_fingerprint
+            .and_then(|f| self.fingerprints.get(&lookup_key(f)))
+            .and_then(|candidates| candidates.iter().copied().find(|index| self.is_match(*index, &tokens)));
+        let by_tree = self.find_in_tree(&tokens);
+        by_fingerprint.into_iter().chain(by_tree).min().map(|index| &self.values[index])
+    }
+
+    fn find_in_tree(&self, sql_tokens: &[Token]) -> Option<usize> {
+        if self.root.children.is_empty() && self.root.wildcard.is_none() && self.root.variadic.is_none() {
+            return None;
+        }
+        let mut candidates = vec![];
+        find_in(&self.root, sql_tokens, &mut candidates);
+        candidates.sort_unstable();
+        candidates.dedup();
+        //前缀树中标识符不区分大小写,带引号的标识符是否区分大小写需要逐个校验
+        candidates.into_iter().find(|index| self.is_match(*index, sql_tokens))
+    }
+
+    fn is_match(&self, index: usize, sql_tokens: &[Token]) -> bool {
+        sys_sql::match_tokens(&self.patterns[index], sql_tokens, &mut vec![])
+    }
+}
+
+//与token_key一致,标识符不区分大小写,带引号与不带引号的标识符得到相同的key
+fn lookup_key(sql_fingerprint: &SqlFingerprint) -> u64 {
+    let pattern = sql_fingerprint.pattern.replace('`', "").to_ascii_uppercase();
+    sys_fingerprint::fnv1a(pattern.as_bytes())
+}
+
+//收集所有可能匹配的模板
+fn find_in(node: &Node, tokens: &[Token], candidates: &mut Vec<usize>) {
+    if let Some(n) = node.variadic.as_ref() {
+        for end in sys_sql::value_list_ends(tokens) {
+            find_in(n, &tokens[end..], candidates);
+        }
+    }
+    let token = match tokens.first() {
+        None => {
+            candidates.extend_from_slice(&node.terminal);
+            return;
+        }
+        Some(token) => token,
+    };
+    let rest = &tokens[1..];
+    if let Some(n) = node.wildcard.as_ref() {
+        find_in(n, rest, candidates);
+    }
+    match token_key(token) {
+        Some(key) => {
+            if let Some(n) = node.children.get(&key) {
+                find_in(n, rest, candidates);
+            }
+        }
+        //SQL中的占位符可以匹配模板中的任意token
+        None => node.children.values().for_each(|n| find_in(n, rest, candidates)),
+    }
+}
+
+//与is_pattern_match的比较方式保持一致,占位符返回None
+fn token_key(token: &Token) -> Option<String> {
+    match token {
+        Token::Placeholder(_) => None,
+        Token::Word(w) => Some(format!("W{}", w.value.to_ascii_uppercase())),
+        Token::HexStringLiteral(v) => Some(format!("X{}", v.to_ascii_uppercase())),
+        Token::Number(v, long) => Some(format!("N{}{}", v, long)),
+        Token::EscapedStringLiteral(_) => Some(String::from("E")),
+        Token::Whitespace(_) => Some(String::from(" ")),
+        _ => Some(format!("{:?}", token)),
+    }
+}
+
+#[cfg(test)]
+fn tokens(sql: &str) -> Vec<Token> {
+    sys_sql::template_tokens(&MySqlDialect {}, sql)
+}
+
+#[test]
+fn test_pattern_index() {
+    let dialect = MySqlDialect {};
+    let index = PatternIndex::new(vec![
+        (tokens("SELECT * FROM ARTICLE WHERE ID = ?"), 1),
+        (tokens("SELECT * FROM ARTICLE WHERE ID = 1"), 2),
+        (tokens("SELECT * FROM ARTICLE WHERE ID = ? LIMIT ?"), 3),
+        (tokens("SELECT * FROM `ARTICLE` WHERE TITLE = ?"), 4),
+        (tokens("SELECT * FROM ARTICLE WHERE ID IN (?...) LIMIT ?..."), 5),
+    ]);
+    //`?...`无法解析,只有最后一个模板放在前缀树中
+    assert_eq!(4, index.fingerprints.values().map(Vec::len).sum::<usize>());
+
+    assert_eq!(Some(&1), index.find_sql("SELECT * FROM ARTICLE WHERE ID = 1", &dialect));
+    assert_eq!(Some(&3), index.find_sql("SELECT * FROM ARTICLE WHERE ID = 1 LIMIT 10", &dialect));
+    assert_eq!(Some(&4), index.find_sql("SELECT * FROM ARTICLE WHERE TITLE = 'A'", &dialect));
+    assert_eq!(Some(&1), index.find_sql("SELECT * FROM ARTICLE WHERE ID = ?", &dialect));
+    assert_eq!(None, index.find_sql("SELECT * FROM ARTICLE", &dialect));
+    assert_eq!(None, index.find_sql("SELECT * FROM ARTICLE WHERE ID = 1 LIMIT 10, 20", &dialect));
+    assert_eq!(Some(&5), index.find_sql("SELECT * FROM ARTICLE WHERE ID IN (1) LIMIT 10", &dialect));
+    assert_eq!(Some(&5), index.find_sql("SELECT * FROM ARTICLE WHERE ID IN (1, 2, -3) LIMIT 10 OFFSET 20", &dialect));
+    assert_eq!(None, index.find_sql("SELECT * FROM ARTICLE WHERE ID IN () LIMIT 10", &dialect));
+    assert_eq!(Some(&1), index.find_sql("select * from article where id = 1", &dialect));
+    assert_eq!(Some(&4), index.find_sql("select * from `ARTICLE` where title = 'a'", &dialect));
+    assert_eq!(None, index.find_sql("select * from `article` where title = 'a'", &dialect));
+}
+
+#[test]
+fn test_pattern_index_literal_case() {
+    let dialect = MySqlDialect {};
+    let index = PatternIndex::new(vec![
+        (tokens("SELECT * FROM user WHERE name = 'abc'"), 1),
+        (tokens("SELECT * FROM `User` WHERE name = 'ABC'"), 2),
+        (tokens("SELECT * FROM user WHERE name = ?"), 3),
+    ]);
+
+    assert_eq!(Some(&1), index.find_sql("select * from USER where NAME = 'abc'", &dialect));
+    assert_eq!(Some(&2), index.find_sql("select * from User where name = 'ABC'", &dialect));
+    assert_eq!(Some(&3), index.find_sql("select * from user where name = 'ABC'", &dialect));
+    assert_eq!(Some(&3), index.find_sql("select * from user where name = 'Abc'", &dialect));
+}
+
+#[test]
+fn test_pattern_index_fingerprint() {
+    let dialect = MySqlDialect {};
+    let index = PatternIndex::new(vec![
+        (tokens("SELECT id FROM article WHERE id IN (SELECT article_id FROM tag WHERE name = ?) ORDER BY id DESC"), 1),
+        (tokens("SELECT id FROM article WHERE id = ?"), 2),
+    ]);
+
+    assert_eq!(Some(&1), index.find_sql("select id from article where id in (select article_id from tag where name = 'a') order by id desc", &dialect));
+    assert_eq!(Some(&2), index.find_sql("SELECT id\n FROM article WHERE id = 10", &dialect));
+    assert_eq!(None, index.find_sql("select id from article where id = 1 or id = 2", &dialect));
+    //无法解析的SQL没有指纹
+    assert_eq!(None, index.find_fingerprint("select id from article where id = 1", None, &dialect));
+}
@@ -2,7 +2,9 @@
 
 use std::collections::HashMap;
 use std::env;
+use std::ops::Range;
 use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 use sqlparser::ast::{BinaryOperator, Expr, Query, Select, SetExpr, Statement, Value};
 use sqlparser::dialect::{Dialect, MySqlDialect};
@@ -16,216 +18,371 @@ use sqlparser::tokenizer::Token::{Placeholder, Word};
 #[cfg(test)]
 use test_log::test;
 
-const SYS_DIALECT: MySqlDialect = MySqlDialect {};
-
-pub fn normally(dialect: &dyn Dialect, sql: &str) -> String {
-    let mut tokenizer = Tokenizer::new(dialect, &*sql);
-    let tokens: Vec<Token> = tokenizer.tokenize().unwrap();
-    return tokens.iter()
-        .map(|x| {
-            match x {
-                Token::EOF => { "".to_string() }
-                Token::Whitespace(_) => { "".to_string() }
-                _ => {
-                    format!("{}", x)
-                }
-            }
-        })
-        .reduce(|a, b| {
-            if a == "" || b == ""
-                || a == "." || b == "."
-                || a == "," || b == ","
-                || a == "(" || b == "("
-                || a == ")" || b == ")"
-            {
-                format!("{}{}", a, b)
-            } else {
-                format!("{} {}", a, b)
-            }
-        })
-        .unwrap_or(sql.to_string());
+//后端MySQL的lower_case_table_names,为0时带引号的标识符区分大小写
+static LOWER_CASE_TABLE_NAMES: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_lower_case_table_names(value: u8) {
+    LOWER_CASE_TABLE_NAMES.store(value, Ordering::Relaxed);
 }
 
+pub fn lower_case_table_names() -> u8 {
+    LOWER_CASE_TABLE_NAMES.load(Ordering::Relaxed)
+}
+
+//模板中的`?...`,匹配一个或多个逗号分隔的值,如`IN (?...)`、`LIMIT ?...`
+pub const VARIADIC_PLACEHOLDER: &str = "?...";
 
-pub fn is_pattern_match(tokens1: &Vec<Token>, sql2: &str, dialect: &MySqlDialect) -> bool {
-    let tokens2: Vec<Token> = Tokenizer::new(dialect, sql2)
+pub fn is_pattern_match(tokens1: &[Token], sql2: &str, dialect: &MySqlDialect) -> bool {
+    let tokens2: Vec<Token> = normalize_tokens(trim_tokens(Tokenizer::new(dialect, sql2)
         .tokenize()
-        .unwrap_or_default()
-        .into_iter()
-        .filter(|t| {
-            return match t {
-                Token::EOF => false,
-                Token::Whitespace(_) => false,
-                _ => true,
-            };
-        })
-        .collect();
+        .unwrap_or_default()));
     // debug!("tokens1:{:?}\ntokens2:{:?}\ntokens1.len:{:?},tokens2.len:{:?}", tokens1, tokens2, tokens1.len(), tokens2.len());
     debug!("sql:{:?},pattern:{:?}",sql2,tokens1);
-    if tokens1.len() != tokens2.len() {
-        return false;
-    }
+    match_tokens(tokens1, &tokens2, &mut vec![])
+}
 
+/// Match the normalized template tokens with the normalized SQL tokens.
+/// The range of SQL tokens matched by each placeholder is pushed to `captures`.
+pub fn match_tokens(tokens1: &[Token], tokens2: &[Token], captures: &mut Vec<Range<usize>>) -> bool {
+    match_tokens_from(tokens1, tokens2, 0, captures)
+}
+
+fn match_tokens_from(tokens1: &[Token], tokens2: &[Token], offset: usize, captures: &mut Vec<Range<usize>>) -> bool {
     for index in 0..tokens1.len() {
         let a = &tokens1[index];
-        let b = &tokens2[index];
-        trace!("sql match token pair. a:{:?},b:{:?}", a, b);
-        let is_same = match (a, b) {
-            (Token::Word(v_a), Token::Word(v_b)) => {
-                v_a.value == v_b.value
-            }
-            (Token::Number(v_a_str, v_a_bool), Token::Number(v_b_str, v_b_bool)) => {
-                v_a_str == v_b_str && v_a_bool == v_b_bool
-            }
-            (Token::Char(v_a), Token::Char(v_b)) => {
-                v_a == v_b
-            }
-            (Token::SingleQuotedString(v_a), Token::SingleQuotedString(v_b)) => {
-                v_a == v_b
-            }
-            (Token::NationalStringLiteral(v_a), Token::NationalStringLiteral(v_b)) => {
-                v_a == v_b
-            }
-            (Token::EscapedStringLiteral(v_a), Token::EscapedStringLiteral(v_b)) => {
-                true
-            }
-            (Token::HexStringLiteral(v_a), Token::HexStringLiteral(v_b)) => {
-                v_a == v_b
-            }
-            (Token::Whitespace(v_a), Token::Whitespace(v_b)) => {
-                true
-            }
-            (Token::Placeholder(v_a), v) => {
-                true
-            }
-            (v, Token::Placeholder(v_a)) => {
-                true
-            }
-            _ => {
-                a == b
+        if is_variadic_placeholder(a) {
+            let rest = &tokens2[index.min(tokens2.len())..];
+            let captures_len = captures.len();
+            for end in value_list_ends(rest) {
+                captures.push(offset + index..offset + index + end);
+                if match_tokens_from(&tokens1[index + 1..], &rest[end..], offset + index + end, captures) {
+                    return true;
+                }
+                captures.truncate(captures_len);
             }
+            return false;
+        }
+        let b = match tokens2.get(index) {
+            None => return false,
+            Some(b) => b,
         };
-        if !is_same {
+        trace!("sql match token pair. a:{:?},b:{:?}", a, b);
+        if !is_same_token(a, b) {
             debug!("a != b,return");
             return false;
         }
+        if let Token::Placeholder(_) = a {
+            captures.push(offset + index..offset + index + 1);
+        }
+    }
+    tokens1.len() == tokens2.len()
+}
+
+pub fn is_same_token(a: &Token, b: &Token) -> bool {
+    match (a, b) {
+        (Token::Word(v_a), Token::Word(v_b)) => {
+            is_same_word(v_a, v_b)
+        }
+        (Token::Number(v_a_str, v_a_bool), Token::Number(v_b_str, v_b_bool)) => {
+            v_a_str == v_b_str && v_a_bool == v_b_bool
+        }
+        (Token::Char(v_a), Token::Char(v_b)) => {
+            v_a == v_b
+        }
+        (Token::SingleQuotedString(v_a), Token::SingleQuotedString(v_b)) => {
+            v_a == v_b
+        }
+        (Token::NationalStringLiteral(v_a), Token::NationalStringLiteral(v_b)) => {
+            v_a == v_b
+        }
+        (Token::EscapedStringLiteral(v_a), Token::EscapedStringLiteral(v_b)) => {
+            true
+        }
+        (Token::HexStringLiteral(v_a), Token::HexStringLiteral(v_b)) => {
+            v_a.eq_ignore_ascii_case(v_b)
+        }
+        (Token::Whitespace(v_a), Token::Whitespace(v_b)) => {
+            true
+        }
+        (Token::Placeholder(v_a), v) => {
+            true
+        }
+        (v, Token::Placeholder(v_a)) => {
+            true
+        }
+        _ => {
+            a == b
+        }
+    }
+}
+
+//关键字和标识符不区分大小写,带引号的标识符按lower_case_table_names处理
+pub fn is_same_word(a: &tokenizer::Word, b: &tokenizer::Word) -> bool {
+    compare_word(a, b, lower_case_table_names())
+}
+
+fn compare_word(a: &tokenizer::Word, b: &tokenizer::Word, lower_case_table_names: u8) -> bool {
+    if (a.quote_style.is_some() || b.quote_style.is_some()) && lower_case_table_names == 0 {
+        a.value == b.value
+    } else {
+        a.value.eq_ignore_ascii_case(&b.value)
+    }
+}
+
+pub fn is_variadic_placeholder(token: &Token) -> bool {
+    matches!(token, Token::Placeholder(v) if v == VARIADIC_PLACEHOLDER)
+}
+
+/// The possible lengths of a list of one or more comma separated values at the start of `tokens`, shortest first
+pub fn value_list_ends(tokens: &[Token]) -> Vec<usize> {
+    let mut ends = vec![];
+    let mut pos = 0;
+    loop {
+        let value_len = value_len(&tokens[pos..]);
+        if value_len == 0 {
+            break;
+        }
+        pos += value_len;
+        ends.push(pos);
+        if tokens.get(pos) != Some(&Token::Comma) {
+            break;
+        }
+        pos += 1;
+    }
+    ends
+}
+
+//单个值占用的token数,不是值时返回0
+fn value_len(tokens: &[Token]) -> usize {
+    let sign_len = match tokens.first() {
+        Some(Token::Minus) | Some(Token::Plus) => 1,
+        _ => 0,
+    };
+    let is_value = match tokens.get(sign_len) {
+        Some(Token::Number(_, _)) | Some(Token::Placeholder(_)) => true,
+        Some(Token::SingleQuotedString(_)) | Some(Token::NationalStringLiteral(_))
+        | Some(Token::EscapedStringLiteral(_)) | Some(Token::HexStringLiteral(_)) => sign_len == 0,
+        Some(Token::Word(w)) => sign_len == 0 && w.quote_style.is_none()
+            && (w.keyword == Keyword::NULL || w.keyword == Keyword::TRUE || w.keyword == Keyword::FALSE),
+        _ => false,
+    };
+    if is_value {
+        sign_len + 1
+    } else {
+        0
     }
-    return true;
 }
 
 pub fn trim_tokens(tokens: Vec<Token>) -> Vec<Token> {
     tokens
         .into_iter()
-        .filter(|t| {
-            return match t {
-                Token::EOF => false,
-                Token::Whitespace(_) => false,
-                _ => true,
-            };
-        })
+        .filter(|t| !matches!(t, Token::EOF | Token::Whitespace(_)))
         .collect()
 }
 
-pub fn is_sql_pattern_match(pattern: &str, sql2: &str, dialect: &MySqlDialect) -> bool {
-    let tokens1: Vec<Token> = Tokenizer::new(dialect, pattern)
-        .tokenize()
-        .unwrap_or_default();
-    return is_pattern_match(&tokens1, sql2, dialect);
+/// Normalize trimmed tokens so that equivalent forms compare equal:
+/// `? ...` becomes one variadic placeholder, `LIMIT n OFFSET m` becomes `LIMIT m , n`,
+/// and only the first row of a multi-row `VALUES` list is kept.
+pub fn normalize_tokens(tokens: Vec<Token>) -> Vec<Token> {
+    normalize_indexed(&tokens).into_iter().map(|(token, _)| token).collect()
 }
 
+//同normalize_tokens,并记录每个Token在输入中的位置,补出来的Token没有位置
+fn normalize_indexed(tokens: &[Token]) -> Vec<(Token, Option<usize>)> {
+    let mut result: Vec<(Token, Option<usize>)> = Vec::with_capacity(tokens.len());
+    let mut index = 0;
+    //VALUES后第一行的右括号位置
+    let mut values_row_end: Option<usize> = None;
+    while index < tokens.len() {
+        let token = &tokens[index];
+        if values_row_end == Some(index) {
+            values_row_end = None;
+            result.push((token.clone(), Some(index)));
+            index += 1;
+            //跳过其余的行
+            while tokens.get(index) == Some(&Token::Comma) && tokens.get(index + 1) == Some(&Token::LParen) {
+                match closing_paren(tokens, index + 1) {
+                    None => break,
+                    Some(end) => index = end + 1,
+                }
+            }
+            continue;
+        }
+        if let Token::Placeholder(p) = token {
+            if p == "?" && tokens[index + 1..].iter().take(3).filter(|t| **t == Token::Period).count() == 3 {
+                result.push((Token::Placeholder(String::from(VARIADIC_PLACEHOLDER)), Some(index)));
+                index += 4;
+                continue;
+            }
+        }
+        if let Token::Word(w) = token {
+            if w.keyword == Keyword::LIMIT && index + 3 < tokens.len() {
+                let is_offset = matches!(&tokens[index + 2], Token::Word(o) if o.keyword == Keyword::OFFSET);
+                if is_offset && value_len(&tokens[index + 1..index + 2]) == 1 && value_len(&tokens[index + 3..index + 4]) == 1 {
+                    result.push((token.clone(), Some(index)));
+                    result.push((tokens[index + 3].clone(), Some(index + 3)));
+                    result.push((Token::Comma, None));
+                    result.push((tokens[index + 1].clone(), Some(index + 1)));
+                    index += 4;
+                    continue;
+                }
+            }
+            if w.keyword == Keyword::VALUES && tokens.get(index + 1) == Some(&Token::LParen) {
+                values_row_end = closing_paren(tokens, index + 1);
+            }
+        }
+        result.push((token.clone(), Some(index)));
+        index += 1;
+    }
+    result
+}
 
-pub fn sql_to_pattern(sql: &str) -> Option<String> {
-    let stmts_result = Parser::parse_sql(&SYS_DIALECT, sql);
-    if let Err(stmts) = stmts_result {
-        return None;
+//与start处的左括号对应的右括号位置
+fn closing_paren(tokens: &[Token], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
     }
-    let mut stmts: Vec<Statement> = stmts_result.unwrap();
-    if stmts.len() < 1 {
+    None
+}
+
+/// Tokenize a SQL template the same way as the SQL it is matched with
+pub fn template_tokens(dialect: &MySqlDialect, template: &str) -> Vec<Token> {
+    normalize_tokens(trim_tokens(Tokenizer::new(dialect, template)
+        .tokenize()
+        .unwrap_or_default()))
+}
+
+pub fn is_sql_pattern_match(pattern: &str, sql2: &str, dialect: &MySqlDialect) -> bool {
+    let tokens1: Vec<Token> = template_tokens(dialect, pattern);
+    is_pattern_match(&tokens1, sql2, dialect)
+}
+
+/// Match `sql` against the pattern tokens and return the source text of `sql` matched by each placeholder.
+pub fn capture_placeholders(tokens1: &[Token], sql: &str, dialect: &MySqlDialect) -> Option<Vec<String>> {
+    let sql = sql.trim();
+    let tokens = Tokenizer::new(dialect, sql).tokenize().ok()?;
+    //Token的值丢掉了转义等原文信息,捕获的值要取原文
+    let (tokens, spans): (Vec<Token>, Vec<Range<usize>>) = tokens.iter().cloned()
+        .zip(token_spans(sql, &tokens)?)
+        .filter(|(t, _)| !matches!(t, Token::EOF | Token::Whitespace(_)))
+        .unzip();
+    let (tokens2, origins): (Vec<Token>, Vec<Option<usize>>) = normalize_indexed(&tokens).into_iter().unzip();
+    let mut captures = vec![];
+    if !match_tokens(tokens1, &tokens2, &mut captures) {
         return None;
     }
-    let ast: Statement = stmts.pop().unwrap();
-    let ast = match ast {
-        Statement::Query(q) => {
-            let q = q.clone() as Box<ast::Query>;
-            let tmp_q = q.clone();
-            let result = match q.body {
-                SetExpr::Select(select_expr) => {
-                    let mut select_expr: Box<Select> = select_expr.clone() as Box<Select>;
-                    let expr = select_expr.selection
-                        .map(|expr| {
-                            let new_expr = replace_expr(expr.clone());
-                            new_expr
-                        });
-                    select_expr.selection = expr;
-                    let select = SetExpr::Select(select_expr);
-
-                    let mut sub_query = tmp_q.clone();
-                    sub_query.body = select;
-                    sub_query
-                }
-                SetExpr::Query(x) => {
-                    tmp_q
+    Some(captures.into_iter().map(|range| {
+        let mut text = String::new();
+        //原文中相邻的Token连同中间的空白一起取出,不相邻的用空格隔开
+        let mut last: Option<usize> = None;
+        for index in range {
+            match origins[index] {
+                Some(origin) if last.is_some_and(|l| l + 1 == origin) => {
+                    text.push_str(&sql[spans[origin - 1].end..spans[origin].end]);
                 }
-                _ => {
-                    tmp_q
+                origin => {
+                    if !text.is_empty() {
+                        text.push(' ');
+                    }
+                    match origin {
+                        Some(origin) => text.push_str(&sql[spans[origin].clone()]),
+                        None => text.push_str(&tokens2[index].to_string()),
+                    }
                 }
-            };
-            Some(Statement::Query(result))
-        }
-        _ => {
-            None
-        }
-    };
-    return match ast{
-        None => None,
-        Some(v) => {
-            Some(v.to_string())
+            }
+            last = origins[index];
         }
-    };
+        text
+    }).collect())
 }
 
-fn replace_expr(expr: Expr) -> Expr {
-    match expr {
-        Expr::BinaryOp { op, left, right } => {
-            if let BinaryOperator::And = op {
-                Expr::BinaryOp {
-                    op,
-                    left: Box::new(replace_expr(*left)),
-                    right: Box::new(replace_expr(*right)),
-                }
-            } else {
-                Expr::BinaryOp {
-                    op,
-                    left: Box::new(replace_expr(*left)),
-                    right: Box::new(Expr::Value(Value::Placeholder("?".to_string()))),
+/// Locate each token of `sql` in the source text.
+/// Returns None if the tokens can't be lined up with the source.
+pub fn token_spans(sql: &str, tokens: &[Token]) -> Option<Vec<Range<usize>>> {
+    let mut spans = Vec::with_capacity(tokens.len());
+    let mut pos = 0;
+    for token in tokens {
+        let rest = &sql[pos..];
+        let len = match token {
+            Token::EOF => 0,
+            Token::SingleQuotedString(_) => quoted_string_len(rest, false)?,
+            Token::NationalStringLiteral(_) | Token::HexStringLiteral(_) => 1 + quoted_string_len(rest.get(1..)?, false)?,
+            Token::EscapedStringLiteral(_) => 1 + quoted_string_len(rest.get(1..)?, true)?,
+            Token::Word(w) if w.quote_style.is_some() => quoted_ident_len(rest)?,
+            //`\r\n`和`\r`都是Newline
+            Token::Whitespace(tokenizer::Whitespace::Newline) if rest.starts_with('\r') => {
+                if rest.starts_with("\r\n") { 2 } else { 1 }
+            }
+            Token::Neq if rest.starts_with("<>") => 2,
+            _ => {
+                let text = token.to_string();
+                if !rest.starts_with(&text) {
+                    return None;
                 }
+                text.len()
             }
-        }
-        Expr::Between {
-            expr,
-            negated,
-            low,
-            high,
-        } => {
-            let new_low = Box::new(Expr::Value(Value::Placeholder("?".to_string())));
-            let new_high = Box::new(Expr::Value(Value::Placeholder("?".to_string())));
-            Expr::Between {
-                expr: Box::new(replace_expr(*expr)),
-                negated,
-                low: new_low,
-                high: new_high,
+        };
+        spans.push(pos..pos + len);
+        pos += len;
+    }
+    if pos == sql.len() { Some(spans) } else { None }
+}
+
+//与Tokenizer一致:`''`是转义的引号,MySQL下`\\`切换转义状态,E''字符串中其它字符会结束转义
+fn quoted_string_len(s: &str, reset_escape: bool) -> Option<usize> {
+    let mut chars = s.char_indices().peekable();
+    if chars.next()?.1 != '\'' {
+        return None;
+    }
+    let mut is_escaped = false;
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '\'' if is_escaped => is_escaped = false,
+            '\'' => {
+                if chars.peek().map(|(_, c)| *c) == Some('\'') {
+                    chars.next();
+                } else {
+                    return Some(index + 1);
+                }
             }
+            '\\' => is_escaped = !is_escaped,
+            _ if reset_escape => is_escaped = false,
+            _ => {}
         }
-        Expr::UnaryOp { op, expr } => {
-            Expr::UnaryOp {
-                op,
-                expr: Box::new(replace_expr(*expr)),
+    }
+    None
+}
+
+//带引号的标识符,结束引号重复一次表示引号本身
+fn quoted_ident_len(s: &str) -> Option<usize> {
+    let mut chars = s.char_indices().peekable();
+    let quote_end = match chars.next()?.1 {
+        '[' => ']',
+        quote => quote,
+    };
+    while let Some((index, ch)) = chars.next() {
+        if ch == quote_end {
+            if chars.peek().map(|(_, c)| *c) == Some(quote_end) {
+                chars.next();
+            } else {
+                return Some(index + ch.len_utf8());
             }
         }
-        _ => expr,
     }
+    None
 }
 
+
 #[test]
 fn test_match() {
     let sql = "SELECT * FROM article where article_id = 116728608290413363";
@@ -265,23 +422,66 @@ fn test_matchs() {
     for (pattern, sql) in sqls {
         let matched = is_sql_pattern_match(pattern, sql, &dialect);
         println!("pattern:{:?}\nsql:{:?}\neq:{:?}\n", pattern, sql, matched);
-        assert_eq!(true, matched);
+        assert!(matched);
+    }
+
+    // variable-length lists
+    let mut variadic_sqls: Vec<(&str, &str, bool)> = vec![];
+    let in_pattern = "SELECT a.article_id,a.article_title from article a  where a.tenant_id = ? and a.article_id IN (?...) order by field( a.article_id,?...)";
+    variadic_sqls.push((in_pattern, "SELECT a.article_id,a.article_title from article a  where a.tenant_id = 1 and a.article_id IN (1) order by field( a.article_id,1)", true));
+    variadic_sqls.push((in_pattern, "SELECT a.article_id,a.article_title from article a  where a.tenant_id = 1 and a.article_id IN (1,2,3,4,5) order by field( a.article_id,5,4,3,2,1)", true));
+    variadic_sqls.push((in_pattern, "SELECT a.article_id,a.article_title from article a  where a.tenant_id = 1 and a.article_id IN ('a', 'b', NULL, -1) order by field( a.article_id,'a')", true));
+    variadic_sqls.push((in_pattern, "SELECT a.article_id,a.article_title from article a  where a.tenant_id = 1 and a.article_id IN () order by field( a.article_id,1)", false));
+    variadic_sqls.push((in_pattern, "SELECT a.article_id,a.article_title from article a  where a.tenant_id = 1 and a.article_id IN (select id from t) order by field( a.article_id,1)", false));
+    let limit_pattern = "SELECT a.article_id from article a where a.tenant_id = ? order by a.publish_time desc limit ?...";
+    variadic_sqls.push((limit_pattern, "SELECT a.article_id from article a where a.tenant_id = 1 order by a.publish_time desc limit 10", true));
+    variadic_sqls.push((limit_pattern, "SELECT a.article_id from article a where a.tenant_id = 1 order by a.publish_time desc limit 0,10", true));
+    variadic_sqls.push((limit_pattern, "SELECT a.article_id from article a where a.tenant_id = 1 order by a.publish_time desc limit 10 offset 20", true));
+    variadic_sqls.push(("SELECT a.article_id from article a limit ?,?", "SELECT a.article_id from article a limit 10 offset 20", true));
+    variadic_sqls.push(("SELECT a.article_id from article a limit ? offset ?", "SELECT a.article_id from article a limit 20,10", true));
+    variadic_sqls.push(("SELECT a.article_id from article a limit ?", "SELECT a.article_id from article a limit 20,10", false));
+    let values_pattern = "insert into article(article_id,article_title) values (?,?)";
+    variadic_sqls.push((values_pattern, "insert into article(article_id,article_title) values (1,'a')", true));
+    variadic_sqls.push((values_pattern, "insert into article(article_id,article_title) values (1,'a'),(2,'b'),(3,'c')", true));
+    variadic_sqls.push(("insert into article values (?...)", "insert into article values (1,'a',now())", false));
+    variadic_sqls.push(("insert into article values (?...)", "insert into article values (1,'a',3),(2,'b',4)", true));
+
+    for (pattern, sql, expected) in variadic_sqls {
+        let matched = is_sql_pattern_match(pattern, sql, &dialect);
+        println!("pattern:{:?}\nsql:{:?}\neq:{:?}\n", pattern, sql, matched);
+        assert_eq!(expected, matched);
     }
 }
 
 
 #[test]
-fn test_sql_verify() {
-    let sql = r#" SELECT zu.id,zu.account,zu.realname,
+fn test_capture_placeholders() {
+    let dialect = MySqlDialect {};
+    let pattern = template_tokens(&dialect, "SELECT * FROM ARTICLE WHERE TITLE = ? AND ID IN (?...) LIMIT ?...");
+    let captures = capture_placeholders(&pattern, " select * from article where title = 'Rust' and id in (10, 11) limit 5 offset 20 ", &dialect).unwrap();
+    assert_eq!(vec!["'Rust'", "10, 11", "20 , 5"], captures);
+    assert_eq!(None, capture_placeholders(&pattern, "select * from article where title = 'Rust'", &dialect));
+}
+#[test]
+fn test_match_case() {
+    let dialect = MySqlDialect {};
+    assert!(is_sql_pattern_match("SELECT * FROM article WHERE name = ?", "select * from ARTICLE where NAME = 'abc'", &dialect));
+    assert!(is_sql_pattern_match("SELECT * FROM article WHERE name = 'abc'", "select * from article where name = 'abc'", &dialect));
+    assert!(!is_sql_pattern_match("SELECT * FROM article WHERE name = 'abc'", "select * from article where name = 'ABC'", &dialect));
+    assert!(is_sql_pattern_match("SELECT * FROM t WHERE b = x'0A'", "select * from t where b = X'0a'", &dialect));
+
+    let captures = capture_placeholders(&template_tokens(&dialect, "SELECT * FROM article WHERE name = ?"), "select * from article where name = 'AbC'", &dialect);
+    assert_eq!(Some(vec!["'AbC'".to_string()]), captures);
 
- (
-	select GROUP_CONCAT(zg.`name` separator ',') from zt_group zg join zt_usergroup zug on zg.id = zug.`group` where zug.account = zu.account
- )
-	from zt_user zu
- "#;
-    let dialect = &MySqlDialect {};
-    let normally_sql = normally(dialect, sql);
-    assert_ne!(sql, normally_sql)
+    let word = |sql: &str| match Tokenizer::new(&dialect, sql).tokenize().unwrap().remove(0) {
+        Word(w) => w,
+        t => panic!("not a word:{:?}", t),
+    };
+    assert!(compare_word(&word("`Article`"), &word("Article"), 0));
+    assert!(!compare_word(&word("`Article`"), &word("article"), 0));
+    assert!(!compare_word(&word("`Article`"), &word("`ARTICLE`"), 0));
+    assert!(compare_word(&word("`Article`"), &word("`ARTICLE`"), 1));
+    assert!(compare_word(&word("Article"), &word("ARTICLE"), 0));
 }
 
 #[cfg(test)]
@@ -300,29 +500,160 @@ pub fn test_sql_to_pattern() {
        order by a.publish_time desc limit ?,?
     "#;
 
-    let pattern = sql_to_pattern(sql);
+    let pattern = super::sys_fingerprint::fingerprint(sql).map(|f| f.pattern);
     println!("ast:{:?}", pattern);
+    assert_eq!(Some(String::from("SELECT a.article_id FROM article AS a WHERE a.tenant_id = ? AND a.aa = ? AND a.bb > ? AND a.bb < ? AND a.bb BETWEEN ? AND ? AND a.cc = ? AND a.app_id = ? AND a.article_status = ? ORDER BY a.publish_time DESC LIMIT ? OFFSET ?")), pattern);
+}
+
+/// Lowercased names of the tables read by a statement, in order of appearance without duplicates.
+/// Tables are taken from `FROM` lists and `JOIN`s at any nesting depth, schema qualifiers are dropped.
+pub fn table_names(dialect: &MySqlDialect, sql: &str) -> Vec<String> {
+    let tokens = trim_tokens(Tokenizer::new(dialect, sql).tokenize().unwrap_or_default());
+    let mut tables: Vec<String> = vec![];
+    //每层括号是否处于FROM列表中
+    let mut in_from = vec![false];
+    let mut expect_table = false;
+    let mut index = 0;
+    while index < tokens.len() {
+        match &tokens[index] {
+            Token::LParen => {
+                in_from.push(false);
+                expect_table = false;
+            }
+            Token::RParen => {
+                if in_from.len() > 1 {
+                    in_from.pop();
+                }
+            }
+            Token::Comma => expect_table = *in_from.last().unwrap(),
+            Token::Word(w) if expect_table => {
+                //db.table取最后一段
+                let mut name = w.value.clone();
+                while tokens.get(index + 1) == Some(&Token::Period) {
+                    match tokens.get(index + 2) {
+                        Some(Token::Word(part)) => {
+                            name = part.value.clone();
+                            index += 2;
+                        }
+                        _ => break,
+                    }
+                }
+                let name = name.to_lowercase();
+                if !tables.contains(&name) {
+                    tables.push(name);
+                }
+                expect_table = false;
+            }
+            Token::Word(w) => match w.keyword {
+                Keyword::FROM => {
+                    *in_from.last_mut().unwrap() = true;
+                    expect_table = true;
+                }
+                Keyword::JOIN | Keyword::UPDATE | Keyword::INTO => expect_table = true,
+                Keyword::WHERE | Keyword::GROUP | Keyword::ORDER | Keyword::LIMIT | Keyword::HAVING
+                | Keyword::UNION | Keyword::SET | Keyword::WINDOW => {
+                    *in_from.last_mut().unwrap() = false;
+                }
+                _ => {}
+            },
+            _ => expect_table = false,
+        }
+        index += 1;
+    }
+    tables
+}
+
+#[test]
+fn test_table_names() {
+    let dialect = MySqlDialect {};
+    assert_eq!(vec!["users"], table_names(&dialect, "select * from users where id = 1"));
+    assert_eq!(vec!["article", "users", "tags", "labels"], table_names(&dialect,
+        "select * from db.Article a join `users` u on a.uid = u.id, tags t where a.id in (select id from labels) order by a.id"));
+    assert_eq!(vec!["orders", "items"], table_names(&dialect,
+        "select * from (select * from orders) o left join items i using (order_id) limit 10"));
+    assert!(table_names(&dialect, "select 1").is_empty());
 }
 
 pub fn remove_comments(query: String) -> String {
-    let mut result = String::new();
-    let mut skip = false;
-
-    let mut chars = query.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '/' && chars.peek() == Some(&'*') {
-            // 如果遇到 '/*'，则跳过直到找到 '*/'
-            skip = true;
-            chars.next(); // 跳过下一个字符 '*'
-        } else if skip && c == '*' && chars.peek() == Some(&'/') {
-            // 如果找到 '*/'，则结束跳过状态
-            skip = false;
-            chars.next(); // 跳过下一个字符 '/'
-        } else if !skip {
-            result.push(c);
+    String::from(split_comments(&query).0.trim())
+}
+
+/// Split a statement into the SQL MySQL executes and its comments, each comment with its delimiters.
+/// Quoted strings and identifiers are kept as they are, and the content of `/*! ... */` stays in the SQL
+/// because MySQL executes it.
+pub fn split_comments(query: &str) -> (String, Vec<String>) {
+    let chars: Vec<char> = query.chars().collect();
+    let mut sql = String::with_capacity(query.len());
+    let mut comments = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' | '"' | '`' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() {
+                    //反引号中的反斜杠不是转义符
+                    if chars[i] == '\\' && c != '`' {
+                        i += 2;
+                        continue;
+                    }
+                    if chars[i] == c {
+                        //连续两个引号表示引号本身
+                        if chars.get(i + 1) == Some(&c) {
+                            i += 2;
+                            continue;
+                        }
+                        break;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+                sql.extend(&chars[start..i]);
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let end = (i + 2..chars.len().saturating_sub(1)).find(|&j| chars[j] == '*' && chars[j + 1] == '/');
+                let body: String = chars[i + 2..end.unwrap_or(chars.len())].iter().collect();
+                let start = i;
+                i = end.map_or(chars.len(), |end| end + 2);
+                sql.push(' ');
+                match body.strip_prefix('!') {
+                    //可执行注释,去掉版本号后作为SQL
+                    Some(body) => {
+                        sql.push_str(body.trim_start_matches(|c: char| c.is_ascii_digit()));
+                        sql.push(' ');
+                    }
+                    None => comments.push(chars[start..i].iter().collect()),
+                }
+            }
+            '#' | '-' if c == '#' || is_dash_comment(&chars, i) => {
+                let end = (i..chars.len()).find(|&j| chars[j] == '\n').unwrap_or(chars.len());
+                comments.push(chars[i..end].iter().collect());
+                sql.push(' ');
+                i = end;
+            }
+            _ => {
+                sql.push(c);
+                i += 1;
+            }
         }
     }
+    (sql, comments)
+}
+
+//MySQL的--注释后面必须是空白或控制字符,1--1是减法
+fn is_dash_comment(chars: &[char], i: usize) -> bool {
+    chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_none_or(|c| c.is_whitespace() || c.is_control())
+}
 
-    String::from(result.trim())
+#[test]
+fn test_split_comments() {
+    assert_eq!("select   1", remove_comments(String::from("/*+ VIRTDB_CACHE(ttl=60) */ select /* a */ 1")));
+    assert_eq!("select 1;   DROP TABLE t", remove_comments(String::from("select 1; /*!50000 DROP TABLE t */")));
+    assert_eq!("select '/* not a comment */', \"it''s\\\" -- \"", remove_comments(String::from("select '/* not a comment */', \"it''s\\\" -- \" -- tail")));
+    assert_eq!("select `a#b` from t", remove_comments(String::from("select `a#b` from t # tail")));
+    assert_eq!("select 1--1", remove_comments(String::from("select 1--1")));
+    let (sql, comments) = split_comments("select 1 /* a */ # b\n-- c");
+    assert_eq!("select 1    \n ", sql);
+    assert_eq!(vec!["/* a */", "# b", "-- c"], comments);
 }
@@ -1,8 +1,12 @@
 drop table if exists cache_config;
 CREATE TABLE `cache_config` (
   `id` int(11) NOT NULL AUTO_INCREMENT,
-  `sql_template` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT 'SQL模板',
+  `sql_template` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT 'SQL模板,?匹配单个值,?...匹配一个或多个逗号分隔的值',
   `duration` int(11) NOT NULL COMMENT '缓存有效时长',
+  `timeout_ms` int(11) NOT NULL DEFAULT '0' COMMENT '语句超时毫秒数,0表示使用用户或全局配置',
+  `admission_mode` varchar(20) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT 'ALWAYS' COMMENT '缓存准入 ALWAYS:总是缓存 ADAPTIVE:只缓存慢查询或高频查询',
+  `max_result_bytes` int(11) NOT NULL DEFAULT '0' COMMENT '可缓存的最大结果字节数,超过后直接转发,0表示使用全局配置',
+  `warm_params` varchar(4000) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '预热参数,JSON数组,每项是一组占位符的值,?...对应数组',
   `cache_name` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '缓存名',
   `remark` varchar(200) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '备注',
   `enabled` int(11) NOT NULL DEFAULT '1' COMMENT '是否启用',
@@ -17,10 +21,40 @@ CREATE TABLE `cache_config` (
   KEY `idx_updated_by` (`updated_by`) USING BTREE
 ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='缓存配置';
 
+drop table if exists cache_purge_log;
+CREATE TABLE `cache_purge_log` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `purge_type` varchar(20) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '清除方式 CACHE_CONFIG/TABLE/TAG/SQL',
+  `purge_value` text COLLATE utf8mb4_unicode_ci NOT NULL COMMENT 'cache_config的id,表名,标签或SQL',
+  `vt_nodes` varchar(2000) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '下发的节点',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT '创建时间',
+  `created_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '操作人',
+  `created_by_name` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '操作人用户名',
+  PRIMARY KEY (`id`) USING BTREE,
+  KEY `idx_created_at` (`created_at`) USING BTREE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='缓存清除记录';
+
+drop table if exists cache_warm_query;
+CREATE TABLE `cache_warm_query` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `cache_config_id` int(11) NOT NULL COMMENT '缓存配置id',
+  `user_name` varchar(64) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '执行语句的MySQL用户',
+  `schema_name` varchar(64) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '数据库',
+  `sql_hash` char(16) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '用户、数据库和SQL的hash',
+  `sql_str` text COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '代理记录的具体SQL',
+  `hit_count` bigint(20) NOT NULL DEFAULT '0' COMMENT '累计执行次数',
+  `updated_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP COMMENT '最后执行时间',
+  PRIMARY KEY (`id`) USING BTREE,
+  UNIQUE KEY `uk_cache_config_sql` (`cache_config_id`,`sql_hash`) USING BTREE,
+  KEY `idx_hit_count` (`cache_config_id`,`user_name`,`hit_count`) USING BTREE,
+  KEY `idx_updated_at` (`updated_at`) USING BTREE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='缓存预热用的历史语句';
+
 drop table if exists metric_history;
 CREATE TABLE `metric_history` (
   `id` int(11) NOT NULL AUTO_INCREMENT,
-  `sql_str` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT 'SQL',
+  `sql_digest` char(16) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT 'SQL指纹',
+  `sql_str` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT 'SQL,字面量已替换为占位符',
   `db_server_ip` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '数据库ip',
   `db_server_port` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '数据库端口',
   `database_name` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '数据库',
@@ -29,8 +63,63 @@ CREATE TABLE `metric_history` (
   `min_duration` int(11) NOT NULL COMMENT '最小耗时',
   `exec_count` int(11) NOT NULL COMMENT '执行次数',
   `cache_hit_count` int(11) NOT NULL COMMENT '缓存命中次数',
+  `throttle_count` int(11) NOT NULL DEFAULT '0' COMMENT '限流拒绝次数',
   `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT '创建时间',
+  `config_version` varchar(32) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '统计期间生效的规则版本',
   PRIMARY KEY (`id`) USING BTREE,
   KEY `idx_created_at` (`created_at`) USING BTREE,
-  KEY `idx_sql_str` (`sql_str`,`created_at`) USING BTREE
-) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='性能指标历史';
\ No newline at end of file
+  KEY `idx_sql_str` (`sql_str`,`created_at`) USING BTREE,
+  KEY `idx_sql_digest` (`sql_digest`,`created_at`) USING BTREE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='性能指标历史';
+
+drop table if exists rate_limit_config;
+CREATE TABLE `rate_limit_config` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `limit_name` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '限流规则名',
+  `limit_type` varchar(20) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '限流维度:USER/IP/PATTERN',
+  `limit_key` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '用户名/客户端IP/SQL模板,*表示每个用户(IP)分别限流',
+  `max_qps` int(11) NOT NULL DEFAULT '0' COMMENT '每秒最多执行次数,0不限制',
+  `max_concurrency` int(11) NOT NULL DEFAULT '0' COMMENT '最大并发执行数,0不限制',
+  `queue_timeout_ms` int(11) NOT NULL DEFAULT '0' COMMENT '超限时排队等待的最长毫秒数,0直接拒绝',
+  `remark` varchar(200) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '备注',
+  `enabled` int(11) NOT NULL DEFAULT '1' COMMENT '是否启用',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT '创建时间',
+  `updated_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP COMMENT '更新时间',
+  `created_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '创建者',
+  `updated_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '最后更新者',
+  PRIMARY KEY (`id`) USING BTREE,
+  KEY `idx_limit_type` (`limit_type`) USING BTREE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='限流配置';
+
+drop table if exists firewall_rule;
+CREATE TABLE `firewall_rule` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `rule_name` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '规则名',
+  `rule_type` varchar(20) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '规则类型:DENY_NO_WHERE/DENY_DDL/DENY_PATTERN/ALLOW_PATTERN',
+  `sql_template` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT 'SQL模板,仅PATTERN类型使用',
+  `users` varchar(500) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '生效的用户,逗号分隔,为空表示所有用户',
+  `dry_run` int(11) NOT NULL DEFAULT '0' COMMENT '是否只记录日志不拦截',
+  `remark` varchar(200) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '备注',
+  `enabled` int(11) NOT NULL DEFAULT '1' COMMENT '是否启用',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT '创建时间',
+  `updated_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP COMMENT '更新时间',
+  `created_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '创建者',
+  `updated_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '最后更新者',
+  PRIMARY KEY (`id`) USING BTREE,
+  KEY `idx_rule_type` (`rule_type`) USING BTREE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='SQL防火墙规则';
+
+drop table if exists query_rewrite;
+CREATE TABLE `query_rewrite` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `rule_name` varchar(50) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '规则名',
+  `sql_template` varchar(2000) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '需要改写的SQL模板',
+  `replacement` varchar(2000) COLLATE utf8mb4_unicode_ci NOT NULL COMMENT '改写后的SQL,?按顺序替换为模板占位符对应的值,$n替换为第n个值',
+  `remark` varchar(200) COLLATE utf8mb4_unicode_ci NOT NULL DEFAULT '' COMMENT '备注',
+  `enabled` int(11) NOT NULL DEFAULT '1' COMMENT '是否启用',
+  `created_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP COMMENT '创建时间',
+  `updated_at` datetime NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP COMMENT '更新时间',
+  `created_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '创建者',
+  `updated_by` bigint(20) NOT NULL DEFAULT '-1' COMMENT '最后更新者',
+  PRIMARY KEY (`id`) USING BTREE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci ROW_FORMAT=DYNAMIC COMMENT='SQL改写规则';